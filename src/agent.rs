@@ -0,0 +1,446 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use libc;
+use serde_json;
+use entrystore::Cipher;
+use pwdcrypto::AesCipher;
+use secret::{self, SecretBytes, SecretString};
+use errors::*;
+
+const SOCKET_FILE: &str = "agent.sock";
+const SOCKET_ENV: &str = "PWWR_AGENT_SOCK";
+
+/// Default idle timeout of the agent in seconds.
+pub const DEFAULT_IDLE_TIMEOUT: u64 = 15 * 60;
+/// Default absolute lifetime of the agent in seconds.
+pub const DEFAULT_MAX_LIFETIME: u64 = 8 * 60 * 60;
+/// How long a client may take to send its request.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Encrypt { data: Vec<u8> },
+    Decrypt { data: Vec<u8> },
+    Lock,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Response {
+    Data { data: Vec<u8> },
+    Locked,
+    Error { message: String },
+}
+
+/// the socket path of the agent serving the vault in `pwwr_dir`.
+pub fn socket_path(pwwr_dir: &Path) -> PathBuf {
+    match env::var_os(SOCKET_ENV) {
+        Some(path) => PathBuf::from(path),
+        None => pwwr_dir.join(SOCKET_FILE),
+    }
+}
+
+/// A daemon holding the derived key and encrypting/decrypting on behalf of the CLI.
+pub struct Agent {
+    cipher: AesCipher,
+    idle_timeout: Duration,
+    max_lifetime: Duration,
+    read_timeout: Duration,
+}
+
+impl Agent {
+    pub fn new(mut cipher: AesCipher, idle_timeout: u64, max_lifetime: u64) -> Agent {
+        cipher.lock_memory();
+        Agent {
+            cipher,
+            idle_timeout: Duration::from_secs(idle_timeout),
+            max_lifetime: Duration::from_secs(max_lifetime),
+            read_timeout: READ_TIMEOUT,
+        }
+    }
+
+    /// bind the socket, refusing to replace a running agent.
+    pub fn bind(path: &Path) -> Result<UnixListener> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                bail!("an agent is already running on [{}]", path.display());
+            }
+            fs::remove_file(path).chain_err(|| "can't remove the stale agent socket")?;
+        }
+
+        let old_mask = unsafe { libc::umask(0o177) };
+        let listener = UnixListener::bind(path);
+        unsafe { libc::umask(old_mask) };
+        listener.chain_err(|| format!("can't bind the agent socket [{}]", path.display()))
+    }
+
+    /// serve requests until locked or timed out. The key is wiped when this returns.
+    pub fn serve(self, listener: UnixListener, path: &Path) -> Result<()> {
+        listener
+            .set_nonblocking(true)
+            .chain_err(|| "can't configure the agent socket")?;
+        let started = Instant::now();
+        let mut last_used = Instant::now();
+
+        loop {
+            if last_used.elapsed() >= self.idle_timeout || started.elapsed() >= self.max_lifetime {
+                break;
+            }
+
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(200));
+                    continue;
+                }
+                Err(_) => continue,
+            };
+
+            match self.handle(stream) {
+                Ok(true) => last_used = Instant::now(),
+                Ok(false) => break,
+                Err(_) => {}
+            }
+        }
+
+        let _ = fs::remove_file(path);
+        Ok(())
+    }
+
+    /// handle one connection. Returns false when the agent was asked to lock.
+    fn handle(&self, stream: UnixStream) -> Result<bool> {
+        stream
+            .set_nonblocking(false)
+            .chain_err(|| "can't configure the connection")?;
+        stream
+            .set_read_timeout(Some(self.read_timeout))
+            .chain_err(|| "can't configure the connection")?;
+        check_peer(&stream)?;
+
+        let mut reader = BufReader::new(&stream);
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .chain_err(|| "can't read an agent request")?;
+
+        let line = SecretString::new(line);
+        let (mut response, keep_running) = match serde_json::from_str::<Request>(line.expose()) {
+            Ok(Request::Encrypt { mut data }) => {
+                let response = to_response(self.cipher.encrypt(&data));
+                secret::wipe(&mut data);
+                (response, true)
+            }
+            Ok(Request::Decrypt { data }) => (
+                to_response(self.cipher.decrypt(&data).map(|d| d.expose().to_vec())),
+                true,
+            ),
+            Ok(Request::Lock) => (Response::Locked, false),
+            Err(e) => (
+                Response::Error {
+                    message: format!("invalid request: {}", e),
+                },
+                true,
+            ),
+        };
+
+        let written = write_message(&stream, &response);
+        if let Response::Data { ref mut data } = response {
+            secret::wipe(data);
+        }
+        written?;
+        Ok(keep_running)
+    }
+}
+
+fn to_response(result: Result<Vec<u8>>) -> Response {
+    match result {
+        Ok(data) => Response::Data { data },
+        Err(e) => Response::Error {
+            message: e.to_string(),
+        },
+    }
+}
+
+fn write_message<T: ::serde::Serialize>(mut stream: &UnixStream, message: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(message).chain_err(|| "can't serialize an agent message")?;
+    line.push(b'\n');
+    let line = SecretBytes::new(line);
+    stream
+        .write_all(line.expose())
+        .chain_err(|| "can't write to the agent socket")
+}
+
+/// fail unless the process on the other end runs as the user of the agent.
+pub fn check_peer(stream: &UnixStream) -> Result<()> {
+    check_uid(peer_uid(stream)?)
+}
+
+fn check_uid(uid: u32) -> Result<()> {
+    if uid != unsafe { libc::geteuid() } {
+        bail!("rejected a connection from uid {}", uid);
+    }
+    Ok(())
+}
+
+/// the uid of the process on the other end.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> Result<u32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = ::std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        bail!("can't read the peer credentials");
+    }
+    Ok(cred.uid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> Result<u32> {
+    let mut uid: libc::uid_t = 0;
+    let mut gid: libc::gid_t = 0;
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        bail!("can't read the peer credentials");
+    }
+    Ok(uid)
+}
+
+/// detach from the terminal. Returns the child pid in the parent and `None` in the daemon.
+pub fn daemonize() -> Result<Option<i32>> {
+    match unsafe { libc::fork() } {
+        -1 => bail!("can't fork the agent"),
+        0 => {}
+        pid => return Ok(Some(pid)),
+    }
+
+    unsafe {
+        libc::setsid();
+        let devnull = libc::open(b"/dev/null\0".as_ptr() as *const libc::c_char, libc::O_RDWR);
+        if devnull >= 0 {
+            libc::dup2(devnull, 0);
+            libc::dup2(devnull, 1);
+            libc::dup2(devnull, 2);
+            if devnull > 2 {
+                libc::close(devnull);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// A `Cipher` forwarding every operation to a running agent.
+pub struct AgentClient {
+    path: PathBuf,
+}
+
+impl AgentClient {
+    /// connect to the agent, or `None` if no agent is listening.
+    pub fn connect(path: &Path) -> Option<AgentClient> {
+        match UnixStream::connect(path) {
+            Ok(_) => Some(AgentClient {
+                path: path.to_path_buf(),
+            }),
+            Err(_) => None,
+        }
+    }
+
+    /// ask the agent to wipe its key and exit.
+    pub fn lock(&self) -> Result<()> {
+        match self.request(&Request::Lock)? {
+            Response::Locked => Ok(()),
+            Response::Error { message } => bail!(message),
+            Response::Data { .. } => bail!("unexpected agent response"),
+        }
+    }
+
+    fn request(&self, request: &Request) -> Result<Response> {
+        let stream = UnixStream::connect(&self.path).chain_err(|| "can't connect to the agent")?;
+        write_message(&stream, request)?;
+
+        let mut line = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut line)
+            .chain_err(|| "can't read an agent response")?;
+        let line = SecretString::new(line);
+        serde_json::from_str(line.expose()).chain_err(|| "invalid agent response")
+    }
+
+    fn data(&self, request: &Request) -> Result<SecretBytes> {
+        match self.request(request)? {
+            Response::Data { data } => Ok(SecretBytes::new(data)),
+            Response::Error { message } => bail!(message),
+            Response::Locked => bail!("the agent is locked"),
+        }
+    }
+}
+
+impl Cipher for AgentClient {
+    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut request = Request::Encrypt {
+            data: Vec::from(data),
+        };
+        let encrypted = self.data(&request);
+        if let Request::Encrypt { ref mut data } = request {
+            secret::wipe(data);
+        }
+        Ok(encrypted?.expose().to_vec())
+    }
+
+    fn decrypt(&self, data: &[u8]) -> Result<SecretBytes> {
+        self.data(&Request::Decrypt {
+            data: Vec::from(data),
+        })
+    }
+}
+
+/// The cipher of an unlocked session: a local key or the agent.
+pub enum SessionCipher {
+    Local(AesCipher),
+    Agent(AgentClient),
+}
+
+impl Cipher for SessionCipher {
+    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match *self {
+            SessionCipher::Local(ref c) => c.encrypt(data),
+            SessionCipher::Agent(ref c) => c.encrypt(data),
+        }
+    }
+
+    fn decrypt(&self, data: &[u8]) -> Result<SecretBytes> {
+        match *self {
+            SessionCipher::Local(ref c) => c.decrypt(data),
+            SessionCipher::Agent(ref c) => c.decrypt(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use agent::*;
+    use testutil::TempDir;
+
+    fn agent(idle_timeout: u64) -> Agent {
+        Agent::new(
+            AesCipher::from_key(SecretBytes::new(vec![7; 32])),
+            idle_timeout,
+            60,
+        )
+    }
+
+    /// send `line` over a socket pair and return what `handle` answered.
+    fn exchange(agent: &Agent, line: &str) -> (Result<bool>, String) {
+        let (client, server) = UnixStream::pair().unwrap();
+        (&client).write_all(line.as_bytes()).unwrap();
+        let handled = agent.handle(server);
+        let mut response = String::new();
+        BufReader::new(&client).read_line(&mut response).unwrap();
+        (handled, response)
+    }
+
+    #[test]
+    fn handle_test() {
+        let agent = agent(60);
+
+        let request = serde_json::to_string(&Request::Encrypt {
+            data: b"secret".to_vec(),
+        })
+        .unwrap();
+        let (handled, response) = exchange(&agent, &(request + "\n"));
+        assert!(handled.unwrap());
+        let encrypted = match serde_json::from_str(&response).unwrap() {
+            Response::Data { data } => data,
+            _ => panic!("unexpected response {}", response),
+        };
+        assert_ne!(encrypted, b"secret".to_vec());
+
+        let request = serde_json::to_string(&Request::Decrypt { data: encrypted }).unwrap();
+        let (handled, response) = exchange(&agent, &(request + "\n"));
+        assert!(handled.unwrap());
+        match serde_json::from_str(&response).unwrap() {
+            Response::Data { data } => assert_eq!(data, b"secret".to_vec()),
+            _ => panic!("unexpected response {}", response),
+        }
+
+        let (handled, response) = exchange(&agent, "garbage\n");
+        assert!(handled.unwrap());
+        match serde_json::from_str(&response).unwrap() {
+            Response::Error { .. } => {}
+            _ => panic!("unexpected response {}", response),
+        }
+
+        let (handled, response) = exchange(&agent, "{\"op\":\"lock\"}\n");
+        assert!(!handled.unwrap());
+        match serde_json::from_str(&response).unwrap() {
+            Response::Locked => {}
+            _ => panic!("unexpected response {}", response),
+        }
+    }
+
+    #[test]
+    fn read_timeout_test() {
+        let mut agent = agent(60);
+        agent.read_timeout = Duration::from_millis(100);
+        let (_client, server) = UnixStream::pair().unwrap();
+        let started = Instant::now();
+        assert!(agent.handle(server).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn peer_test() {
+        let (client, server) = UnixStream::pair().unwrap();
+        assert!(check_peer(&server).is_ok());
+        assert!(check_peer(&client).is_ok());
+        let uid = unsafe { libc::geteuid() };
+        assert!(check_uid(uid).is_ok());
+        assert!(check_uid(uid.wrapping_add(1)).is_err());
+    }
+
+    #[test]
+    fn serve_test() {
+        let dir = TempDir::new("agent");
+        let path = dir.join("agent.sock");
+        let listener = Agent::bind(&path).unwrap();
+        assert!(Agent::bind(&path).is_err());
+        let server = {
+            let path = path.clone();
+            thread::spawn(move || agent(60).serve(listener, &path))
+        };
+
+        let client = AgentClient::connect(&path).unwrap();
+        let encrypted = client.encrypt(b"secret").unwrap();
+        assert_eq!(client.decrypt(&encrypted).unwrap().expose(), b"secret");
+        client.lock().unwrap();
+        server.join().unwrap().unwrap();
+        assert!(!path.exists());
+        assert!(AgentClient::connect(&path).is_none());
+    }
+
+    #[test]
+    fn expiry_test() {
+        let dir = TempDir::new("agent-expiry");
+        let path = dir.join("agent.sock");
+        let listener = Agent::bind(&path).unwrap();
+        agent(0).serve(listener, &path).unwrap();
+        assert!(!path.exists());
+    }
+}
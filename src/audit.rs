@@ -0,0 +1,344 @@
+//! The audit log of a vault: a record of every saved change to an entry,
+//! with its time, host and OS user, to tell who changed what and when.
+//!
+//! A record names the entry and the fields changed, never their values. The
+//! log is `audit` in the directory of the vault, one record per line,
+//! encrypted with the key of the vault and only ever appended to. Each record
+//! holds the SHA-256 of the line before it, so a record changed, removed or
+//! moved breaks the chain; only the last records can be cut unnoticed.
+//!
+//! The log is kept per machine and `pwwr sync` doesn't share it: it holds
+//! the changes saved on this machine, and those pulled from the remote,
+//! merged or not, are in the log of the machine that made them.
+use std::ffi::CStr;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use base64;
+use libc;
+use serde_json;
+use clock;
+use entrystore::{AuthInfo, Cipher};
+use primitives;
+use rotation::DAY;
+use errors::*;
+
+pub const FILE: &str = "audit";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Add,
+    Change,
+    Remove,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Add => "add",
+            Action::Change => "change",
+            Action::Remove => "remove",
+        }
+    }
+}
+
+/// A change of an entry, before it is recorded.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Change {
+    pub action: Action,
+    pub entry: String,
+    /// the title before a rename.
+    pub from: Option<String>,
+    pub fields: Vec<String>,
+}
+
+/// A record of the log.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Record {
+    /// seconds since the unix epoch.
+    pub time: u64,
+    pub host: String,
+    pub os_user: String,
+    pub action: Action,
+    pub entry: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    pub fields: Vec<String>,
+    /// the SHA-256 of the line before in hex, empty for the first record.
+    pub prev: String,
+}
+
+/// the names of the fields that differ between `old` and `new`, the history
+/// aside.
+pub fn changed_fields(old: &AuthInfo, new: &AuthInfo) -> Vec<String> {
+    let mut fields = Vec::new();
+    if old.user != new.user {
+        fields.push(String::from("user"));
+    }
+    if old.password != new.password {
+        fields.push(String::from("password"));
+    }
+    if old.other != new.other {
+        fields.push(String::from("other"));
+    }
+    let mut names: Vec<&String> = old.fields.keys().chain(new.fields.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        if old.fields.get(name) != new.fields.get(name) {
+            fields.push(name.clone());
+        }
+    }
+    if old.tags != new.tags {
+        fields.push(String::from("tags"));
+    }
+    if old.attachments != new.attachments {
+        fields.push(String::from("attachments"));
+    }
+    fields
+}
+
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let name = unsafe {
+        if libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) == 0 {
+            buf[buf.len() - 1] = 0;
+            CStr::from_ptr(buf.as_ptr() as *const libc::c_char).to_string_lossy().into_owned()
+        } else {
+            String::new()
+        }
+    };
+    if name.is_empty() {
+        String::from("unknown")
+    } else {
+        name
+    }
+}
+
+pub fn os_user() -> String {
+    let uid = unsafe { libc::geteuid() };
+    let name = unsafe {
+        let passwd = libc::getpwuid(uid);
+        if passwd.is_null() || (*passwd).pw_name.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr((*passwd).pw_name).to_string_lossy().into_owned())
+        }
+    };
+    name.unwrap_or_else(|| uid.to_string())
+}
+
+fn line_hash(line: &str) -> String {
+    primitives::sha256(&[line.as_bytes()]).expose().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The audit log of a vault.
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> AuditLog {
+        AuditLog { path }
+    }
+
+    fn lines(&self) -> Result<Vec<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => Ok(content.lines().map(String::from).collect()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e).chain_err(|| format!("can't read [{}]", self.path.display())),
+        }
+    }
+
+    /// record `changes`, made now by this user on this host.
+    pub fn append<C: Cipher>(&self, cipher: &C, changes: &[Change]) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let mut prev = self.lines()?.last().map_or_else(String::new, |line| line_hash(line));
+        let (time, host, os_user) = (clock::now(), hostname(), os_user());
+        let mut content = String::new();
+        for change in changes {
+            let record = Record {
+                time,
+                host: host.clone(),
+                os_user: os_user.clone(),
+                action: change.action,
+                entry: change.entry.clone(),
+                from: change.from.clone(),
+                fields: change.fields.clone(),
+                prev,
+            };
+            let json = serde_json::to_vec(&record).chain_err(|| "can't write an audit record")?;
+            let line = base64::encode(&cipher.encrypt(&json)?);
+            prev = line_hash(&line);
+            content.push_str(&line);
+            content.push('\n');
+        }
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(&self.path)
+            .chain_err(|| format!("can't open [{}]", self.path.display()))?;
+        file.write_all(content.as_bytes())
+            .and_then(|_| file.sync_all())
+            .chain_err(|| format!("can't write [{}]", self.path.display()))
+    }
+
+    /// every record, oldest first, failing when the chain is broken.
+    pub fn read<C: Cipher>(&self, cipher: &C) -> Result<Vec<Record>> {
+        let mut records = Vec::new();
+        let mut prev = String::new();
+        for (i, line) in self.lines()?.iter().enumerate() {
+            let record = base64::decode(line)
+                .ok()
+                .and_then(|data| cipher.decrypt(&data).ok())
+                .and_then(|json| serde_json::from_slice::<Record>(json.expose()).ok());
+            match record {
+                Some(ref record) if record.prev == prev => {}
+                _ => bail!("the audit log was tampered with at record {}", i + 1),
+            }
+            prev = line_hash(line);
+            records.extend(record);
+        }
+        Ok(records)
+    }
+}
+
+/// days since the unix epoch of a civil date, from Howard Hinnant's date
+/// algorithms.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// the time of `--since`: a date `YYYY-MM-DD` in UTC, or an age as `30m`,
+/// `12h` or `7d` before `now`.
+pub fn parse_since(text: &str, now: u64) -> Result<u64> {
+    let invalid = || Error::from(format!("invalid time [{}], use YYYY-MM-DD, or 30m, 12h or 7d", text));
+    let units = [('m', 60), ('h', 3600), ('d', DAY)];
+    for &(suffix, unit) in &units {
+        if text.ends_with(suffix) {
+            let count: u64 = text[..text.len() - 1].parse().map_err(|_| invalid())?;
+            return Ok(now.saturating_sub(count.saturating_mul(unit)));
+        }
+    }
+
+    let parts: Vec<&str> = text.split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 {
+        return Err(invalid());
+    }
+    let mut numbers = [0i64; 3];
+    for (number, part) in numbers.iter_mut().zip(&parts) {
+        *number = part.parse().map_err(|_| invalid())?;
+    }
+    let (year, month, day) = (numbers[0], numbers[1], numbers[2]);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    Ok(days_from_civil(year, month, day) as u64 * DAY)
+}
+
+#[cfg(test)]
+mod test {
+    use audit::*;
+    use entrystore::Entry;
+    use secret::SecretString;
+    use testutil::{empty_store, PlainCipher, TempDir};
+
+    fn change(action: Action, entry: &str, fields: &[&str]) -> Change {
+        Change {
+            action,
+            entry: String::from(entry),
+            from: None,
+            fields: fields.iter().map(|f| String::from(*f)).collect(),
+        }
+    }
+
+    #[test]
+    fn chain_test() {
+        let dir = TempDir::new("audit");
+        let path = dir.join(FILE);
+        let log = AuditLog::new(path.clone());
+        assert!(log.read(&PlainCipher).unwrap().is_empty());
+        log.append(&PlainCipher, &[change(Action::Add, "db", &["user", "password"])]).unwrap();
+        log.append(
+            &PlainCipher,
+            &[change(Action::Change, "db", &["password"]), change(Action::Remove, "mail", &[])],
+        )
+        .unwrap();
+
+        let records = log.read(&PlainCipher).unwrap();
+        assert_eq!(3, records.len());
+        assert_eq!("", records[0].prev);
+        assert_eq!(vec!["password"], records[1].fields);
+        assert_eq!(Action::Remove, records[2].action);
+        assert_eq!(os_user(), records[2].os_user);
+
+        let lines: Vec<String> = fs::read_to_string(&path).unwrap().lines().map(String::from).collect();
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(log.read(&PlainCipher).is_err());
+        let forged = base64::encode(base64::decode(&lines[1]).unwrap().iter().map(|b| b ^ 1).collect::<Vec<_>>());
+        fs::write(&path, format!("{}\n{}\n{}\n", lines[0], forged, lines[2])).unwrap();
+        assert!(log.read(&PlainCipher).is_err());
+    }
+
+    #[test]
+    fn journal_test() {
+        let dir = TempDir::new("journal");
+        let path = dir.join(FILE);
+        let mut store = empty_store().with_audit(AuditLog::new(path.clone()));
+        let mut auth = AuthInfo::new("alice", SecretString::from("pw"), SecretString::default());
+        store.add(Entry::new("db", auth.clone())).unwrap();
+        store.add(Entry::new("mail", AuthInfo::blank())).unwrap();
+        store.save().unwrap();
+
+        auth.password = SecretString::from("new");
+        auth.set_field("host", SecretString::from("db.local")).unwrap();
+        store.change("db", Entry::new("prod/db", auth.clone())).unwrap();
+        store.remove("mail").unwrap();
+        store.save().unwrap();
+        store.clear();
+        store.add(Entry::new("prod/db", auth)).unwrap();
+        store.save().unwrap();
+
+        let records = store.audit_records().unwrap();
+        let summary: Vec<(&str, &str, Option<&str>, Vec<&str>)> = records
+            .iter()
+            .map(|r| {
+                let fields = r.fields.iter().map(|f| f.as_str()).collect();
+                (r.action.name(), r.entry.as_str(), r.from.as_deref(), fields)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("add", "db", None, vec!["user", "password"]),
+                ("add", "mail", None, vec![]),
+                ("remove", "mail", None, vec![]),
+                ("change", "prod/db", Some("db"), vec!["password", "host"]),
+            ],
+            summary
+        );
+    }
+
+    #[test]
+    fn parse_since_test() {
+        assert_eq!(0, parse_since("1970-01-01", 0).unwrap());
+        assert_eq!(1_760_745_600, parse_since("2025-10-18", 0).unwrap());
+        assert_eq!("2024-02-29 00:00:00", clock::format_time(parse_since("2024-02-29", 0).unwrap()));
+        assert_eq!(1000, parse_since("7d", 1000 + 7 * DAY).unwrap());
+        assert_eq!(400, parse_since("10m", 1000).unwrap());
+        assert_eq!(0, parse_since("2h", 1000).unwrap());
+        assert!(parse_since("yesterday", 0).is_err());
+        assert!(parse_since("2025-13-01", 0).is_err());
+        assert!(parse_since("xd", 0).is_err());
+    }
+}
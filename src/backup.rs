@@ -0,0 +1,248 @@
+//! Portable backups: the entries of the vault in a file protected by its own
+//! passphrase, which can be restored on any machine.
+//!
+//! A backup file is a magic, the length of a JSON header holding the Argon2id
+//! parameters and the nonce, the header, then the archive encrypted with
+//! ChaCha20-Poly1305, which authenticates the header too. The archive holds
+//! `metadata.json` and `entries.json` with a manifest of their SHA-256.
+use std::collections::BTreeMap;
+use base64;
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use rand::{OsRng, Rng};
+use serde_json;
+use clock;
+use entrystore::Entry;
+use primitives;
+use secret::{SecretBytes, SecretString};
+use errors::*;
+
+const MAGIC: &[u8] = b"PWWRBAK\x01";
+const FORMAT: u32 = 1;
+const TAG_LEN: usize = 16;
+const METADATA_FILE: &str = "metadata.json";
+const ENTRIES_FILE: &str = "entries.json";
+
+/// Argon2id memory in KiB, iterations and lanes, the second recommended
+/// option of RFC 9106.
+pub const KDF_PARAMS: (u32, u32, u32) = (64 * 1024, 3, 4);
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    kdf: String,
+    memory: u32,
+    iterations: u32,
+    lanes: u32,
+    salt: String,
+    nonce: String,
+}
+
+/// What a backup tells about itself.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Metadata {
+    pub format: u32,
+    /// seconds since the unix epoch.
+    pub created: u64,
+    /// the user name the vault key is derived with.
+    pub user: String,
+    pub version: String,
+    pub entries: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Archive {
+    /// the SHA-256 of each file in hex.
+    manifest: BTreeMap<String, String>,
+    files: BTreeMap<String, SecretBytes>,
+}
+
+pub struct Backup {
+    pub metadata: Metadata,
+    pub entries: Vec<Entry>,
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn derive_key(header: &Header, passphrase: &SecretString) -> Result<SecretBytes> {
+    if header.kdf != "argon2id" {
+        bail!("unsupported kdf [{}]", header.kdf);
+    }
+    // the header is only authenticated with the derived key.
+    primitives::check_argon2(
+        u64::from(header.memory),
+        u64::from(header.iterations),
+        u64::from(header.lanes),
+    )?;
+    let salt = base64::decode(&header.salt).chain_err(|| "invalid backup header")?;
+    primitives::argon2id(
+        passphrase.expose().as_bytes(),
+        &salt,
+        (header.memory, header.iterations, header.lanes),
+    )
+}
+
+/// the magic, the header length and the header, authenticated with the archive.
+fn prefix(header: &[u8]) -> Vec<u8> {
+    let mut prefix = MAGIC.to_vec();
+    let len = header.len() as u32;
+    prefix.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+    prefix.extend_from_slice(header);
+    prefix
+}
+
+/// write a backup of `entries` of the vault of `user`.
+pub fn create(entries: &[Entry], user: &str, passphrase: &SecretString) -> Result<Vec<u8>> {
+    create_with(entries, user, passphrase, KDF_PARAMS)
+}
+
+fn create_with(
+    entries: &[Entry],
+    user: &str,
+    passphrase: &SecretString,
+    (memory, iterations, lanes): (u32, u32, u32),
+) -> Result<Vec<u8>> {
+    let mut rng = OsRng::new().chain_err(|| "can't open the OS random generator")?;
+    let mut salt = [0; 32];
+    let mut nonce = [0; 8];
+    rng.fill_bytes(&mut salt);
+    rng.fill_bytes(&mut nonce);
+    let header = Header {
+        kdf: String::from("argon2id"),
+        memory,
+        iterations,
+        lanes,
+        salt: base64::encode(salt),
+        nonce: base64::encode(nonce),
+    };
+
+    let metadata = Metadata {
+        format: FORMAT,
+        created: clock::now(),
+        user: String::from(user),
+        version: String::from(env!("CARGO_PKG_VERSION")),
+        entries: entries.len(),
+    };
+    let mut files = BTreeMap::new();
+    files.insert(
+        String::from(METADATA_FILE),
+        SecretBytes::new(serde_json::to_vec(&metadata).chain_err(|| "can't write the metadata")?),
+    );
+    files.insert(
+        String::from(ENTRIES_FILE),
+        SecretBytes::new(serde_json::to_vec(entries).chain_err(|| "can't write the entries")?),
+    );
+    let manifest = files
+        .iter()
+        .map(|(name, data)| (name.clone(), hex(primitives::sha256(&[data.expose()]).expose())))
+        .collect();
+    let archive = Archive { manifest, files };
+    let plain = SecretBytes::new(serde_json::to_vec(&archive).chain_err(|| "can't write the archive")?);
+
+    let key = derive_key(&header, passphrase)?;
+    let mut out = prefix(&serde_json::to_vec(&header).chain_err(|| "can't write the header")?);
+    let mut cipher = ChaCha20Poly1305::new(key.expose(), &nonce, &out);
+    let mut encrypted = vec![0; plain.expose().len()];
+    let mut tag = [0; TAG_LEN];
+    cipher.encrypt(plain.expose(), &mut encrypted, &mut tag);
+    out.extend(encrypted);
+    out.extend_from_slice(&tag);
+    Ok(out)
+}
+
+/// decrypt a backup and verify its manifest.
+pub fn open(data: &[u8], passphrase: &SecretString) -> Result<Backup> {
+    if data.len() < MAGIC.len() + 4 || &data[..MAGIC.len()] != MAGIC {
+        bail!("not a pwwr backup");
+    }
+    let len_bytes = &data[MAGIC.len()..MAGIC.len() + 4];
+    let len = len_bytes.iter().rev().fold(0, |n, &b| n << 8 | b as usize);
+    let body = MAGIC.len() + 4 + len;
+    if data.len() < body + TAG_LEN {
+        bail!("truncated backup");
+    }
+    let header: Header =
+        serde_json::from_slice(&data[MAGIC.len() + 4..body]).chain_err(|| "invalid backup header")?;
+    let nonce = base64::decode(&header.nonce).chain_err(|| "invalid backup header")?;
+    if nonce.len() != 8 {
+        bail!("invalid backup header");
+    }
+
+    let key = derive_key(&header, passphrase)?;
+    let (encrypted, tag) = data[body..].split_at(data.len() - body - TAG_LEN);
+    let mut plain = SecretBytes::new(vec![0; encrypted.len()]);
+    if !ChaCha20Poly1305::new(key.expose(), &nonce, &data[..body]).decrypt(encrypted, plain.expose_mut(), tag) {
+        bail!("wrong passphrase or corrupted backup");
+    }
+
+    let archive: Archive = serde_json::from_slice(plain.expose()).chain_err(|| "invalid backup archive")?;
+    let file = |name: &str| -> Result<&SecretBytes> {
+        match (archive.files.get(name), archive.manifest.get(name)) {
+            (Some(data), Some(sum)) if hex(primitives::sha256(&[data.expose()]).expose()) == *sum => Ok(data),
+            (Some(_), Some(_)) => bail!("the checksum of {} doesn't match", name),
+            _ => bail!("{} is missing from the backup", name),
+        }
+    };
+    let metadata: Metadata =
+        serde_json::from_slice(file(METADATA_FILE)?.expose()).chain_err(|| "invalid backup metadata")?;
+    if metadata.format != FORMAT {
+        bail!("unsupported backup format {}", metadata.format);
+    }
+    let entries: Vec<Entry> =
+        serde_json::from_slice(file(ENTRIES_FILE)?.expose()).chain_err(|| "invalid backup entries")?;
+    if entries.len() != metadata.entries {
+        bail!("the backup holds {} entries instead of {}", entries.len(), metadata.entries);
+    }
+    Ok(Backup { metadata, entries })
+}
+
+#[cfg(test)]
+mod test {
+    use backup::*;
+    use entrystore::AuthInfo;
+
+    #[test]
+    fn roundtrip_test() {
+        let mut auth = AuthInfo::new("alice", SecretString::from("s3cret"), SecretString::default());
+        auth.tags = vec![String::from("work")];
+        let entries = vec![Entry::new("Work/github", auth), Entry::new("mail", AuthInfo::blank())];
+        let passphrase = SecretString::from("backup passphrase");
+        let data = create_with(&entries, "alice", &passphrase, (1024, 1, 1)).unwrap();
+
+        let backup = open(&data, &passphrase).unwrap();
+        assert!(entries == backup.entries);
+        assert_eq!("alice", backup.metadata.user);
+        assert_eq!(2, backup.metadata.entries);
+
+        assert!(open(&data, &SecretString::from("wrong")).is_err());
+        let mut corrupted = data.clone();
+        let last = corrupted.len() - 20;
+        corrupted[last] ^= 1;
+        assert!(open(&corrupted, &passphrase).is_err());
+        let mut header = data.clone();
+        header[MAGIC.len() + 5] = b' ';
+        assert!(open(&header, &passphrase).is_err());
+    }
+
+    #[test]
+    fn kdf_limits_test() {
+        let passphrase = SecretString::from("backup passphrase");
+        for &(memory, iterations, lanes) in &[(u32::MAX, 1, 1), (1024, u32::MAX, 1), (1024, 1, u32::MAX)] {
+            let header = Header {
+                kdf: String::from("argon2id"),
+                memory,
+                iterations,
+                lanes,
+                salt: base64::encode([0; 32]),
+                nonce: base64::encode([0; 8]),
+            };
+            let mut data = prefix(&serde_json::to_vec(&header).unwrap());
+            data.extend_from_slice(&[0; TAG_LEN]);
+            match open(&data, &passphrase) {
+                Err(e) => assert!(e.to_string().starts_with("the kdf asks for")),
+                Ok(_) => panic!("the header should be rejected"),
+            }
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Bitwarden JSON exports, unencrypted or protected by a password.
+//!
+//! Logins, secure notes, cards, identities and SSH keys are imported with
+//! their custom fields, and folders become folders of the title. The TOTP
+//! seed of a login is its `otp` field and an SSH key is its `ssh_key` field.
+use std::collections::{BTreeMap, HashMap};
+use base64;
+use crypto::hkdf;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::pbkdf2;
+use crypto::sha2::Sha256;
+use crypto::util::fixed_time_eq;
+use serde_json;
+use import::Item;
+use primitives;
+use secret::{SecretBytes, SecretString};
+use sshagent::KEY_FIELD;
+use errors::*;
+
+const PBKDF2: u32 = 0;
+const ARGON2ID: u32 = 1;
+
+const LOGIN: u32 = 1;
+const CARD: u32 = 3;
+const IDENTITY: u32 = 4;
+const SSH_KEY: u32 = 5;
+
+/// custom fields linked to a built-in one hold no value.
+const LINKED_FIELD: u32 = 3;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Export {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    password_protected: bool,
+    #[serde(default)]
+    salt: String,
+    #[serde(default)]
+    kdf_type: u32,
+    #[serde(default)]
+    kdf_iterations: u32,
+    kdf_memory: Option<u32>,
+    kdf_parallelism: Option<u32>,
+    #[serde(rename = "encKeyValidation_DO_NOT_EDIT")]
+    enc_key_validation: Option<String>,
+    data: Option<String>,
+    #[serde(default)]
+    folders: Vec<Folder>,
+    #[serde(default)]
+    items: Vec<ExportItem>,
+}
+
+#[derive(Deserialize)]
+struct Folder {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportItem {
+    folder_id: Option<String>,
+    #[serde(rename = "type")]
+    kind: u32,
+    name: Option<String>,
+    notes: Option<SecretString>,
+    fields: Option<Vec<Field>>,
+    login: Option<Login>,
+    card: Option<Card>,
+    identity: Option<BTreeMap<String, Option<SecretString>>>,
+    ssh_key: Option<SshKey>,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    name: Option<String>,
+    value: Option<SecretString>,
+    #[serde(rename = "type")]
+    kind: u32,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    uris: Option<Vec<Uri>>,
+    username: Option<String>,
+    password: Option<SecretString>,
+    totp: Option<SecretString>,
+}
+
+#[derive(Deserialize)]
+struct Uri {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Card {
+    cardholder_name: Option<SecretString>,
+    brand: Option<SecretString>,
+    number: Option<SecretString>,
+    exp_month: Option<String>,
+    exp_year: Option<String>,
+    code: Option<SecretString>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SshKey {
+    private_key: Option<SecretString>,
+}
+
+/// The keys of a password protected export.
+struct Keys {
+    enc: SecretBytes,
+    mac: SecretBytes,
+}
+
+impl Keys {
+    fn derive(export: &Export, password: &SecretString) -> Result<Keys> {
+        let mut master = SecretBytes::new(vec![0; 32]);
+        match export.kdf_type {
+            PBKDF2 => {
+                if export.kdf_iterations > primitives::MAX_PBKDF2_ITERATIONS {
+                    bail!(
+                        "the kdf asks for {} iterations, at most {} are accepted",
+                        export.kdf_iterations,
+                        primitives::MAX_PBKDF2_ITERATIONS
+                    );
+                }
+                let mut mac = Hmac::new(Sha256::new(), password.expose().as_bytes());
+                pbkdf2::pbkdf2(&mut mac, export.salt.as_bytes(), export.kdf_iterations, master.expose_mut());
+            }
+            ARGON2ID => {
+                // the memory of an export is in MiB.
+                let memory = export
+                    .kdf_memory
+                    .unwrap_or(64)
+                    .checked_mul(1024)
+                    .ok_or_else(|| Error::from("invalid argon2 memory"))?;
+                let (iterations, lanes) = (export.kdf_iterations, export.kdf_parallelism.unwrap_or(4));
+                primitives::check_argon2(u64::from(memory), u64::from(iterations), u64::from(lanes))?;
+                let salt = primitives::sha256(&[export.salt.as_bytes()]);
+                master = primitives::argon2id(password.expose().as_bytes(), salt.expose(), (memory, iterations, lanes))?;
+            }
+            kdf => bail!("unsupported kdf {}", kdf),
+        }
+        let expand = |info: &[u8]| {
+            let mut key = SecretBytes::new(vec![0; 32]);
+            hkdf::hkdf_expand(Sha256::new(), master.expose(), info, key.expose_mut());
+            key
+        };
+        Ok(Keys {
+            enc: expand(b"enc"),
+            mac: expand(b"mac"),
+        })
+    }
+
+    /// decrypt an `EncString` of type 2: `2.IV|DATA|MAC` in base64.
+    fn decrypt(&self, enc_string: &str) -> Result<SecretBytes> {
+        let parts: Vec<&str> = match enc_string.find('.') {
+            Some(i) if &enc_string[..i] == "2" => enc_string[i + 1..].split('|').collect(),
+            _ => bail!("unsupported encryption"),
+        };
+        if parts.len() != 3 {
+            bail!("invalid encrypted data");
+        }
+        let decode = |part: &str| base64::decode(part).chain_err(|| "invalid encrypted data");
+        let (iv, data, mac) = (decode(parts[0])?, decode(parts[1])?, decode(parts[2])?);
+
+        let mut hmac = Hmac::new(Sha256::new(), self.mac.expose());
+        hmac.input(&iv);
+        hmac.input(&data);
+        if !fixed_time_eq(hmac.result().code(), &mac) {
+            bail!("wrong password");
+        }
+        primitives::decrypt_aes(self.enc.expose(), &iv, &data)
+    }
+}
+
+fn text(value: &Option<SecretString>) -> SecretString {
+    value.clone().unwrap_or_default()
+}
+
+fn to_item(exported: ExportItem, folders: &HashMap<String, String>) -> Item {
+    let mut item = Item {
+        folder: exported
+            .folder_id
+            .and_then(|id| folders.get(&id).cloned())
+            .unwrap_or_default(),
+        name: exported.name.unwrap_or_default(),
+        notes: text(&exported.notes),
+        ..Item::default()
+    };
+
+    match exported.kind {
+        LOGIN => {
+            if let Some(login) = exported.login {
+                item.user = login.username.unwrap_or_default();
+                item.password = text(&login.password);
+                for uri in login.uris.unwrap_or_default() {
+                    item.add_field("url", SecretString::new(uri.uri.unwrap_or_default()));
+                }
+                item.add_field("otp", text(&login.totp));
+            }
+        }
+        CARD => {
+            if let Some(card) = exported.card {
+                item.add_field("cardholder", text(&card.cardholder_name));
+                item.add_field("brand", text(&card.brand));
+                item.add_field("number", text(&card.number));
+                if let (Some(month), Some(year)) = (card.exp_month, card.exp_year) {
+                    item.add_field("expiry", SecretString::new(format!("{:0>2}/{}", month, year)));
+                }
+                item.add_field("code", text(&card.code));
+            }
+        }
+        IDENTITY => {
+            for (name, value) in exported.identity.unwrap_or_default() {
+                match name.as_str() {
+                    "username" => item.user = String::from(text(&value).expose()),
+                    _ => item.add_field(&name, text(&value)),
+                }
+            }
+        }
+        SSH_KEY => {
+            if let Some(key) = exported.ssh_key {
+                item.add_field(KEY_FIELD, text(&key.private_key));
+            }
+        }
+        _ => {}
+    }
+
+    for field in exported.fields.unwrap_or_default() {
+        if field.kind != LINKED_FIELD {
+            item.add_field(&field.name.unwrap_or_default(), text(&field.value));
+        }
+    }
+    item
+}
+
+/// read the items of an export. `password` is asked only for a protected one.
+pub fn read<F>(data: &[u8], password: F) -> Result<Vec<Item>>
+where
+    F: FnOnce() -> Result<SecretString>,
+{
+    let mut export: Export = serde_json::from_slice(data).chain_err(|| "not a Bitwarden JSON export")?;
+    if export.encrypted {
+        if !export.password_protected {
+            bail!("exports encrypted with the account key can't be read. Export with a password instead.");
+        }
+        let keys = Keys::derive(&export, &password()?)?;
+        if let Some(ref validation) = export.enc_key_validation {
+            keys.decrypt(validation)?;
+        }
+        let plain = keys.decrypt(export.data.as_ref().map_or("", |d| d.as_str()))?;
+        export = serde_json::from_slice(plain.expose()).chain_err(|| "invalid encrypted data")?;
+    }
+
+    let folders: HashMap<String, String> = export.folders.into_iter().map(|f| (f.id, f.name)).collect();
+    Ok(export.items.into_iter().map(|i| to_item(i, &folders)).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use bitwarden::*;
+    use entrystore::Entry;
+
+    fn entries(items: Vec<Item>) -> Vec<Entry> {
+        items.into_iter().map(Item::into_entry).collect()
+    }
+
+    fn check(entries: Vec<Entry>) {
+        let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(
+            vec!["github", "Work/Servers/db", "wifi", "visa", "me", "Work/Servers/deploy key"],
+            titles
+        );
+
+        let github = &entries[0].auth_info;
+        assert_eq!("alice", github.user);
+        assert_eq!("s3cret", github.password.expose());
+        assert_eq!("memo", github.other.expose());
+        assert_eq!("https://gist.github.com", github.fields["url (2)"].expose());
+        assert_eq!("JBSWY3DP", github.fields["otp"].expose());
+        assert_eq!("1234", github.fields["pin"].expose());
+        assert_eq!("true", github.fields["remember"].expose());
+        assert!(!github.fields.contains_key("Username"));
+
+        assert_eq!("the key is 42", entries[2].auth_info.other.expose());
+        assert_eq!("4111111111111111", entries[3].auth_info.fields["number"].expose());
+        assert_eq!("04/2030", entries[3].auth_info.fields["expiry"].expose());
+        assert_eq!("alice", entries[4].auth_info.user);
+        assert_eq!("Liddell", entries[4].auth_info.fields["lastName"].expose());
+        assert!(!entries[4].auth_info.fields.contains_key("middleName"));
+        assert!(entries[5].auth_info.fields[KEY_FIELD].expose().contains("OPENSSH PRIVATE KEY"));
+    }
+
+    #[test]
+    fn plain_test() {
+        let data = include_bytes!("../testdata/bitwarden.json");
+        let items = read(data, || bail!("no password is needed")).unwrap();
+        check(entries(items));
+    }
+
+    #[test]
+    fn kdf_limits_test() {
+        let export = |kdf_type, kdf_iterations, kdf_memory, kdf_parallelism| Export {
+            encrypted: true,
+            password_protected: true,
+            salt: String::from("salt"),
+            kdf_type,
+            kdf_iterations,
+            kdf_memory,
+            kdf_parallelism,
+            enc_key_validation: None,
+            data: None,
+            folders: Vec::new(),
+            items: Vec::new(),
+        };
+        let password = SecretString::from("test");
+        let derive = |export| Keys::derive(&export, &password).map(|_| ());
+
+        assert!(derive(export(PBKDF2, primitives::MAX_PBKDF2_ITERATIONS + 1, None, None)).is_err());
+        assert!(derive(export(ARGON2ID, 1, Some(u32::MAX), None)).is_err());
+        assert!(derive(export(ARGON2ID, 1, Some(1024 * 1024), None)).is_err());
+        assert!(derive(export(ARGON2ID, 1000, Some(16), None)).is_err());
+        assert!(derive(export(ARGON2ID, 1, Some(16), Some(u32::MAX))).is_err());
+        assert!(derive(export(ARGON2ID, 1, Some(16), Some(1))).is_ok());
+    }
+
+    #[test]
+    fn protected_test() {
+        let pbkdf2 = &include_bytes!("../testdata/bitwarden_pbkdf2.json")[..];
+        let argon2 = &include_bytes!("../testdata/bitwarden_argon2.json")[..];
+        for data in &[pbkdf2, argon2] {
+            check(entries(read(data, || Ok(SecretString::from("test"))).unwrap()));
+            assert!(read(data, || Ok(SecretString::from("wrong"))).is_err());
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Wall clock time, as stored in the vault and shown to the user.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// seconds since the unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// `secs` since the unix epoch as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_time(secs: u64) -> String {
+    // days to civil date, from Howard Hinnant's date algorithms.
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use clock::*;
+
+    #[test]
+    fn format_time_test() {
+        assert_eq!("1970-01-01 00:00:00", format_time(0));
+        assert_eq!("2000-02-29 12:34:56", format_time(951_827_696));
+    }
+}
@@ -1,7 +1,13 @@
+use std::io::{BufRead, Write};
 use entrystore::{AuthInfo, Entry, EntryStore};
 use entrystore::EntrySelector;
 use rawio::RawStore;
 use entrystore::Cipher;
+use audit::Record;
+use secret::SecretString;
+use secretref::SecretRef;
+use gitcred;
+use import::{self, ConflictPolicy, ImportReport};
 use errors::*;
 
 pub struct Command<T: EntrySelector, RW: RawStore, CP: Cipher> {
@@ -17,8 +23,8 @@ where
 {
     pub fn new(input: T, entry_store: EntryStore<RW, CP>) -> Command<T, RW, CP> {
         Command {
-            entry_store: entry_store,
-            input: input,
+            entry_store,
+            input,
         }
     }
 
@@ -34,18 +40,83 @@ where
         self.entry_store.select_one(title, &self.input)
     }
 
+    /// a `TITLE:FIELD` reference, parsed against the entries.
+    pub fn parse_ref(&self, reference: &str) -> Result<SecretRef> {
+        SecretRef::parse(reference, &self.entry_store)
+    }
+
+    /// the value of a `TITLE:FIELD` reference.
+    pub fn resolve(&self, reference: &SecretRef) -> Result<SecretString> {
+        reference.resolve(&self.entry_store, &self.input)
+    }
+
+    /// the entry a `TITLE:FIELD` reference points to.
+    pub fn lookup(&self, reference: &SecretRef) -> Result<Entry> {
+        reference.lookup(&self.entry_store, &self.input)
+    }
+
+    /// the entries whose title starts with `title`, sorted by title.
+    pub fn list_entries(&self, title: &str) -> Vec<Entry> {
+        let mut entries = self.entry_store.search(title);
+        entries.sort_by(|a, b| a.title.cmp(&b.title));
+        entries
+    }
+
+    /// whether the title of each entry to import is already taken.
+    pub fn taken_titles(&self, entries: &[Entry]) -> Vec<bool> {
+        import::taken(&self.entry_store, entries)
+    }
+
+    /// add imported entries and save them at once.
+    pub fn import_entries(&mut self, entries: Vec<Entry>, policy: ConflictPolicy) -> Result<ImportReport> {
+        let report = import::apply(&mut self.entry_store, entries, policy)?;
+        self.entry_store.save()?;
+        Ok(report)
+    }
+
+    /// replace every entry with `entries` and save them at once.
+    pub fn replace_entries(&mut self, entries: Vec<Entry>) -> Result<ImportReport> {
+        self.entry_store.clear();
+        let report = import::apply(&mut self.entry_store, entries, ConflictPolicy::Fail)?;
+        self.entry_store.save()?;
+        Ok(report)
+    }
+
+    /// save from now on without adding the changes to the audit log.
+    pub fn detach_audit(&mut self) {
+        self.entry_store.detach_audit();
+    }
+
+    /// the records of the audit log since `since`, of the entry `title` or
+    /// of every entry.
+    pub fn audit_records(&self, title: Option<&str>, since: u64) -> Result<Vec<Record>> {
+        let records = self.entry_store.audit_records()?;
+        Ok(records
+            .into_iter()
+            .filter(|r| r.time >= since)
+            .filter(|r| title.is_none_or(|t| r.entry == t || r.from.as_ref().is_some_and(|from| from == t)))
+            .collect())
+    }
+
+    /// the entries of a copy of the vault, e.g. one kept before a save.
+    pub fn entries_of(&self, content: &[u8]) -> Result<Vec<Entry>> {
+        self.entry_store.decrypt_entries(content)
+    }
+
+    /// answer one request of the git credential helper protocol.
+    pub fn git_credential<R: BufRead, W: Write>(&mut self, action: &str, reader: R, writer: W) -> Result<()> {
+        gitcred::handle(action, &mut self.entry_store, reader, writer)
+    }
+
     pub fn change_entry(&mut self, title: &str) -> Result<()> {
         match self.entry_store.select_one(title, &self.input)? {
             Some(default) => {
                 let new_entry = self.input.get_entry_info(&default)?;
-                self.entry_store.change(&default.title.as_str(), new_entry)?;
+                self.entry_store.change(default.title.as_str(), new_entry)?;
                 self.entry_store.save()?;
                 Ok(())
             }
-            None => {
-                println!("No entry.");
-                Ok(())
-            }
+            None => bail!(ErrorKind::EntryNotFound(String::from(title))),
         }
     }
 }
@@ -0,0 +1,381 @@
+//! The configuration file, `config.toml` in `$XDG_CONFIG_HOME/pwwr`:
+//!
+//! ```toml
+//! data_dir = "~/.pwwr"
+//! default_vault = "work"
+//! clipboard_timeout = 45
+//! lock_timeout = 10
+//! format = "text"
+//!
+//! [generator]
+//! length = 24
+//! symbols = false
+//!
+//! [kdf]
+//! memory = 65536
+//! iterations = 3
+//! lanes = 4
+//! ```
+//!
+//! `--config` gives another file. `PWWR_HOME` moves both the data directory
+//! and the file, which is then `$PWWR_HOME/config.toml`. Every key is
+//! optional and `config set` rewrites the file without its comments.
+use std::env;
+use std::ffi::CStr;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use libc;
+use toml;
+use backup;
+use generator;
+use output::Format;
+use vault::{self, KdfParams};
+use errors::*;
+
+pub const ENV: &str = "PWWR_CONFIG";
+pub const HOME_ENV: &str = "PWWR_HOME";
+const FILE: &str = "config.toml";
+const DATA_DIR: &str = ".pwwr";
+pub const DEFAULT_CLIPBOARD_TIMEOUT: u64 = 45;
+pub const DEFAULT_LOCK_TIMEOUT: u64 = 10;
+
+/// every key of the file, as `config get` and `config set` name them.
+pub const KEYS: &[&str] = &[
+    "data_dir",
+    "default_vault",
+    "clipboard_timeout",
+    "lock_timeout",
+    "format",
+    "generator.length",
+    "generator.symbols",
+    "kdf.memory",
+    "kdf.iterations",
+    "kdf.lanes",
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GeneratorConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbols: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct KdfConfig {
+    /// in KiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iterations: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lanes: Option<u32>,
+}
+
+impl GeneratorConfig {
+    fn is_empty(&self) -> bool {
+        *self == GeneratorConfig::default()
+    }
+}
+
+impl KdfConfig {
+    fn is_empty(&self) -> bool {
+        *self == KdfConfig::default()
+    }
+}
+
+/// The configuration, the defaults for the keys left out.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_vault: Option<String>,
+    /// seconds before a copied password is cleared, 0 to keep it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clipboard_timeout: Option<u64>,
+    /// seconds to wait for another process changing the vault.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(default, skip_serializing_if = "GeneratorConfig::is_empty")]
+    pub generator: GeneratorConfig,
+    #[serde(default, skip_serializing_if = "KdfConfig::is_empty")]
+    pub kdf: KdfConfig,
+}
+
+/// the home directory of `HOME`, else of the password database.
+pub fn home_dir() -> Result<PathBuf> {
+    match env::var_os("HOME") {
+        Some(ref home) if !home.is_empty() => return Ok(PathBuf::from(home)),
+        _ => {}
+    }
+    let dir = unsafe {
+        let passwd = libc::getpwuid(libc::geteuid());
+        if passwd.is_null() || (*passwd).pw_dir.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr((*passwd).pw_dir).to_string_lossy().into_owned())
+        }
+    };
+    match dir {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => bail!("Can't find home directory."),
+    }
+}
+
+/// `path` with a leading `~/` replaced by the home directory.
+fn expand(path: &str) -> Result<PathBuf> {
+    if path == "~" {
+        return home_dir();
+    }
+    match path.strip_prefix("~/") {
+        Some(rest) => Ok(home_dir()?.join(rest)),
+        None => Ok(PathBuf::from(path)),
+    }
+}
+
+fn non_empty_env(name: &str) -> Option<PathBuf> {
+    env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+/// the file of `--config`, `PWWR_HOME` or `XDG_CONFIG_HOME`, which may not exist.
+pub fn path() -> Result<PathBuf> {
+    if let Some(path) = non_empty_env(ENV) {
+        return Ok(path);
+    }
+    if let Some(home) = non_empty_env(HOME_ENV) {
+        return Ok(home.join(FILE));
+    }
+    let config_home = match non_empty_env("XDG_CONFIG_HOME") {
+        Some(dir) => dir,
+        None => home_dir()?.join(".config"),
+    };
+    Ok(config_home.join("pwwr").join(FILE))
+}
+
+impl Config {
+    /// the configuration of the file of `path()`.
+    pub fn load() -> Result<Config> {
+        Config::load_from(&path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Config> {
+        let config = Config::read_from(path)?;
+        config
+            .validate()
+            .chain_err(|| format!("invalid config [{}]", path.display()))?;
+        Ok(config)
+    }
+
+    /// the file as it is, without checking the values, so that `config get`
+    /// and `config set` still work when one of them is invalid.
+    pub fn read_from(path: &Path) -> Result<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = fs::read_to_string(path).chain_err(|| format!("can't read [{}]", path.display()))?;
+        toml::from_str(&content).chain_err(|| format!("invalid config [{}]", path.display()))
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty() && !d.exists()) {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .chain_err(|| format!("can't create [{}]", dir.display()))?;
+        }
+        let content = toml::to_string(self).chain_err(|| "can't write the config")?;
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .chain_err(|| format!("can't write [{}]", path.display()))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for key in KEYS {
+            self.check(key)?;
+        }
+        Ok(())
+    }
+
+    /// fail if the value of `key` is invalid.
+    fn check(&self, key: &str) -> Result<()> {
+        let timeout = match key {
+            "clipboard_timeout" => self.clipboard_timeout,
+            "lock_timeout" => self.lock_timeout,
+            _ => None,
+        };
+        match key {
+            "data_dir" if self.data_dir.as_ref().is_some_and(|dir| dir.is_empty()) => {
+                bail!("data_dir can't be empty")
+            }
+            "default_vault" => {
+                if let Some(ref name) = self.default_vault {
+                    vault::check_name(name)?;
+                }
+            }
+            "clipboard_timeout" | "lock_timeout" if timeout.is_some_and(|t| t > 3600) => {
+                bail!("{} must be at most 3600 seconds", key)
+            }
+            "format" => {
+                if let Some(ref format) = self.format {
+                    Format::parse(format)?;
+                }
+            }
+            "generator.length" if self.generator.length == Some(0) => bail!("generator.length must be positive"),
+            "kdf.memory" | "kdf.iterations" | "kdf.lanes" => self.kdf().validate()?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// the directory of the vaults: `PWWR_HOME`, else `data_dir`, else `~/.pwwr`.
+    pub fn data_dir(&self) -> Result<PathBuf> {
+        if let Some(home) = non_empty_env(HOME_ENV) {
+            return Ok(home);
+        }
+        match self.data_dir {
+            Some(ref dir) => expand(dir),
+            None => Ok(home_dir()?.join(DATA_DIR)),
+        }
+    }
+
+    pub fn clipboard_timeout(&self) -> u64 {
+        self.clipboard_timeout.unwrap_or(DEFAULT_CLIPBOARD_TIMEOUT)
+    }
+
+    pub fn lock_timeout(&self) -> u64 {
+        self.lock_timeout.unwrap_or(DEFAULT_LOCK_TIMEOUT)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+            .as_ref()
+            .and_then(|name| Format::parse(name).ok())
+            .unwrap_or(Format::Text)
+    }
+
+    pub fn generator_length(&self) -> usize {
+        self.generator.length.unwrap_or(generator::DEFAULT_LENGTH)
+    }
+
+    pub fn generator_symbols(&self) -> bool {
+        self.generator.symbols.unwrap_or(true)
+    }
+
+    /// the Argon2id parameters of new key slots, unless a vault sets its own.
+    pub fn kdf(&self) -> KdfParams {
+        let (memory, iterations, lanes) = backup::KDF_PARAMS;
+        KdfParams {
+            memory: self.kdf.memory.unwrap_or(memory),
+            iterations: self.kdf.iterations.unwrap_or(iterations),
+            lanes: self.kdf.lanes.unwrap_or(lanes),
+        }
+    }
+
+    /// the value of `key`, its default when it isn't set.
+    pub fn get(&self, key: &str) -> Result<String> {
+        Ok(match key {
+            "data_dir" => self.data_dir()?.display().to_string(),
+            "default_vault" => self.default_vault.clone().unwrap_or_else(|| String::from(vault::DEFAULT)),
+            "clipboard_timeout" => self.clipboard_timeout().to_string(),
+            "lock_timeout" => self.lock_timeout().to_string(),
+            "format" => String::from(self.format().name()),
+            "generator.length" => self.generator_length().to_string(),
+            "generator.symbols" => self.generator_symbols().to_string(),
+            "kdf.memory" => self.kdf().memory.to_string(),
+            "kdf.iterations" => self.kdf().iterations.to_string(),
+            "kdf.lanes" => self.kdf().lanes.to_string(),
+            _ => bail!("unknown config key [{}], one of {}", key, KEYS.join(", ")),
+        })
+    }
+
+    /// set `key` to `value`, or back to its default without one.
+    pub fn set(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        fn parse<T: ::std::str::FromStr>(key: &str, value: Option<&str>) -> Result<Option<T>> {
+            match value {
+                Some(v) => Ok(Some(v.parse().map_err(|_| Error::from(format!("invalid {} [{}]", key, v)))?)),
+                None => Ok(None),
+            }
+        }
+        let text = value.map(String::from);
+        match key {
+            "data_dir" => self.data_dir = text,
+            "default_vault" => self.default_vault = text,
+            "clipboard_timeout" => self.clipboard_timeout = parse(key, value)?,
+            "lock_timeout" => self.lock_timeout = parse(key, value)?,
+            "format" => self.format = text,
+            "generator.length" => self.generator.length = parse(key, value)?,
+            "generator.symbols" => self.generator.symbols = parse(key, value)?,
+            "kdf.memory" => self.kdf.memory = parse(key, value)?,
+            "kdf.iterations" => self.kdf.iterations = parse(key, value)?,
+            "kdf.lanes" => self.kdf.lanes = parse(key, value)?,
+            _ => bail!("unknown config key [{}], one of {}", key, KEYS.join(", ")),
+        }
+        self.check(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use config::*;
+    use testutil::TempDir;
+
+    #[test]
+    fn config_test() {
+        let config: Config = toml::from_str(
+            "default_vault = \"work\"\nclipboard_timeout = 10\n\
+             [generator]\nlength = 32\n[kdf]\nmemory = 1024\n",
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!("work", config.get("default_vault").unwrap());
+        assert_eq!("32", config.get("generator.length").unwrap());
+        assert_eq!("true", config.get("generator.symbols").unwrap());
+        assert_eq!((1024, backup::KDF_PARAMS.1, backup::KDF_PARAMS.2), config.kdf().tuple());
+        assert_eq!("text", config.get("format").unwrap());
+        assert_eq!("10", config.get("lock_timeout").unwrap());
+        assert!(config.get("nope").is_err());
+
+        assert!(toml::from_str::<Config>("colour = \"red\"\n").is_err());
+        let mut config = config;
+        assert!(config.set("format", Some("xml")).is_err());
+        assert!(config.set("generator.length", Some("0")).is_err());
+        assert!(config.set("generator.symbols", Some("maybe")).is_err());
+        assert!(config.set("default_vault", Some("../x")).is_err());
+        assert!(config.set("kdf.lanes", Some("1000")).is_err());
+        assert!(config.set("kdf.iterations", Some("1000")).is_err());
+        assert!(config.set("lock_timeout", Some("7200")).is_err());
+        let mut config = Config::default();
+        config.set("format", Some("json")).unwrap();
+        config.set("kdf.iterations", Some("5")).unwrap();
+        config.set("format", None).unwrap();
+
+        let dir = TempDir::new("config");
+        let path = dir.join("pwwr").join(FILE);
+        config.save_to(&path).unwrap();
+        assert_eq!(config, Config::load_from(&path).unwrap());
+        assert_eq!("[kdf]\niterations = 5\n", fs::read_to_string(&path).unwrap());
+
+        // an invalid value only fails loading, and can still be fixed.
+        fs::write(&path, "lock_timeout = 7200\nformat = \"xml\"\n").unwrap();
+        assert!(Config::load_from(&path).is_err());
+        let mut config = Config::read_from(&path).unwrap();
+        config.set("lock_timeout", Some("30")).unwrap();
+        config.set("format", None).unwrap();
+        config.save_to(&path).unwrap();
+        assert_eq!("30", Config::load_from(&path).unwrap().get("lock_timeout").unwrap());
+    }
+}
@@ -0,0 +1,251 @@
+//! CSV files of browsers and other password managers.
+//!
+//! A column map tells which column holds which part of an entry. The parts
+//! are `title`, `folder`, `user`, `password`, `other` and `tags`, any other
+//! part is a field of that name. A folder is prepended to the title, and an
+//! entry without a title is named after the host of its `url`.
+use std::io::{Read, Write};
+use csv::{ReaderBuilder, Writer};
+use entrystore::{AuthInfo, Entry};
+use secret::SecretString;
+use errors::*;
+
+pub const PRESETS: &[&str] = &["chrome", "firefox", "bitwarden", "lastpass", "1password"];
+
+/// the parts of each preset and their columns.
+fn preset_columns(name: &str) -> Option<&'static [(&'static str, &'static str)]> {
+    let columns: &'static [(&'static str, &'static str)] = match name {
+        "chrome" => &[
+            ("title", "name"),
+            ("url", "url"),
+            ("user", "username"),
+            ("password", "password"),
+            ("other", "note"),
+        ],
+        "firefox" => &[("url", "url"), ("user", "username"), ("password", "password")],
+        "bitwarden" => &[
+            ("folder", "folder"),
+            ("title", "name"),
+            ("other", "notes"),
+            ("url", "login_uri"),
+            ("user", "login_username"),
+            ("password", "login_password"),
+            ("otp", "login_totp"),
+        ],
+        "lastpass" => &[
+            ("url", "url"),
+            ("user", "username"),
+            ("password", "password"),
+            ("otp", "totp"),
+            ("other", "extra"),
+            ("title", "name"),
+            ("folder", "grouping"),
+        ],
+        "1password" => &[
+            ("title", "Title"),
+            ("url", "Url"),
+            ("user", "Username"),
+            ("password", "Password"),
+            ("otp", "OTPAuth"),
+            ("tags", "Tags"),
+            ("other", "Notes"),
+        ],
+        _ => return None,
+    };
+    Some(columns)
+}
+
+/// Which column holds which part of an entry.
+#[derive(Debug)]
+pub struct ColumnMap {
+    /// parts and their columns, in the order of exported columns.
+    columns: Vec<(String, String)>,
+}
+
+impl ColumnMap {
+    pub fn preset(name: &str) -> Result<ColumnMap> {
+        match preset_columns(name) {
+            Some(columns) => Ok(ColumnMap {
+                columns: columns
+                    .iter()
+                    .map(|&(part, column)| (String::from(part), String::from(column)))
+                    .collect(),
+            }),
+            None => bail!("unknown preset [{}], choose one of {}", name, PRESETS.join(", ")),
+        }
+    }
+
+    /// parse `title=name,user=username,...`.
+    pub fn parse(spec: &str) -> Result<ColumnMap> {
+        let mut columns: Vec<(String, String)> = Vec::new();
+        for pair in spec.split(',') {
+            let mut halves = pair.splitn(2, '=').map(|s| s.trim());
+            match (halves.next(), halves.next()) {
+                (Some(part), Some(column)) if !part.is_empty() && !column.is_empty() => {
+                    if columns.iter().any(|c| c.0 == part) {
+                        bail!("[{}] is mapped twice", part);
+                    }
+                    columns.push((String::from(part), String::from(column)));
+                }
+                _ => bail!("invalid mapping [{}], expected PART=COLUMN", pair),
+            }
+        }
+        if !columns.iter().any(|c| c.0 == "password") {
+            bail!("the map has no column for the password");
+        }
+        Ok(ColumnMap { columns })
+    }
+
+    fn column(&self, part: &str) -> Option<&str> {
+        self.columns.iter().find(|c| c.0 == part).map(|c| c.1.as_str())
+    }
+}
+
+/// the host of a url, used as the title of entries without one.
+fn host(url: &str) -> &str {
+    let rest = url.splitn(2, "://").last().unwrap_or("");
+    let host = rest.split(&['/', '?', '#'][..]).next().unwrap_or("");
+    let host = host.rsplit('@').next().unwrap_or("");
+    host.split(':').next().unwrap_or("")
+}
+
+fn row_entry(values: &[(&str, SecretString)]) -> Result<Entry> {
+    let mut auth = AuthInfo::blank();
+    let mut title = String::new();
+    let mut folder = String::new();
+    for &(part, ref value) in values {
+        match part {
+            "title" => title = String::from(value.expose().trim()),
+            "folder" => folder = String::from(value.expose().trim().trim_matches('/')),
+            "password" => auth.password = value.clone(),
+            "tags" => {
+                auth.tags = value
+                    .expose()
+                    .split(&[';', ','][..])
+                    .map(|t| t.trim())
+                    .filter(|t| !t.is_empty())
+                    .map(String::from)
+                    .collect()
+            }
+            _ if value.is_empty() => {}
+            part => auth.set_field(part, value.clone())?,
+        }
+    }
+    if title.is_empty() {
+        title = String::from(auth.fields.get("url").map_or("", |url| host(url.expose())));
+    }
+    if title.is_empty() {
+        title = String::from("untitled");
+    }
+    if !folder.is_empty() {
+        title = format!("{}/{}", folder, title);
+    }
+    Ok(Entry::new(&title, auth))
+}
+
+/// read the entries of a CSV file with a header row.
+pub fn read<R: Read>(reader: R, map: &ColumnMap) -> Result<Vec<Entry>> {
+    let mut reader = ReaderBuilder::new().flexible(true).from_reader(reader);
+    let headers = reader.headers().chain_err(|| "can't read the header row")?.clone();
+    let mut indexes = Vec::new();
+    for (part, column) in &map.columns {
+        match headers.iter().position(|h| h.trim().eq_ignore_ascii_case(column)) {
+            Some(index) => indexes.push((part.as_str(), index)),
+            None if part == "password" => bail!("no [{}] column for the password", column),
+            None => {}
+        }
+    }
+
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        let record = record.chain_err(|| "invalid CSV")?;
+        if record.iter().all(|value| value.is_empty()) {
+            continue;
+        }
+        let values: Vec<(&str, SecretString)> = indexes
+            .iter()
+            .map(|&(part, index)| (part, SecretString::from(record.get(index).unwrap_or(""))))
+            .collect();
+        entries.push(row_entry(&values)?);
+    }
+    Ok(entries)
+}
+
+/// write `entries` with a header row of the columns of `map`.
+pub fn write<W: Write>(writer: W, entries: &[Entry], map: &ColumnMap) -> Result<()> {
+    let mut writer = Writer::from_writer(writer);
+    writer
+        .write_record(map.columns.iter().map(|c| c.1.as_str()))
+        .chain_err(|| "can't write the header row")?;
+    let has_folder = map.column("folder").is_some();
+    for entry in entries {
+        let (folder, title) = match entry.title.rfind('/') {
+            Some(i) if has_folder => (&entry.title[..i], &entry.title[i + 1..]),
+            _ => ("", entry.title.as_str()),
+        };
+        let auth = &entry.auth_info;
+        let values: Vec<SecretString> = map
+            .columns
+            .iter()
+            .map(|(part, _)| match part.as_str() {
+                "title" => SecretString::from(title),
+                "folder" => SecretString::from(folder),
+                "tags" => SecretString::new(auth.tags.join(",")),
+                part => auth.get_field(part).unwrap_or_default(),
+            })
+            .collect();
+        writer
+            .write_record(values.iter().map(|v| v.expose()))
+            .chain_err(|| "can't write a row")?;
+    }
+    writer.flush().chain_err(|| "can't write the file")
+}
+
+#[cfg(test)]
+mod test {
+    use csvfile::*;
+
+    #[test]
+    fn presets_test() {
+        let chrome = "name,url,username,password,note\n\
+                      github,https://github.com/login,alice,s3cret,\"memo, \"\"quoted\"\"\"\n\
+                      ,https://user@mail.example.com:8443/x,bob,pw,\n";
+        let entries = read(chrome.as_bytes(), &ColumnMap::preset("chrome").unwrap()).unwrap();
+        assert_eq!("github", entries[0].title);
+        assert_eq!("alice", entries[0].auth_info.user);
+        assert_eq!("memo, \"quoted\"", entries[0].auth_info.other.expose());
+        assert_eq!("https://github.com/login", entries[0].auth_info.fields["url"].expose());
+        assert_eq!("mail.example.com", entries[1].title);
+
+        let bitwarden = "folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp\n\
+                         Work/,,login,db,,,0,,root,dbpw,JBSWY3DP\n";
+        let entries = read(bitwarden.as_bytes(), &ColumnMap::preset("bitwarden").unwrap()).unwrap();
+        assert_eq!("Work/db", entries[0].title);
+        assert_eq!("JBSWY3DP", entries[0].auth_info.fields["otp"].expose());
+        assert!(!entries[0].auth_info.fields.contains_key("url"));
+
+        assert!(read("url,username\nx,y\n".as_bytes(), &ColumnMap::preset("firefox").unwrap()).is_err());
+        assert!(ColumnMap::preset("keepass").is_err());
+    }
+
+    #[test]
+    fn map_roundtrip_test() {
+        assert!(ColumnMap::parse("title=name,user").is_err());
+        assert!(ColumnMap::parse("title=name,user=login").is_err());
+        let map = ColumnMap::parse("folder=Group, title=Name,password=Secret,tags=Tags,pin=PIN").unwrap();
+
+        let mut auth = AuthInfo::new("", SecretString::from("pw,\n\"x\""), SecretString::default());
+        auth.tags = vec![String::from("a"), String::from("b")];
+        auth.set_field("pin", SecretString::from("1234")).unwrap();
+        let entries = vec![Entry::new("Work/Mail/imap", auth)];
+
+        let mut data = Vec::new();
+        write(&mut data, &entries, &map).unwrap();
+        assert!(data.starts_with(b"Group,Name,Secret,Tags,PIN\nWork/Mail,imap,"));
+        let read_back = read(&data[..], &map).unwrap();
+        assert_eq!("Work/Mail/imap", read_back[0].title);
+        assert_eq!("pw,\n\"x\"", read_back[0].auth_info.password.expose());
+        assert_eq!(vec!["a", "b"], read_back[0].auth_info.tags);
+        assert_eq!("1234", read_back[0].auth_info.fields["pin"].expose());
+    }
+}
@@ -1,43 +1,79 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::iter::FromIterator;
-use std::io::{stdin, stdout};
+use std::io::{stderr, stdin};
 use serde_json;
 use std::io::Write;
+use rpassword::read_password;
+use audit::{self, Action, AuditLog, Change, Record};
 use rawio::RawStore;
+use secret::{SecretBytes, SecretString};
 use errors::*;
 
 pub trait Cipher {
     fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>>;
-    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>>;
+    fn decrypt(&self, data: &[u8]) -> Result<SecretBytes>;
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct AuthInfo {
     pub user: String,
-    pub password: String,
-    pub other: String,
+    pub password: SecretString,
+    pub other: SecretString,
+    #[serde(default)]
+    pub fields: BTreeMap<String, SecretString>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// files by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attachments: BTreeMap<String, SecretBytes>,
+    /// earlier versions, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<AuthInfo>,
 }
 
 impl AuthInfo {
-    pub fn new(user: &str, password: &str, other: &str) -> AuthInfo {
+    pub fn new(user: &str, password: SecretString, other: SecretString) -> AuthInfo {
         AuthInfo {
             user: String::from(user),
-            password: String::from(password),
-            other: String::from(other),
+            password,
+            other,
+            fields: BTreeMap::new(),
+            tags: Vec::new(),
+            attachments: BTreeMap::new(),
+            history: Vec::new(),
         }
     }
 
     pub fn blank() -> AuthInfo {
-        AuthInfo {
-            user: String::new(),
-            password: String::new(),
-            other: String::new(),
+        AuthInfo::new("", SecretString::default(), SecretString::default())
+    }
+
+    /// get a named field. `user`, `password` and `other` are the built-in ones.
+    pub fn get_field(&self, name: &str) -> Option<SecretString> {
+        match name {
+            "user" => Some(SecretString::from(self.user.as_str())),
+            "password" => Some(self.password.clone()),
+            "other" => Some(self.other.clone()),
+            _ => self.fields.get(name).cloned(),
+        }
+    }
+
+    /// set a named field. `user` and `other` are the built-in ones.
+    pub fn set_field(&mut self, name: &str, value: SecretString) -> Result<()> {
+        match name {
+            "title" | "password" => bail!("[{}] can't be set as a field", name),
+            "user" => self.user = String::from(value.expose()),
+            "other" => self.other = value,
+            _ => {
+                self.fields.insert(String::from(name), value);
+            }
         }
+        Ok(())
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Entry {
     pub title: String,
     pub auth_info: AuthInfo,
@@ -47,7 +83,7 @@ impl Entry {
     pub fn new(title: &str, auth_info: AuthInfo) -> Entry {
         Entry {
             title: String::from(title),
-            auth_info: auth_info,
+            auth_info,
         }
     }
 }
@@ -59,9 +95,19 @@ impl fmt::Display for Entry {
             "title: {}\nuser: {}\npassword: {}\nother: {}\n",
             self.title.as_str(),
             self.auth_info.user.as_str(),
-            self.auth_info.password.as_str(),
-            self.auth_info.other.as_str()
-        )
+            self.auth_info.password.expose(),
+            self.auth_info.other.expose()
+        )?;
+        for (name, value) in &self.auth_info.fields {
+            writeln!(f, "{}: {}", name, value.expose())?;
+        }
+        if !self.auth_info.tags.is_empty() {
+            writeln!(f, "tags: {}", self.auth_info.tags.join(", "))?;
+        }
+        for (name, data) in &self.auth_info.attachments {
+            writeln!(f, "attachment: {} ({} bytes)", name, data.expose().len())?;
+        }
+        Ok(())
     }
 }
 
@@ -73,6 +119,11 @@ where
     data: HashMap<String, AuthInfo>,
     cipher: CP,
     rw: RW,
+    audit: Option<AuditLog>,
+    /// the entries changed since the last save, as they were then.
+    touched: BTreeMap<String, Option<AuthInfo>>,
+    /// new title to old title of the entries renamed since the last save.
+    renamed: BTreeMap<String, String>,
 }
 
 impl<RW, CP> EntryStore<RW, CP>
@@ -83,40 +134,120 @@ where
     pub fn load(mut rw: RW, cipher: CP) -> Result<EntryStore<RW, CP>> {
         let mut content = Vec::new();
         rw.read_to_end(&mut content)?;
-        if content.len() == 0 {
-            Ok(EntryStore {
-                data: HashMap::new(),
-                cipher: cipher,
-                rw: rw,
-            })
+        let data = EntryStore::<RW, CP>::decrypt_data(&cipher, &content)?;
+        Ok(EntryStore {
+            data,
+            cipher,
+            rw,
+            audit: None,
+            touched: BTreeMap::new(),
+            renamed: BTreeMap::new(),
+        })
+    }
+
+    /// record the changes of each save in `audit`.
+    pub fn with_audit(mut self, audit: AuditLog) -> EntryStore<RW, CP> {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// stop adding changes to the audit log, for changes made elsewhere.
+    pub fn detach_audit(&mut self) {
+        self.audit = None;
+        self.touched.clear();
+        self.renamed.clear();
+    }
+
+    /// keep the entry `title` as it was before the first change since the
+    /// last save.
+    fn touch(&mut self, title: &str) {
+        if self.audit.is_some() && !self.touched.contains_key(title) {
+            let original = self.data.get(title).cloned();
+            self.touched.insert(String::from(title), original);
+        }
+    }
+
+    /// the changes since the last save, renames as a change of the new title.
+    fn changes(&self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        let renamed_from: Vec<&String> = self
+            .renamed
+            .iter()
+            .filter(|&(new, old)| self.data.contains_key(new.as_str()) && !self.data.contains_key(old.as_str()))
+            .map(|(_, old)| old)
+            .collect();
+        for (title, original) in &self.touched {
+            let from = self.renamed.get(title).filter(|old| renamed_from.contains(old));
+            let original = match from {
+                Some(old) => self.touched.get(old.as_str()).and_then(|o| o.as_ref()),
+                None => original.as_ref(),
+            };
+            let change = |action, fields| Change {
+                action,
+                entry: title.clone(),
+                from: from.cloned(),
+                fields,
+            };
+            match (original, self.data.get(title)) {
+                (None, Some(current)) => {
+                    changes.push(change(Action::Add, audit::changed_fields(&AuthInfo::blank(), current)))
+                }
+                (Some(_), None) if renamed_from.contains(&title) => {}
+                (Some(_), None) => changes.push(change(Action::Remove, Vec::new())),
+                (Some(original), Some(current)) => {
+                    let fields = audit::changed_fields(original, current);
+                    if !fields.is_empty() || from.is_some() {
+                        changes.push(change(Action::Change, fields));
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        changes
+    }
+
+    /// every record of the audit log, oldest first.
+    pub fn audit_records(&self) -> Result<Vec<Record>> {
+        match self.audit {
+            Some(ref audit) => audit.read(&self.cipher),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn decrypt_data(cipher: &CP, content: &[u8]) -> Result<HashMap<String, AuthInfo>> {
+        if content.is_empty() {
+            Ok(HashMap::new())
         } else {
-            let plain_data = cipher.decrypt(&content)?;
-            let plain_str =
-                String::from_utf8(plain_data).chain_err(|| "plain data can't convert to utf8")?;
-            let data: HashMap<String, AuthInfo> = serde_json::from_str(plain_str.as_str())
-                .chain_err(|| "plain str can't convert to json")?;
-            Ok(EntryStore {
-                data: data,
-                cipher: cipher,
-                rw: rw,
-            })
+            let plain_data = cipher.decrypt(content)?;
+            serde_json::from_slice(plain_data.expose()).chain_err(|| "plain data can't convert to json")
         }
     }
 
+    /// the entries of `content` saved by a store with the same key.
+    pub fn decrypt_entries(&self, content: &[u8]) -> Result<Vec<Entry>> {
+        let data = EntryStore::<RW, CP>::decrypt_data(&self.cipher, content)?;
+        Ok(data.into_iter().map(|(title, auth_info)| Entry::new(&title, auth_info)).collect())
+    }
+
     pub fn add(&mut self, entry: Entry) -> Result<()> {
         if self.data.keys().any(|k| k.as_str() == entry.title.as_str()) {
             bail!("the entry already been exist")
         } else {
+            self.touch(&entry.title);
             self.data.insert(entry.title, entry.auth_info);
             Ok(())
         }
     }
 
+    pub fn contains(&self, title: &str) -> bool {
+        self.data.contains_key(title)
+    }
+
     pub fn search(&self, title_pattern: &str) -> Vec<Entry> {
         Vec::from_iter(self.data.iter().filter_map(
             |(k, v)| {
             if k.as_str().starts_with(title_pattern) {
-                Some(Entry::new(&k, v.clone()))
+                Some(Entry::new(k, v.clone()))
             } else {
                 None
             }
@@ -128,6 +259,10 @@ where
         title_pattern: &str,
         input: &T,
     ) -> Result<Option<Entry>> {
+        if let Some(auth_info) = self.data.get(title_pattern) {
+            return Ok(Some(Entry::new(title_pattern, auth_info.clone())));
+        }
+
         let mut entries = self.search(title_pattern);
         let len = entries.len();
         if len == 0 {
@@ -139,12 +274,18 @@ where
 
         let sorted = entries.as_mut_slice();
         sorted.sort_by_key(|a| a.title.clone());
-        let entry = input.select_one(Vec::from(sorted.as_ref()))?;
-        return Ok(Some(entry));
+        let entry = input.select_one(Vec::from(sorted))?;
+        Ok(Some(entry))
     }
 
     pub fn change(&mut self, title: &str, entry: Entry) -> Result<()> {
-        if let Some(_) = self.data.get(title) {
+        if self.data.contains_key(title) {
+            self.touch(title);
+            self.touch(&entry.title);
+            if entry.title != title && self.audit.is_some() {
+                let old = self.renamed.remove(title).unwrap_or_else(|| String::from(title));
+                self.renamed.insert(entry.title.clone(), old);
+            }
             self.data.remove(title);
             self.data.insert(entry.title, entry.auth_info);
             Ok(())
@@ -153,20 +294,51 @@ where
         }
     }
 
+    pub fn remove(&mut self, title: &str) -> Result<Entry> {
+        self.touch(title);
+        match self.data.remove(title) {
+            Some(auth_info) => Ok(Entry::new(title, auth_info)),
+            None => bail!("the entry is not found"),
+        }
+    }
+
+    /// remove every entry.
+    pub fn clear(&mut self) {
+        let titles: Vec<String> = self.data.keys().cloned().collect();
+        for title in titles {
+            self.touch(&title);
+        }
+        self.data.clear();
+    }
+
+    /// encrypt with `cipher` from the next save on.
+    pub fn set_cipher(&mut self, cipher: CP) {
+        self.cipher = cipher;
+    }
+
     pub fn save(&mut self) -> Result<()> {
-        let j =
-            serde_json::to_string(&self.data).chain_err(|| "fail to converting this data to json")?;
-        let data = self.cipher.encrypt(j.as_bytes())?;
-        self.rw
-            .write_all(&data)
-            .chain_err(|| "fail to writing json data")?;
+        let j = SecretBytes::new(
+            serde_json::to_vec(&self.data).chain_err(|| "fail to converting this data to json")?,
+        );
+        let data = self.cipher.encrypt(j.expose())?;
+        // unchained, an error keeps its kind, as `VaultChanged`.
+        self.rw.write_all(&data)?;
+
+        let changes = self.changes();
+        self.touched.clear();
+        self.renamed.clear();
+        if let Some(ref audit) = self.audit {
+            audit
+                .append(&self.cipher, &changes)
+                .chain_err(|| "the vault was saved, but the change couldn't be added to the audit log")?;
+        }
         Ok(())
     }
 }
 
 pub trait EntrySelector {
     fn get_entry_info(&self, default: &Entry) -> Result<Entry>;
-    fn select_one(&self, Vec<Entry>) -> Result<Entry>;
+    fn select_one(&self, entries: Vec<Entry>) -> Result<Entry>;
 }
 
 pub struct CliEntrySelector;
@@ -178,64 +350,272 @@ impl CliEntrySelector {
 
     pub fn get_or_default(name: &str, default: &str) -> Result<String> {
         let sin = stdin();
-        let mut sout = stdout();
+        let mut serr = stderr();
         let message = format!("{} [{}]: ", name, default);
-        sout.write_all(message.as_bytes())
-            .chain_err(|| "can't write to stdout.")?;
-        sout.flush().chain_err(|| "can't flush to stdout")?;
+        serr.write_all(message.as_bytes())
+            .chain_err(|| "can't write to stderr.")?;
+        serr.flush().chain_err(|| "can't flush to stderr")?;
         let mut value = String::new();
         let size = sin.read_line(&mut value)
             .chain_err(|| "can't read from stdin")?;
         if size == 1 {
             Ok(String::from(default))
         } else {
-            Ok(value.trim_right().to_string())
+            Ok(value.trim_end().to_string())
+        }
+    }
+
+    /// read a secret without echo, asking twice until both inputs match.
+    /// An empty answer keeps `default`, which is never shown on screen.
+    pub fn get_secret_or_default(name: &str, default: &SecretString) -> Result<SecretString> {
+        let read = || Ok(SecretString::new(read_password().chain_err(|| "failed to reading a secret")?));
+        read_secret(name, default, read, stderr())
+    }
+}
+
+/// ask for `name` on `out` and read it twice with `read`.
+fn read_secret<R, W>(name: &str, default: &SecretString, mut read: R, mut out: W) -> Result<SecretString>
+where
+    R: FnMut() -> Result<SecretString>,
+    W: Write,
+{
+    let hint = if default.is_empty() { "" } else { " [unchanged]" };
+    loop {
+        write!(out, "{}{}: ", name, hint)
+            .and_then(|_| out.flush())
+            .chain_err(|| "can't write to stderr.")?;
+        let value = read()?;
+        if value.is_empty() {
+            return Ok(default.clone());
+        }
+
+        write!(out, "retype {}: ", name)
+            .and_then(|_| out.flush())
+            .chain_err(|| "can't write to stderr.")?;
+        if value == read()? {
+            return Ok(value);
         }
+        write!(out, "{}s do not match, try again.\r\n", name).chain_err(|| "can't write to stderr.")?;
     }
 }
 
+/// ask for the title, user, password and other of `default`, keeping the rest of it.
+fn edit_entry<L, S>(default: &Entry, mut line: L, mut secret: S) -> Result<Entry>
+where
+    L: FnMut(&str, &str) -> Result<String>,
+    S: FnMut(&str, &SecretString) -> Result<SecretString>,
+{
+    let mut entry = default.clone();
+    entry.title = line("title", default.title.as_str())?;
+    entry.auth_info.user = line("user", default.auth_info.user.as_str())?;
+    entry.auth_info.password = secret("password", &default.auth_info.password)?;
+    entry.auth_info.other = secret("other", &default.auth_info.other)?;
+    Ok(entry)
+}
+
 impl EntrySelector for CliEntrySelector {
     fn get_entry_info(&self, default: &Entry) -> Result<Entry> {
-        let auth = &default.auth_info;
-        let title = CliEntrySelector::get_or_default("title", default.title.as_str())?;
-        let user = CliEntrySelector::get_or_default("user", auth.user.as_str())?;
-        let password = CliEntrySelector::get_or_default("password", auth.password.as_str())?;
-        let other = CliEntrySelector::get_or_default("other", auth.other.as_str())?;
-        let auth = AuthInfo::new(&user, &password, &other);
-
-        Ok(Entry::new(&title, auth))
+        edit_entry(
+            default,
+            CliEntrySelector::get_or_default,
+            CliEntrySelector::get_secret_or_default,
+        )
     }
 
     fn select_one(&self, v: Vec<Entry>) -> Result<Entry> {
         let sin = stdin();
-        let mut sout = stdout();
+        let mut serr = stderr();
 
         loop {
             {
                 for (i, entry) in v.iter().enumerate() {
-                    sout.write_fmt(format_args!("[{}] {}\r\n", i, entry.title))
+                    serr.write_fmt(format_args!("[{}] {}\r\n", i, entry.title))
                         .chain_err(|| "")?
                 }
             }
-            sout.write_all("> ".as_bytes())
-                .chain_err(|| "can't write to stdout.")?;
-            sout.flush().chain_err(|| "failed to flush to stdout")?;
+            serr.write_all("> ".as_bytes())
+                .chain_err(|| "can't write to stderr.")?;
+            serr.flush().chain_err(|| "failed to flush to stderr")?;
 
             let mut line = String::new();
             sin.read_line(&mut line)
                 .chain_err(|| "can't read from stdio.")?;
-            let n = line.trim_right()
+            let n = line.trim_end()
                 .parse::<usize>()
                 .chain_err(|| "failed to convert strint to usize")?;
 
             if v.as_slice().len() >= n {
                 return Ok(v.into_iter().nth(n).unwrap());
             } else {
-                sout.write_all("invalid number\r\n".as_bytes())
-                    .chain_err(|| "failed to write to stdout")?;
+                serr.write_all("invalid number\r\n".as_bytes())
+                    .chain_err(|| "failed to write to stderr")?;
             }
 
-            sout.flush().chain_err(|| "can't flush to stdout.")?;
+            serr.flush().chain_err(|| "can't flush to stderr.")?;
+        }
+    }
+}
+
+/// An `EntrySelector` for scripts: values come from the command line and
+/// nothing is ever prompted.
+pub struct BatchEntrySelector {
+    user: Option<String>,
+    password: Option<SecretString>,
+    fields: Vec<(String, SecretString)>,
+}
+
+impl BatchEntrySelector {
+    pub fn new(
+        user: Option<String>,
+        password: Option<SecretString>,
+        fields: Vec<(String, SecretString)>,
+    ) -> BatchEntrySelector {
+        BatchEntrySelector {
+            user,
+            password,
+            fields,
+        }
+    }
+}
+
+impl EntrySelector for BatchEntrySelector {
+    fn get_entry_info(&self, default: &Entry) -> Result<Entry> {
+        let mut entry = default.clone();
+        if let Some(ref user) = self.user {
+            entry.auth_info.user = user.clone();
+        }
+        if let Some(ref password) = self.password {
+            entry.auth_info.password = password.clone();
         }
+        for (name, value) in &self.fields {
+            entry.auth_info.set_field(name, value.clone())?;
+        }
+        Ok(entry)
+    }
+
+    fn select_one(&self, v: Vec<Entry>) -> Result<Entry> {
+        let titles = v.into_iter().map(|e| e.title).collect();
+        bail!(ErrorKind::AmbiguousEntry(titles))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use entrystore::*;
+    use commands::Command;
+    use testutil::empty_store;
+
+    fn batch(user: Option<&str>, password: Option<&str>, fields: &[(&str, &str)]) -> BatchEntrySelector {
+        BatchEntrySelector::new(
+            user.map(String::from),
+            password.map(SecretString::from),
+            fields.iter().map(|&(k, v)| (String::from(k), SecretString::from(v))).collect(),
+        )
+    }
+
+    #[test]
+    fn batch_entry_info_test() {
+        let mut auth = AuthInfo::new("alice", SecretString::from("old"), SecretString::from("note"));
+        auth.set_field("host", SecretString::from("db.local")).unwrap();
+        let default = Entry::new("db", auth);
+
+        let kept = batch(None, None, &[]).get_entry_info(&default).unwrap();
+        assert!(kept == default);
+
+        let input = batch(Some("bob"), Some("new"), &[("other", "memo"), ("port", "5432")]);
+        let entry = input.get_entry_info(&default).unwrap();
+        assert_eq!("db", entry.title);
+        assert_eq!("bob", entry.auth_info.user);
+        assert_eq!("new", entry.auth_info.password.expose());
+        assert_eq!("memo", entry.auth_info.other.expose());
+        assert_eq!("db.local", entry.auth_info.get_field("host").unwrap().expose());
+        assert_eq!("5432", entry.auth_info.get_field("port").unwrap().expose());
+
+        assert!(batch(None, None, &[("password", "x")]).get_entry_info(&default).is_err());
+        assert!(batch(None, None, &[("title", "x")]).get_entry_info(&default).is_err());
+    }
+
+    #[test]
+    fn batch_select_one_test() {
+        let mut store = empty_store();
+        store.add(Entry::new("db/prod", AuthInfo::blank())).unwrap();
+        store.add(Entry::new("db/test", AuthInfo::blank())).unwrap();
+        let input = batch(None, None, &[]);
+
+        assert_eq!("db/prod", store.select_one("db/p", &input).unwrap().unwrap().title);
+        match store.select_one("db", &input) {
+            Err(Error(ErrorKind::AmbiguousEntry(titles), _)) => assert_eq!(vec!["db/prod", "db/test"], titles),
+            _ => panic!("the title should be ambiguous"),
+        }
+
+        let mut command = Command::new(batch(None, Some("pw"), &[]), store);
+        match command.change_entry("nope") {
+            Err(Error(ErrorKind::EntryNotFound(title), _)) => assert_eq!("nope", title),
+            _ => panic!("the entry should not be found"),
+        }
+        command.change_entry("db/t").unwrap();
+        assert_eq!("pw", command.get_entry("db/test").unwrap().unwrap().auth_info.password.expose());
+    }
+
+    /// answers the prompts of `edit_entry` with a fixed title and password.
+    struct Scripted;
+
+    impl EntrySelector for Scripted {
+        fn get_entry_info(&self, default: &Entry) -> Result<Entry> {
+            edit_entry(
+                default,
+                |name, default| Ok(String::from(if name == "title" { "db/new" } else { default })),
+                |name, default| Ok(if name == "password" { SecretString::from("new") } else { default.clone() }),
+            )
+        }
+
+        fn select_one(&self, mut entries: Vec<Entry>) -> Result<Entry> {
+            Ok(entries.remove(0))
+        }
+    }
+
+    #[test]
+    fn edit_entry_test() {
+        let mut auth = AuthInfo::new("alice", SecretString::from("old"), SecretString::from("note"));
+        auth.set_field("host", SecretString::from("db.local")).unwrap();
+        auth.tags.push(String::from("work"));
+        let mut store = empty_store();
+        store.add(Entry::new("db/old", auth)).unwrap();
+
+        let mut command = Command::new(Scripted, store);
+        command.change_entry("db/old").unwrap();
+        assert!(command.get_entry("db/old").unwrap().is_none());
+        let auth = command.get_entry("db/new").unwrap().unwrap().auth_info;
+        assert_eq!("alice", auth.user);
+        assert_eq!("new", auth.password.expose());
+        assert_eq!("note", auth.other.expose());
+        assert_eq!("db.local", auth.get_field("host").unwrap().expose());
+        assert_eq!(vec!["work"], auth.tags);
+    }
+
+    #[test]
+    fn read_secret_test() {
+        let answers = |list: &[&str]| {
+            let mut list: Vec<SecretString> = list.iter().map(|&a| SecretString::from(a)).collect();
+            list.reverse();
+            move || Ok(list.pop().unwrap())
+        };
+        let old = SecretString::from("hunter2");
+
+        let mut out = Vec::new();
+        let kept = read_secret("password", &old, answers(&[""]), &mut out).unwrap();
+        assert_eq!("hunter2", kept.expose());
+        assert_eq!("password [unchanged]: ", String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        let typed = read_secret("other", &old, answers(&["a", "b", "c", "c"]), &mut out).unwrap();
+        assert_eq!("c", typed.expose());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("others do not match"));
+        assert!(!out.contains("hunter2"));
+
+        let mut out = Vec::new();
+        read_secret("password", &SecretString::default(), answers(&[""]), &mut out).unwrap();
+        assert_eq!("password: ", String::from_utf8(out).unwrap());
     }
 }
@@ -0,0 +1,41 @@
+use rand::{OsRng, Rng};
+use secret::SecretString;
+use errors::*;
+
+const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!#$%&()*+,-./:;<=>?@[]^_{|}~";
+/// the letters and digits at the start of `CHARSET`.
+const ALPHANUMERIC: usize = 62;
+
+/// Default length of generated passwords.
+pub const DEFAULT_LENGTH: usize = 20;
+
+/// generate a random password from printable ASCII characters, or from
+/// letters and digits only without `symbols`.
+pub fn generate(length: usize, symbols: bool) -> Result<SecretString> {
+    if length == 0 {
+        bail!("the password length must be positive");
+    }
+    let mut rng = OsRng::new().chain_err(|| "can't open the OS random generator")?;
+    let charset = if symbols { CHARSET } else { &CHARSET[..ALPHANUMERIC] };
+    let mut password = String::with_capacity(length);
+    for _ in 0..length {
+        let i = rng.gen_range(0, charset.len());
+        password.push(charset[i] as char);
+    }
+    Ok(SecretString::new(password))
+}
+
+#[cfg(test)]
+mod test {
+    use generator::*;
+
+    #[test]
+    fn generate_test() {
+        let password = generate(32, true).unwrap();
+        assert_eq!(32, password.expose().len());
+        assert!(password.expose().bytes().all(|b| CHARSET.contains(&b)));
+        let password = generate(200, false).unwrap();
+        assert!(password.expose().bytes().all(|b| b.is_ascii_alphanumeric()));
+        assert!(generate(0, true).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Adding entries read from other password managers to the vault.
+use entrystore::{AuthInfo, Cipher, Entry, EntryStore};
+use rawio::RawStore;
+use secret::SecretString;
+use errors::*;
+
+/// names of the entry that custom fields can't take.
+const RESERVED_FIELDS: &[&str] = &["title", "user", "password", "other"];
+
+/// An item of another password manager, the common ground of the importers
+/// of JSON exports.
+#[derive(Default, Debug, PartialEq)]
+pub struct Item {
+    /// folders separated by `/`.
+    pub folder: String,
+    pub name: String,
+    pub user: String,
+    pub password: SecretString,
+    pub notes: SecretString,
+    /// in their order, a name may come more than once.
+    pub fields: Vec<(String, SecretString)>,
+    pub tags: Vec<String>,
+}
+
+impl Item {
+    /// add a field unless its value is empty.
+    pub fn add_field(&mut self, name: &str, value: SecretString) {
+        if !value.is_empty() {
+            self.fields.push((String::from(name.trim()), value));
+        }
+    }
+
+    /// the entry of the item. A name used twice becomes `NAME (2)`, and so
+    /// do the names of the entry like `password`.
+    pub fn into_entry(self) -> Entry {
+        let mut auth = AuthInfo::new(&self.user, self.password, self.notes);
+        auth.tags = self.tags;
+        for (name, value) in self.fields {
+            let name = if name.is_empty() { String::from("field") } else { name };
+            let free = (1..)
+                .map(|n| if n == 1 { name.clone() } else { format!("{} ({})", name, n) })
+                .find(|n| !RESERVED_FIELDS.contains(&n.as_str()) && !auth.fields.contains_key(n))
+                .unwrap();
+            auth.fields.insert(free, value);
+        }
+
+        let name = self.name.trim();
+        let name = if name.is_empty() { "untitled" } else { name };
+        let folder = self.folder.trim_matches('/');
+        if folder.is_empty() {
+            Entry::new(name, auth)
+        } else {
+            Entry::new(&format!("{}/{}", folder, name), auth)
+        }
+    }
+}
+
+/// What to do with an entry whose title is already taken.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConflictPolicy {
+    /// keep the existing entry.
+    Skip,
+    /// replace the existing entry.
+    Overwrite,
+    /// add the entry as `TITLE (2)`, `TITLE (3)`, ...
+    Rename,
+    /// import nothing if any title is taken.
+    Fail,
+}
+
+impl ConflictPolicy {
+    pub const NAMES: &'static [&'static str] = &["skip", "overwrite", "rename", "fail"];
+
+    pub fn parse(name: &str) -> Result<ConflictPolicy> {
+        match name {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "rename" => Ok(ConflictPolicy::Rename),
+            "fail" => Ok(ConflictPolicy::Fail),
+            _ => bail!("unknown conflict policy [{}]", name),
+        }
+    }
+}
+
+/// The outcome of an import, by title.
+#[derive(Default, Debug)]
+pub struct ImportReport {
+    pub added: Vec<String>,
+    pub overwritten: Vec<String>,
+    /// the original title and the one the entry got.
+    pub renamed: Vec<(String, String)>,
+    pub skipped: Vec<String>,
+    /// entries that came twice in the same import.
+    pub duplicates: Vec<String>,
+}
+
+impl ImportReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} added, {} overwritten, {} renamed, {} skipped, {} duplicates.",
+            self.added.len(),
+            self.overwritten.len(),
+            self.renamed.len(),
+            self.skipped.len(),
+            self.duplicates.len()
+        )
+    }
+}
+
+fn free_title<RW: RawStore, CP: Cipher>(store: &EntryStore<RW, CP>, title: &str) -> String {
+    (2..)
+        .map(|n| format!("{} ({})", title, n))
+        .find(|candidate| !store.contains(candidate))
+        .unwrap()
+}
+
+/// whether the title of each entry is taken, in the store or by an earlier
+/// entry of the same import.
+pub fn taken<RW: RawStore, CP: Cipher>(store: &EntryStore<RW, CP>, entries: &[Entry]) -> Vec<bool> {
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| store.contains(&entry.title) || entries[..i].iter().any(|e| e.title == entry.title))
+        .collect()
+}
+
+/// add `entries` to the store without saving it. An entry equal to an
+/// earlier one of the same import is left out, other entries of the same
+/// import conflict with each other like with the existing ones.
+pub fn apply<RW, CP>(
+    store: &mut EntryStore<RW, CP>,
+    entries: Vec<Entry>,
+    policy: ConflictPolicy,
+) -> Result<ImportReport>
+where
+    RW: RawStore,
+    CP: Cipher,
+{
+    let mut report = ImportReport::default();
+    let mut unique: Vec<Entry> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if unique.contains(&entry) {
+            report.duplicates.push(entry.title);
+        } else {
+            unique.push(entry);
+        }
+    }
+    let entries = unique;
+
+    if policy == ConflictPolicy::Fail {
+        let taken = taken(store, &entries);
+        if taken.iter().any(|&t| t) {
+            let taken: Vec<&str> = entries
+                .iter()
+                .zip(taken)
+                .filter(|&(_, t)| t)
+                .map(|(e, _)| e.title.as_str())
+                .collect();
+            bail!(
+                "these titles are already taken: {}. Choose a policy with --on-conflict.",
+                taken.join(", ")
+            );
+        }
+    }
+
+    for mut entry in entries {
+        if !store.contains(&entry.title) {
+            report.added.push(entry.title.clone());
+            store.add(entry)?;
+            continue;
+        }
+        match policy {
+            ConflictPolicy::Skip | ConflictPolicy::Fail => report.skipped.push(entry.title),
+            ConflictPolicy::Overwrite => {
+                let title = entry.title.clone();
+                store.change(&title, entry)?;
+                report.overwritten.push(title);
+            }
+            ConflictPolicy::Rename => {
+                let title = free_title(store, &entry.title);
+                report.renamed.push((entry.title, title.clone()));
+                entry.title = title;
+                store.add(entry)?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use import::*;
+    use entrystore::AuthInfo;
+    use secret::SecretString;
+    use testutil::empty_store;
+
+    fn entry(title: &str, password: &str) -> Entry {
+        Entry::new(title, AuthInfo::new("", SecretString::from(password), SecretString::default()))
+    }
+
+    #[test]
+    fn policies_test() {
+        let mut store = empty_store();
+        store.add(entry("a", "old")).unwrap();
+        let imported = || vec![entry("a", "new"), entry("b", "1"), entry("b", "2")];
+
+        assert!(apply(&mut store, imported(), ConflictPolicy::Fail).is_err());
+        assert!(!store.contains("b"));
+
+        let report = apply(&mut store, imported(), ConflictPolicy::Skip).unwrap();
+        assert_eq!(vec!["b"], report.added);
+        assert_eq!(vec!["a", "b"], report.skipped);
+
+        let report = apply(&mut store, imported(), ConflictPolicy::Rename).unwrap();
+        assert_eq!(3, report.renamed.len());
+        assert!(store.contains("a (2)") && store.contains("b (3)"));
+
+        apply(&mut store, imported(), ConflictPolicy::Overwrite).unwrap();
+        let a = store.search("a").into_iter().find(|e| e.title == "a").unwrap();
+        assert_eq!("new", a.auth_info.password.expose());
+
+        let report = apply(&mut store, vec![entry("c", "1"), entry("c", "1")], ConflictPolicy::Fail).unwrap();
+        assert_eq!(vec!["c"], report.added);
+        assert_eq!(vec!["c"], report.duplicates);
+    }
+
+    #[test]
+    fn item_test() {
+        let mut item = Item {
+            folder: String::from("/Work/"),
+            user: String::from("alice"),
+            ..Item::default()
+        };
+        item.add_field("url", SecretString::from("https://a"));
+        item.add_field("url", SecretString::from("https://b"));
+        item.add_field("password", SecretString::from("pin"));
+        item.add_field("empty", SecretString::default());
+
+        let entry = item.into_entry();
+        assert_eq!("Work/untitled", entry.title);
+        let names: Vec<&String> = entry.auth_info.fields.keys().collect();
+        assert_eq!(vec!["password (2)", "url", "url (2)"], names);
+    }
+}
@@ -0,0 +1,1005 @@
+//! KeePass databases in the KDBX 3.1 and 4 formats.
+//!
+//! Groups become folders of the title: the entry `db` of the group `Servers`
+//! in `Work` is imported as `Work/Servers/db`. The root group is left out of
+//! the path and the recycle bin is not imported. `Title`, `UserName`,
+//! `Password` and `Notes` are the title, user, password and other of the
+//! entry, `URL` is the `url` field and custom strings keep their names.
+//!
+//! Entries are exported the same way to KDBX 4 with Argon2 and ChaCha20, so
+//! an exported database imports back to the same entries.
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use argon2::{self, Argon2};
+use base64;
+use crypto::aes::KeySize;
+use crypto::aessafe::AesSafe256Encryptor;
+use crypto::chacha20::ChaCha20;
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::salsa20::Salsa20;
+use crypto::sha2::{Sha256, Sha512};
+use crypto::symmetriccipher::{BlockEncryptor, SynchronousStreamCipher};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use rand::{OsRng, Rng};
+use entrystore::{AuthInfo, Entry};
+use primitives::{self, decrypt_aes, sha256};
+use secret::{self, SecretBytes, SecretString};
+use errors::*;
+
+const SIGNATURE1: u32 = 0x9AA2_D903;
+const SIGNATURE2: u32 = 0xB54B_FB67;
+
+const AES_CIPHER: &[u8] = &[
+    0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50, 0xbe, 0x58, 0x05, 0x21, 0x6a, 0xfc, 0x5a, 0xff,
+];
+const CHACHA20_CIPHER: &[u8] = &[
+    0xd6, 0x03, 0x8a, 0x2b, 0x8b, 0x6f, 0x4c, 0xb5, 0xa5, 0x24, 0x33, 0x9a, 0x31, 0xdb, 0xb5, 0x9a,
+];
+const AES_KDF: &[u8] = &[
+    0xc9, 0xd9, 0xf3, 0x9a, 0x62, 0x8a, 0x44, 0x60, 0xbf, 0x74, 0x0d, 0x08, 0xc1, 0x8a, 0x4f, 0xea,
+];
+const AES_KDF_KDBX4: &[u8] = &[
+    0x7c, 0x02, 0xbb, 0x82, 0x79, 0xa7, 0x4a, 0xc0, 0x92, 0x7d, 0x11, 0x4a, 0x00, 0x64, 0x82, 0x38,
+];
+const ARGON2D_KDF: &[u8] = &[
+    0xef, 0x63, 0x6d, 0xdf, 0x8c, 0x29, 0x44, 0x4b, 0x91, 0xf7, 0xa9, 0xa4, 0x03, 0xe3, 0x0a, 0x0c,
+];
+const ARGON2ID_KDF: &[u8] = &[
+    0x9e, 0x29, 0x8b, 0x19, 0x56, 0xdb, 0x47, 0x73, 0xb2, 0x3d, 0xfc, 0x3e, 0xc6, 0xf0, 0xa1, 0xe6,
+];
+
+const SALSA20_STREAM: u32 = 2;
+const CHACHA20_STREAM: u32 = 3;
+const SALSA20_NONCE: &[u8] = &[0xe8, 0x30, 0x09, 0x4b, 0x97, 0x20, 0x5d, 0x2a];
+
+/// The most expensive AES-KDF accepted, the limits of Argon2 are shared
+/// with the other formats.
+const MAX_AES_ROUNDS: u64 = 100_000_000;
+
+/// Reads little endian values.
+struct Bytes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn new(data: &'a [u8]) -> Bytes<'a> {
+        Bytes { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            bail!("truncated database");
+        }
+        self.pos += len;
+        Ok(&self.data[self.pos - len..self.pos])
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(le(self.take(2)?) as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(le(self.take(4)?) as u32)
+    }
+}
+
+fn le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |n, &b| n << 8 | u64::from(b))
+}
+
+fn sha512(parts: &[&[u8]]) -> SecretBytes {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.input(part);
+    }
+    let mut out = SecretBytes::new(vec![0; 64]);
+    hasher.result(out.expose_mut());
+    out
+}
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let mut mac = Hmac::new(Sha256::new(), key);
+    for part in parts {
+        mac.input(part);
+    }
+    mac.result().code().to_vec()
+}
+
+enum Kdf {
+    Aes { seed: Vec<u8>, rounds: u64 },
+    Argon2 {
+        algorithm: argon2::Algorithm,
+        salt: Vec<u8>,
+        memory: u64,
+        iterations: u64,
+        lanes: u32,
+        version: u32,
+    },
+}
+
+struct Header {
+    major: u16,
+    cipher: Vec<u8>,
+    compressed: bool,
+    master_seed: Vec<u8>,
+    iv: Vec<u8>,
+    kdf: Kdf,
+    /// the inner stream of KDBX 3.1. KDBX 4 keeps it in the inner header.
+    stream_id: u32,
+    stream_key: SecretBytes,
+    start_bytes: Vec<u8>,
+    /// the length of the header in the file.
+    len: usize,
+}
+
+/// the values of a KDBX 4 variant dictionary by name.
+fn read_variant_dictionary(data: &[u8]) -> Result<HashMap<String, Vec<u8>>> {
+    let mut r = Bytes::new(data);
+    if r.u16()? >> 8 != 1 {
+        bail!("unsupported kdf parameters");
+    }
+    let mut values = HashMap::new();
+    loop {
+        let kind = r.u8()?;
+        if kind == 0 {
+            return Ok(values);
+        }
+        let name_len = r.u32()? as usize;
+        let name = String::from_utf8_lossy(r.take(name_len)?).into_owned();
+        let value_len = r.u32()? as usize;
+        values.insert(name, r.take(value_len)?.to_vec());
+    }
+}
+
+fn read_kdf(data: &[u8]) -> Result<Kdf> {
+    let params = read_variant_dictionary(data)?;
+    let get = |name: &str| match params.get(name) {
+        Some(value) => Ok(value.clone()),
+        None => Err(Error::from(format!("the kdf parameter [{}] is missing", name))),
+    };
+    let uuid = get("$UUID")?;
+    if uuid == AES_KDF || uuid == AES_KDF_KDBX4 {
+        return Ok(Kdf::Aes {
+            seed: get("S")?,
+            rounds: le(&get("R")?),
+        });
+    }
+    let algorithm = if uuid == ARGON2D_KDF {
+        argon2::Algorithm::Argon2d
+    } else if uuid == ARGON2ID_KDF {
+        argon2::Algorithm::Argon2id
+    } else {
+        bail!("unsupported kdf");
+    };
+    Ok(Kdf::Argon2 {
+        algorithm,
+        salt: get("S")?,
+        memory: le(&get("M")?),
+        iterations: le(&get("I")?),
+        lanes: le(&get("P")?) as u32,
+        version: le(&get("V")?) as u32,
+    })
+}
+
+fn read_header(data: &[u8]) -> Result<Header> {
+    let mut r = Bytes::new(data);
+    if r.u32()? != SIGNATURE1 || r.u32()? != SIGNATURE2 {
+        bail!("not a KeePass database");
+    }
+    r.u16()?;
+    let major = r.u16()?;
+    if major != 3 && major != 4 {
+        bail!("unsupported KDBX version {}", major);
+    }
+
+    let mut header = Header {
+        major,
+        cipher: Vec::new(),
+        compressed: false,
+        master_seed: Vec::new(),
+        iv: Vec::new(),
+        kdf: Kdf::Aes {
+            seed: Vec::new(),
+            rounds: 0,
+        },
+        stream_id: 0,
+        stream_key: SecretBytes::new(Vec::new()),
+        start_bytes: Vec::new(),
+        len: 0,
+    };
+    let mut seed = Vec::new();
+    let mut rounds = 0;
+    loop {
+        let id = r.u8()?;
+        let len = if major == 3 {
+            r.u16()? as usize
+        } else {
+            r.u32()? as usize
+        };
+        let value = r.take(len)?;
+        match id {
+            0 => break,
+            2 => header.cipher = value.to_vec(),
+            3 => header.compressed = le(value) != 0,
+            4 => header.master_seed = value.to_vec(),
+            5 => seed = value.to_vec(),
+            6 => rounds = le(value),
+            7 => header.iv = value.to_vec(),
+            8 => header.stream_key = SecretBytes::from_slice(value),
+            9 => header.start_bytes = value.to_vec(),
+            10 => header.stream_id = le(value) as u32,
+            11 => header.kdf = read_kdf(value)?,
+            _ => {}
+        }
+    }
+    if major == 3 {
+        header.kdf = Kdf::Aes { seed, rounds };
+    }
+    header.len = r.pos;
+    Ok(header)
+}
+
+fn aes_encryptor(key: &[u8]) -> Box<dyn BlockEncryptor> {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if ::crypto::util::supports_aesni() {
+            return Box::new(::crypto::aesni::AesNiEncryptor::new(KeySize::KeySize256, key));
+        }
+    }
+    Box::new(AesSafe256Encryptor::new(key))
+}
+
+/// the key of the payload cipher and of the KDBX 4 HMACs.
+fn transform_key(kdf: &Kdf, password: &SecretString) -> Result<SecretBytes> {
+    let hashed = sha256(&[password.expose().as_bytes()]);
+    let composite = sha256(&[hashed.expose()]);
+    match *kdf {
+        Kdf::Aes { ref seed, rounds } => {
+            if seed.len() != 32 {
+                bail!("invalid kdf seed");
+            }
+            if rounds > MAX_AES_ROUNDS {
+                bail!("the kdf asks for {} rounds, at most {} are accepted", rounds, MAX_AES_ROUNDS);
+            }
+            let encryptor = aes_encryptor(seed);
+            let mut key = composite.clone();
+            let mut block = SecretBytes::new(vec![0; 16]);
+            for _ in 0..rounds {
+                for half in key.expose_mut().chunks_mut(16) {
+                    encryptor.encrypt_block(half, block.expose_mut());
+                    half.copy_from_slice(block.expose());
+                }
+            }
+            Ok(sha256(&[key.expose()]))
+        }
+        Kdf::Argon2 {
+            algorithm,
+            ref salt,
+            memory,
+            iterations,
+            lanes,
+            version,
+        } => {
+            let version = match version {
+                0x10 => argon2::Version::V0x10,
+                0x13 => argon2::Version::V0x13,
+                _ => bail!("unsupported argon2 version {}", version),
+            };
+            primitives::check_argon2(memory / 1024, iterations, u64::from(lanes))?;
+            let params = argon2::Params::new((memory / 1024) as u32, iterations as u32, lanes, Some(32))
+                .map_err(|e| Error::from(format!("invalid argon2 parameters: {}", e)))?;
+            let mut key = SecretBytes::new(vec![0; 32]);
+            Argon2::new(algorithm, version, params)
+                .hash_password_into(composite.expose(), salt, key.expose_mut())
+                .map_err(|e| Error::from(format!("argon2 failed: {}", e)))?;
+            Ok(key)
+        }
+    }
+}
+
+fn decrypt_payload(header: &Header, key: &[u8], data: &[u8]) -> Result<SecretBytes> {
+    if header.cipher == AES_CIPHER {
+        decrypt_aes(key, &header.iv, data)
+    } else if header.cipher == CHACHA20_CIPHER {
+        if header.iv.len() != 12 {
+            bail!("invalid ChaCha20 nonce");
+        }
+        let mut plain = SecretBytes::new(vec![0; data.len()]);
+        ChaCha20::new(key, &header.iv).process(data, plain.expose_mut());
+        Ok(plain)
+    } else {
+        bail!("unsupported cipher, only AES and ChaCha20 databases can be read");
+    }
+}
+
+/// the content of the KDBX 3.1 hashed block stream.
+fn read_hashed_blocks(data: &[u8]) -> Result<SecretBytes> {
+    let mut r = Bytes::new(data);
+    let mut content = Vec::with_capacity(data.len());
+    loop {
+        r.u32()?;
+        let hash = r.take(32)?;
+        let len = r.u32()? as usize;
+        if len == 0 {
+            return Ok(SecretBytes::new(content));
+        }
+        let block = r.take(len)?;
+        if sha256(&[block]).expose() != hash {
+            secret::wipe(&mut content);
+            bail!("corrupted database");
+        }
+        content.extend_from_slice(block);
+    }
+}
+
+fn block_key(hmac_key: &[u8], index: u64) -> SecretBytes {
+    sha512(&[&u64_le(index), hmac_key])
+}
+
+fn u64_le(n: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = (n >> (8 * i)) as u8;
+    }
+    bytes
+}
+
+/// check the header of a KDBX 4 file and return its HMAC block stream content.
+fn read_hmac_blocks(data: &[u8], header: &Header, hmac_key: &[u8]) -> Result<Vec<u8>> {
+    let mut r = Bytes::new(&data[header.len..]);
+    let hash = r.take(32)?;
+    if sha256(&[&data[..header.len]]).expose() != hash {
+        bail!("corrupted database header");
+    }
+    let mac = r.take(32)?;
+    if hmac_sha256(block_key(hmac_key, u64::MAX).expose(), &[&data[..header.len]]) != mac {
+        bail!("wrong password");
+    }
+
+    let mut content = Vec::with_capacity(data.len());
+    let mut index = 0;
+    loop {
+        let mac = r.take(32)?;
+        let len_bytes = r.take(4)?;
+        let len = le(len_bytes) as usize;
+        let block = r.take(len)?;
+        let key = block_key(hmac_key, index);
+        if hmac_sha256(key.expose(), &[&u64_le(index), len_bytes, block]) != mac {
+            bail!("corrupted database");
+        }
+        if len == 0 {
+            return Ok(content);
+        }
+        content.extend_from_slice(block);
+        index += 1;
+    }
+}
+
+fn gunzip(data: &[u8]) -> Result<SecretBytes> {
+    let mut plain = Vec::new();
+    if GzDecoder::new(data).read_to_end(&mut plain).is_err() {
+        secret::wipe(&mut plain);
+        bail!("corrupted compressed data");
+    }
+    Ok(SecretBytes::new(plain))
+}
+
+/// The stream protecting values in the XML.
+enum InnerStream {
+    Salsa20(Salsa20),
+    ChaCha20(ChaCha20),
+}
+
+impl InnerStream {
+    fn new(id: u32, key: &[u8]) -> Result<InnerStream> {
+        match id {
+            SALSA20_STREAM => Ok(InnerStream::Salsa20(Salsa20::new(sha256(&[key]).expose(), SALSA20_NONCE))),
+            CHACHA20_STREAM => {
+                let hash = sha512(&[key]);
+                let hash = hash.expose();
+                Ok(InnerStream::ChaCha20(ChaCha20::new(&hash[..32], &hash[32..44])))
+            }
+            _ => bail!("unsupported inner stream {}", id),
+        }
+    }
+
+    fn apply(&mut self, data: &SecretBytes) -> SecretBytes {
+        let mut out = SecretBytes::new(vec![0; data.expose().len()]);
+        match *self {
+            InnerStream::Salsa20(ref mut c) => c.process(data.expose(), out.expose_mut()),
+            InnerStream::ChaCha20(ref mut c) => c.process(data.expose(), out.expose_mut()),
+        }
+        out
+    }
+}
+
+/// An element of the XML document.
+struct Node {
+    name: String,
+    attrs: Vec<(String, String)>,
+    text: SecretString,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|a| a.0 == name).map(|a| a.1.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|n| n.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Node> + 'a {
+        self.children.iter().filter(move |n| n.name == name)
+    }
+
+    /// the text of a child, empty if there is none.
+    fn child_text(&self, name: &str) -> &str {
+        self.child(name).map_or("", |n| n.text.expose())
+    }
+}
+
+fn start_node(e: &::quick_xml::events::BytesStart) -> Result<Node> {
+    let mut attrs = Vec::new();
+    for attr in e.attributes() {
+        let attr = attr.chain_err(|| "invalid xml")?;
+        let value = attr.unescape_value().chain_err(|| "invalid xml")?;
+        attrs.push((String::from_utf8_lossy(attr.key.as_ref()).into_owned(), value.into_owned()));
+    }
+    Ok(Node {
+        name: String::from_utf8_lossy(e.name().as_ref()).into_owned(),
+        attrs,
+        text: SecretString::default(),
+        children: Vec::new(),
+    })
+}
+
+/// parse the XML, unprotecting values in document order as the format requires.
+fn read_xml(xml: &[u8], stream: &mut InnerStream) -> Result<Node> {
+    let mut reader = Reader::from_reader(xml);
+    let mut stack: Vec<(Node, String)> = Vec::new();
+    loop {
+        match reader.read_event().chain_err(|| "invalid xml")? {
+            Event::Start(e) => stack.push((start_node(&e)?, String::new())),
+            Event::Empty(e) => {
+                let node = start_node(&e)?;
+                match stack.last_mut() {
+                    Some(parent) => parent.0.children.push(node),
+                    None => return Ok(node),
+                }
+            }
+            Event::Text(e) => {
+                if let Some(top) = stack.last_mut() {
+                    top.1.push_str(&e.unescape().chain_err(|| "invalid xml")?);
+                }
+            }
+            Event::CData(e) => {
+                if let Some(top) = stack.last_mut() {
+                    top.1.push_str(&String::from_utf8_lossy(&e.into_inner()));
+                }
+            }
+            Event::End(_) => {
+                let (mut node, text) = match stack.pop() {
+                    Some(top) => top,
+                    None => bail!("invalid xml"),
+                };
+                let text = SecretString::new(text);
+                node.text = if node.attr("Protected") == Some("True") {
+                    let data = base64::decode(text.expose().trim()).chain_err(|| "invalid protected value")?;
+                    let plain = stream.apply(&SecretBytes::new(data));
+                    if node.name == "Value" {
+                        SecretString::new(String::from_utf8_lossy(plain.expose()).into_owned())
+                    } else {
+                        SecretString::new(base64::encode(plain.expose()))
+                    }
+                } else {
+                    text
+                };
+                match stack.last_mut() {
+                    Some(parent) => parent.0.children.push(node),
+                    None => return Ok(node),
+                }
+            }
+            Event::Eof => bail!("unexpected end of xml"),
+            _ => {}
+        }
+    }
+}
+
+fn read_entry(node: &Node, binaries: &HashMap<String, SecretBytes>) -> Result<(String, AuthInfo)> {
+    let mut title = String::new();
+    let mut auth = AuthInfo::blank();
+    for string in node.children("String") {
+        let value = SecretString::from(string.child_text("Value"));
+        match string.child_text("Key") {
+            "Title" => title = String::from(value.expose()),
+            "UserName" => auth.user = String::from(value.expose()),
+            "Password" => auth.password = value,
+            "Notes" => auth.other = value,
+            "URL" if value.is_empty() => {}
+            "URL" => {
+                auth.fields.insert(String::from("url"), value);
+            }
+            key => {
+                auth.fields.insert(String::from(key), value);
+            }
+        }
+    }
+
+    auth.tags = node
+        .child_text("Tags")
+        .split(&[';', ','][..])
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .map(String::from)
+        .collect();
+
+    for binary in node.children("Binary") {
+        let reference = binary.child("Value").and_then(|v| v.attr("Ref")).unwrap_or("");
+        match binaries.get(reference) {
+            Some(data) => {
+                auth.attachments.insert(String::from(binary.child_text("Key")), data.clone());
+            }
+            None => bail!("the attachment [{}] of [{}] is missing", binary.child_text("Key"), title),
+        }
+    }
+
+    if let Some(history) = node.child("History") {
+        for old in history.children("Entry") {
+            auth.history.push(read_entry(old, binaries)?.1);
+        }
+    }
+    Ok((title, auth))
+}
+
+fn read_group(
+    group: &Node,
+    folder: &str,
+    recycle_bin: &str,
+    binaries: &HashMap<String, SecretBytes>,
+    entries: &mut Vec<Entry>,
+) -> Result<()> {
+    for node in group.children("Entry") {
+        let (title, auth) = read_entry(node, binaries)?;
+        let title = if title.is_empty() { "untitled" } else { &title };
+        entries.push(Entry::new(&format!("{}{}", folder, title), auth));
+    }
+    for sub in group.children("Group") {
+        if !recycle_bin.is_empty() && sub.child_text("UUID") == recycle_bin {
+            continue;
+        }
+        let folder = format!("{}{}/", folder, sub.child_text("Name"));
+        read_group(sub, &folder, recycle_bin, binaries, entries)?;
+    }
+    Ok(())
+}
+
+/// read the entries of a database protected by `password`.
+pub fn read(data: &[u8], password: &SecretString) -> Result<Vec<Entry>> {
+    let header = read_header(data)?;
+    let transformed = transform_key(&header.kdf, password)?;
+    let key = sha256(&[&header.master_seed, transformed.expose()]);
+
+    let mut binaries = HashMap::new();
+    let (content, mut stream) = if header.major == 3 {
+        let decrypted = decrypt_payload(&header, key.expose(), &data[header.len..])?;
+        let start_len = header.start_bytes.len();
+        if decrypted.expose().len() < start_len || decrypted.expose()[..start_len] != header.start_bytes[..] {
+            bail!("wrong password");
+        }
+        let mut content = read_hashed_blocks(&decrypted.expose()[start_len..])?;
+        if header.compressed {
+            content = gunzip(content.expose())?;
+        }
+        let stream = InnerStream::new(header.stream_id, header.stream_key.expose())?;
+        (content, stream)
+    } else {
+        let hmac_key = sha512(&[&header.master_seed, transformed.expose(), &[1]]);
+        let encrypted = read_hmac_blocks(data, &header, hmac_key.expose())?;
+        let mut content = decrypt_payload(&header, key.expose(), &encrypted)?;
+        if header.compressed {
+            content = gunzip(content.expose())?;
+        }
+
+        let mut r = Bytes::new(content.expose());
+        let mut stream_id = 0;
+        let mut stream_key = SecretBytes::new(Vec::new());
+        loop {
+            let id = r.u8()?;
+            let len = r.u32()? as usize;
+            let value = r.take(len)?;
+            match id {
+                0 => break,
+                1 => stream_id = le(value) as u32,
+                2 => stream_key = SecretBytes::from_slice(value),
+                // the first byte holds flags.
+                3 if !value.is_empty() => {
+                    binaries.insert(binaries.len().to_string(), SecretBytes::from_slice(&value[1..]));
+                }
+                _ => {}
+            }
+        }
+        let xml = SecretBytes::from_slice(r.rest());
+        (xml, InnerStream::new(stream_id, stream_key.expose())?)
+    };
+
+    let root = read_xml(content.expose(), &mut stream)?;
+    let meta = root.child("Meta");
+    if let Some(meta_binaries) = meta.and_then(|m| m.child("Binaries")) {
+        for binary in meta_binaries.children("Binary") {
+            let data = base64::decode(binary.text.expose().trim()).chain_err(|| "invalid attachment")?;
+            let data = if binary.attr("Compressed") == Some("True") {
+                gunzip(SecretBytes::new(data).expose())?
+            } else {
+                SecretBytes::new(data)
+            };
+            binaries.insert(String::from(binary.attr("ID").unwrap_or("")), data);
+        }
+    }
+    let recycle_bin = meta.map_or("", |m| m.child_text("RecycleBinUUID"));
+
+    let mut entries = Vec::new();
+    if let Some(group) = root.child("Root").and_then(|r| r.child("Group")) {
+        read_group(group, "", recycle_bin, &binaries, &mut entries)?;
+    }
+    Ok(entries)
+}
+
+fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut rng = OsRng::new().chain_err(|| "can't open the OS random generator")?;
+    let mut bytes = vec![0; len];
+    rng.fill_bytes(&mut bytes);
+    Ok(bytes)
+}
+
+fn put_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&u64_le(u64::from(n))[..4]);
+}
+
+/// a field of the outer or the inner KDBX 4 header.
+fn put_field(out: &mut Vec<u8>, id: u8, value: &[u8]) {
+    out.push(id);
+    put_u32(out, value.len() as u32);
+    out.extend_from_slice(value);
+}
+
+fn put_variant(out: &mut Vec<u8>, kind: u8, name: &str, value: &[u8]) {
+    out.push(kind);
+    put_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+    put_u32(out, value.len() as u32);
+    out.extend_from_slice(value);
+}
+
+/// the kdf parameters as a variant dictionary.
+fn write_kdf(kdf: &Kdf) -> Vec<u8> {
+    const BYTES: u8 = 0x42;
+    const U32: u8 = 0x04;
+    const U64: u8 = 0x05;
+    let mut out = vec![0x00, 0x01];
+    match *kdf {
+        Kdf::Aes { ref seed, rounds } => {
+            put_variant(&mut out, BYTES, "$UUID", AES_KDF_KDBX4);
+            put_variant(&mut out, BYTES, "S", seed);
+            put_variant(&mut out, U64, "R", &u64_le(rounds));
+        }
+        Kdf::Argon2 {
+            algorithm,
+            ref salt,
+            memory,
+            iterations,
+            lanes,
+            version,
+        } => {
+            let uuid = match algorithm {
+                argon2::Algorithm::Argon2d => ARGON2D_KDF,
+                _ => ARGON2ID_KDF,
+            };
+            put_variant(&mut out, BYTES, "$UUID", uuid);
+            put_variant(&mut out, BYTES, "S", salt);
+            put_variant(&mut out, U32, "P", &u64_le(u64::from(lanes))[..4]);
+            put_variant(&mut out, U64, "M", &u64_le(memory));
+            put_variant(&mut out, U64, "I", &u64_le(iterations));
+            put_variant(&mut out, U32, "V", &u64_le(u64::from(version))[..4]);
+        }
+    }
+    out.push(0);
+    out
+}
+
+/// Entries by folder.
+#[derive(Default)]
+struct Group<'a> {
+    entries: Vec<(&'a str, &'a AuthInfo)>,
+    groups: BTreeMap<&'a str, Group<'a>>,
+}
+
+impl<'a> Group<'a> {
+    fn tree(entries: &'a [Entry]) -> Group<'a> {
+        let mut root = Group::default();
+        for entry in entries {
+            let mut parts: Vec<&str> = entry.title.split('/').collect();
+            let title = parts.pop().unwrap_or("");
+            let group = parts
+                .into_iter()
+                .filter(|p| !p.is_empty())
+                .fold(&mut root, |group, name| group.groups.entry(name).or_default());
+            group.entries.push((title, &entry.auth_info));
+        }
+        root
+    }
+}
+
+/// Writes the XML document, protecting values in document order.
+struct XmlWriter {
+    xml: String,
+    stream: InnerStream,
+    /// the attachments, referenced by their index.
+    binaries: Vec<SecretBytes>,
+}
+
+impl XmlWriter {
+    fn uuid() -> Result<String> {
+        Ok(base64::encode(random_bytes(16)?))
+    }
+
+    fn text(&mut self, name: &str, value: &str) {
+        self.xml += &format!("<{0}>{1}</{0}>", name, escape(value));
+    }
+
+    fn string(&mut self, key: &str, value: &str, protect: bool) {
+        self.xml += "<String>";
+        self.text("Key", key);
+        if protect {
+            let protected = self.stream.apply(&SecretBytes::from_slice(value.as_bytes()));
+            self.xml += &format!("<Value Protected=\"True\">{}</Value>", base64::encode(protected.expose()));
+        } else {
+            self.text("Value", value);
+        }
+        self.xml += "</String>";
+    }
+
+    fn entry(&mut self, uuid: &str, title: &str, auth: &AuthInfo) {
+        self.xml += "<Entry>";
+        self.text("UUID", uuid);
+        if !auth.tags.is_empty() {
+            self.text("Tags", &auth.tags.join(";"));
+        }
+        self.string("Title", title, false);
+        self.string("UserName", &auth.user, false);
+        self.string("Password", auth.password.expose(), true);
+        self.string("Notes", auth.other.expose(), true);
+        for (name, value) in &auth.fields {
+            let key = if name == "url" { "URL" } else { name.as_str() };
+            self.string(key, value.expose(), true);
+        }
+        for (name, data) in &auth.attachments {
+            self.xml += "<Binary>";
+            self.text("Key", name);
+            self.xml += &format!("<Value Ref=\"{}\"/></Binary>", self.binaries.len());
+            self.binaries.push(data.clone());
+        }
+        if !auth.history.is_empty() {
+            self.xml += "<History>";
+            for old in &auth.history {
+                self.entry(uuid, title, old);
+            }
+            self.xml += "</History>";
+        }
+        self.xml += "</Entry>";
+    }
+
+    fn group(&mut self, name: &str, group: &Group) -> Result<()> {
+        self.xml += "<Group>";
+        self.text("UUID", &XmlWriter::uuid()?);
+        self.text("Name", name);
+        for &(title, auth) in &group.entries {
+            self.entry(&XmlWriter::uuid()?, title, auth);
+        }
+        for (name, sub) in &group.groups {
+            self.group(name, sub)?;
+        }
+        self.xml += "</Group>";
+        Ok(())
+    }
+}
+
+/// the kdf of exported databases, the second recommended option of RFC 9106.
+fn default_kdf() -> Result<Kdf> {
+    Ok(Kdf::Argon2 {
+        algorithm: argon2::Algorithm::Argon2id,
+        salt: random_bytes(32)?,
+        memory: 64 * 1024 * 1024,
+        iterations: 3,
+        lanes: 4,
+        version: 0x13,
+    })
+}
+
+/// write `entries` as a KDBX 4 database protected by `password`.
+pub fn write(entries: &[Entry], password: &SecretString) -> Result<Vec<u8>> {
+    write_with_kdf(entries, password, &default_kdf()?)
+}
+
+fn write_with_kdf(entries: &[Entry], password: &SecretString, kdf: &Kdf) -> Result<Vec<u8>> {
+    let master_seed = random_bytes(32)?;
+    let iv = random_bytes(12)?;
+    let stream_key = SecretBytes::new(random_bytes(64)?);
+
+    let mut header = Vec::new();
+    put_u32(&mut header, SIGNATURE1);
+    put_u32(&mut header, SIGNATURE2);
+    put_u32(&mut header, 4 << 16);
+    put_field(&mut header, 2, CHACHA20_CIPHER);
+    put_field(&mut header, 3, &[1, 0, 0, 0]);
+    put_field(&mut header, 4, &master_seed);
+    put_field(&mut header, 7, &iv);
+    put_field(&mut header, 11, &write_kdf(kdf));
+    put_field(&mut header, 0, b"\r\n\r\n");
+
+    let mut writer = XmlWriter {
+        xml: String::from("<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>"),
+        stream: InnerStream::new(CHACHA20_STREAM, stream_key.expose())?,
+        binaries: Vec::new(),
+    };
+    writer.xml += "<KeePassFile><Meta><Generator>pwwr</Generator></Meta><Root>";
+    writer.group("Root", &Group::tree(entries))?;
+    writer.xml += "</Root></KeePassFile>";
+
+    let mut inner = Vec::new();
+    put_field(&mut inner, 1, &u64_le(u64::from(CHACHA20_STREAM))[..4]);
+    put_field(&mut inner, 2, stream_key.expose());
+    for data in &writer.binaries {
+        // the first byte holds flags, 1 asks to protect the data in memory.
+        let mut value = vec![1];
+        value.extend_from_slice(data.expose());
+        put_field(&mut inner, 3, &value);
+        secret::wipe(&mut value);
+    }
+    put_field(&mut inner, 0, &[]);
+    let inner = SecretBytes::new(inner);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(inner.expose())
+        .and_then(|_| encoder.write_all(writer.xml.as_bytes()))
+        .chain_err(|| "can't compress the database")?;
+    let content = SecretBytes::new(encoder.finish().chain_err(|| "can't compress the database")?);
+
+    let transformed = transform_key(kdf, password)?;
+    let key = sha256(&[&master_seed, transformed.expose()]);
+    let hmac_key = sha512(&[&master_seed, transformed.expose(), &[1]]);
+    let mut encrypted = vec![0; content.expose().len()];
+    ChaCha20::new(key.expose(), &iv).process(content.expose(), &mut encrypted);
+
+    let mut out = header.clone();
+    out.extend_from_slice(sha256(&[&header]).expose());
+    out.extend(hmac_sha256(block_key(hmac_key.expose(), u64::MAX).expose(), &[&header]));
+    let blocks = encrypted.chunks(1024 * 1024).chain(Some(&[][..]));
+    for (index, block) in blocks.enumerate() {
+        let index = index as u64;
+        let mut len = Vec::new();
+        put_u32(&mut len, block.len() as u32);
+        let key = block_key(hmac_key.expose(), index);
+        out.extend(hmac_sha256(key.expose(), &[&u64_le(index), &len, block]));
+        out.extend_from_slice(&len);
+        out.extend_from_slice(block);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use kdbx::*;
+    use serde_json;
+    use testutil::empty_store;
+
+    fn check(entries: Vec<Entry>) {
+        let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(vec!["github", "Work/Servers/db"], titles);
+
+        let github = &entries[0].auth_info;
+        assert_eq!("alice", github.user);
+        assert_eq!("s3cret", github.password.expose());
+        assert_eq!("memo & more", github.other.expose());
+        assert_eq!("https://github.com", github.fields["url"].expose());
+        assert_eq!("JBSWY3DP", github.fields["otp"].expose());
+        assert_eq!(vec!["work", "dev"], github.tags);
+        assert_eq!(b"hello attachment", github.attachments["notes.txt"].expose());
+        assert_eq!(1, github.history.len());
+        assert_eq!("old", github.history[0].password.expose());
+
+        assert_eq!("dbpw", entries[1].auth_info.password.expose());
+    }
+
+    #[test]
+    fn kdbx3_test() {
+        let data = include_bytes!("../testdata/keepass3.kdbx");
+        check(read(data, &SecretString::from("test")).unwrap());
+        assert!(read(data, &SecretString::from("wrong")).is_err());
+    }
+
+    #[test]
+    fn kdbx4_test() {
+        let data = include_bytes!("../testdata/keepass4.kdbx");
+        check(read(data, &SecretString::from("test")).unwrap());
+        assert!(read(data, &SecretString::from("wrong")).is_err());
+    }
+
+    /// cheap parameters, the defaults are too slow for tests.
+    fn test_kdf(algorithm: argon2::Algorithm) -> Kdf {
+        Kdf::Argon2 {
+            algorithm,
+            salt: vec![7; 32],
+            memory: 1024 * 1024,
+            iterations: 1,
+            lanes: 1,
+            version: 0x13,
+        }
+    }
+
+    #[test]
+    fn kdf_limits_test() {
+        let password = SecretString::from("test");
+        let aes = Kdf::Aes {
+            seed: vec![7; 32],
+            rounds: MAX_AES_ROUNDS + 1,
+        };
+        assert!(transform_key(&aes, &password).is_err());
+
+        let argon2 = |memory, iterations| Kdf::Argon2 {
+            algorithm: argon2::Algorithm::Argon2id,
+            salt: vec![7; 32],
+            memory,
+            iterations,
+            lanes: 1,
+            version: 0x13,
+        };
+        assert!(transform_key(&argon2((primitives::MAX_ARGON2_MEMORY + 1) * 1024, 1), &password).is_err());
+        assert!(transform_key(&argon2(1024 * 1024, primitives::MAX_ARGON2_ITERATIONS + 1), &password).is_err());
+        assert!(transform_key(&argon2(1024 * 1024, 1), &password).is_ok());
+    }
+
+    fn sorted_json(mut entries: Vec<Entry>) -> String {
+        entries.sort_by(|a, b| a.title.cmp(&b.title));
+        serde_json::to_string(&entries).unwrap()
+    }
+
+    #[test]
+    fn write_roundtrip_test() {
+        let password = SecretString::from("test");
+        let original = read(include_bytes!("../testdata/keepass4.kdbx"), &password).unwrap();
+        let mut store = empty_store();
+        for entry in original {
+            store.add(entry).unwrap();
+        }
+        let mut extra = AuthInfo::new("me", SecretString::from("<&>"), SecretString::default());
+        extra.fields.insert(String::from("pin"), SecretString::from("1234"));
+        store.add(Entry::new("Work/Mail/ünïcode", extra)).unwrap();
+
+        for &algorithm in &[argon2::Algorithm::Argon2id, argon2::Algorithm::Argon2d] {
+            let entries = store.search("");
+            let data = write_with_kdf(&entries, &password, &test_kdf(algorithm)).unwrap();
+            let imported = read(&data, &password).unwrap();
+            assert_eq!(sorted_json(entries), sorted_json(imported));
+            assert!(read(&data, &SecretString::from("wrong")).is_err());
+        }
+    }
+}
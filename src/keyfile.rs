@@ -0,0 +1,178 @@
+//! Keyfiles, read the way KeePass reads them so that its keyfiles open the
+//! vault too.
+//!
+//! An XML keyfile holds the key in `<Data>`, base64 in version 1.0 and hex
+//! with a check in version 2.0. Otherwise a file of 32 bytes is the key, a
+//! file of 64 hex digits is the hex of the key, and any other file is hashed
+//! with SHA-256.
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use rand::{OsRng, Rng};
+use base64;
+use primitives;
+use secret::{SecretBytes, SecretString};
+use errors::*;
+
+pub const KEY_LEN: usize = 32;
+
+/// the `<Data>` of an XML keyfile and its attributes.
+struct Xml {
+    version: String,
+    hash: Option<String>,
+    data: SecretString,
+}
+
+fn decode_hex(text: &str) -> Option<SecretBytes> {
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes = (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+        .collect();
+    Some(SecretBytes::new(bytes))
+}
+
+/// the keyfile as XML, `None` when it isn't a KeePass `<KeyFile>`.
+fn parse_xml(content: &[u8]) -> Option<Xml> {
+    let mut reader = Reader::from_reader(content);
+    let mut path: Vec<String> = Vec::new();
+    let mut xml = Xml {
+        version: String::new(),
+        hash: None,
+        data: SecretString::default(),
+    };
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                if path.is_empty() && name != "KeyFile" {
+                    return None;
+                }
+                if name == "Data" {
+                    for attr in e.attributes() {
+                        let attr = attr.ok()?;
+                        if attr.key.as_ref() == b"Hash" {
+                            xml.hash = Some(attr.unescape_value().ok()?.into_owned());
+                        }
+                    }
+                }
+                path.push(name);
+            }
+            Event::Text(e) => {
+                let text = e.unescape().ok()?;
+                match path.last().map(|s| s.as_str()) {
+                    Some("Version") => xml.version.push_str(text.trim()),
+                    Some("Data") => xml.data = SecretString::new(format!("{}{}", xml.data.expose(), text)),
+                    _ => {}
+                }
+            }
+            Event::End(_) => {
+                path.pop();
+            }
+            Event::Eof if !xml.version.is_empty() => return Some(xml),
+            Event::Eof => return None,
+            _ => {}
+        }
+    }
+}
+
+/// the check of a version 2.0 keyfile: the first 4 bytes of the SHA-256 of
+/// the key in hex.
+fn check(key: &[u8]) -> String {
+    primitives::sha256(&[key]).expose()[..4].iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// the key of a keyfile.
+pub fn read(content: &[u8]) -> Result<SecretBytes> {
+    if content.is_empty() {
+        bail!("the keyfile is empty");
+    }
+    if let Some(xml) = parse_xml(content) {
+        let data: String = xml.data.expose().chars().filter(|c| !c.is_whitespace()).collect();
+        let data = SecretString::new(data);
+        return match xml.version.as_str() {
+            v if v.starts_with("1.") => {
+                let key = base64::decode(data.expose()).chain_err(|| "invalid keyfile data")?;
+                Ok(SecretBytes::new(key))
+            }
+            v if v.starts_with("2.") => {
+                let key = decode_hex(data.expose()).chain_err(|| "invalid keyfile data")?;
+                match xml.hash {
+                    Some(ref hash) if !hash.eq_ignore_ascii_case(&check(key.expose())) => {
+                        bail!("the keyfile data doesn't match its hash")
+                    }
+                    _ => Ok(key),
+                }
+            }
+            v => bail!("unsupported keyfile version [{}]", v),
+        };
+    }
+    if content.len() == KEY_LEN {
+        return Ok(SecretBytes::from_slice(content));
+    }
+    if content.len() == 2 * KEY_LEN {
+        if let Some(key) = ::std::str::from_utf8(content).ok().and_then(decode_hex) {
+            return Ok(key);
+        }
+    }
+    Ok(primitives::sha256(&[content]))
+}
+
+/// a new random keyfile in the XML format of KeePass 2.
+pub fn generate() -> Result<SecretString> {
+    let mut rng = OsRng::new().chain_err(|| "can't open the OS random generator")?;
+    let mut key = SecretBytes::new(vec![0; KEY_LEN]);
+    rng.fill_bytes(key.expose_mut());
+
+    let hex: Vec<String> = key
+        .expose()
+        .chunks(4)
+        .map(|group| group.iter().map(|b| format!("{:02X}", b)).collect())
+        .collect();
+    let lines: Vec<String> = hex.chunks(4).map(|line| format!("\t\t\t{}", line.join(" "))).collect();
+    Ok(SecretString::new(format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <KeyFile>\n\
+         \t<Meta>\n\
+         \t\t<Version>2.0</Version>\n\
+         \t</Meta>\n\
+         \t<Key>\n\
+         \t\t<Data Hash=\"{}\">\n{}\n\t\t</Data>\n\
+         \t</Key>\n\
+         </KeyFile>\n",
+        check(key.expose()),
+        lines.join("\n")
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use keyfile::*;
+
+    #[test]
+    fn read_test() {
+        let generated = generate().unwrap();
+        let key = read(generated.expose().as_bytes()).unwrap();
+        assert_eq!(KEY_LEN, key.expose().len());
+        let tampered = generated.expose().replacen("<Data Hash=\"", "<Data Hash=\"0", 1);
+        assert!(read(tampered.as_bytes()).is_err());
+
+        let v2 = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<KeyFile><Meta><Version>2.0</Version></Meta>\
+                  <Key><Data Hash=\"630DCD29\">00010203 04050607 08090A0B 0C0D0E0F\n\
+                  10111213 14151617 18191A1B 1C1D1E1F</Data></Key></KeyFile>";
+        let expected: Vec<u8> = (0..32).collect();
+        assert_eq!(expected, read(v2.as_bytes()).unwrap().expose());
+        let v1 = format!(
+            "<KeyFile><Meta><Version>1.00</Version></Meta><Key><Data>{}</Data></Key></KeyFile>",
+            base64::encode(&expected)
+        );
+        assert_eq!(expected, read(v1.as_bytes()).unwrap().expose());
+
+        assert_eq!(expected, read(&expected).unwrap().expose());
+        let hex: String = expected.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(expected, read(hex.as_bytes()).unwrap().expose());
+        assert_eq!(primitives::sha256(&[b"any file"]).expose(), read(b"any file").unwrap().expose());
+        assert!(read(b"").is_err());
+    }
+}
@@ -0,0 +1,354 @@
+//! Key slots: a random data key encrypts the vault, and each slot wraps it
+//! under a key of its own secret, like the key slots of LUKS.
+//!
+//! A slot holds the Argon2id parameters deriving a key from its secret (a
+//! password, a keyfile, both or a recovery code), and the data key
+//! encrypted with ChaCha20-Poly1305 under that key. Any slot opens the vault,
+//! so secrets are added and removed without encrypting the vault again.
+use base64;
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use rand::{OsRng, Rng};
+use serde_json;
+use backup;
+use clock;
+use primitives;
+use pwdcrypto::KEY_LEN;
+use rawio::RawStore;
+use secret::{SecretBytes, SecretString};
+use errors::*;
+
+pub const FILE: &str = "keyslots";
+const FORMAT: u32 = 1;
+const TAG_LEN: usize = 16;
+/// recovery codes are random, a light KDF is enough for them.
+const RECOVERY_CODE_KDF: (u32, u32, u32) = (8 * 1024, 1, 1);
+/// base32 without padding, 26 characters hold 130 bits.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const CODE_LEN: usize = 26;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    Password,
+    Keyfile,
+    RecoveryCode,
+    /// both a password and a keyfile, neither opens it alone.
+    PasswordKeyfile,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Password => "password",
+            Kind::Keyfile => "keyfile",
+            Kind::RecoveryCode => "recovery-code",
+            Kind::PasswordKeyfile => "password-keyfile",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Kdf {
+    pub algorithm: String,
+    /// in KiB.
+    pub memory: u32,
+    pub iterations: u32,
+    pub lanes: u32,
+    pub salt: String,
+}
+
+impl Kdf {
+    fn params(&self) -> (u32, u32, u32) {
+        (self.memory, self.iterations, self.lanes)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Slot {
+    pub id: u32,
+    pub kind: Kind,
+    #[serde(default)]
+    pub label: String,
+    /// seconds since the unix epoch.
+    pub created: u64,
+    pub kdf: Kdf,
+    nonce: String,
+    wrapped: String,
+}
+
+impl Slot {
+    /// the slot id and kind are authenticated with the data key.
+    fn aad(&self) -> Vec<u8> {
+        format!("pwwr keyslot {} {}", self.id, self.kind.name()).into_bytes()
+    }
+
+    fn unwrap_key(&self, secret: &[u8]) -> Result<SecretBytes> {
+        if self.kdf.algorithm != "argon2id" {
+            bail!("unsupported kdf [{}]", self.kdf.algorithm);
+        }
+        let decode = |value: &str| base64::decode(value).chain_err(|| format!("invalid key slot {}", self.id));
+        let (salt, nonce, wrapped) = (decode(&self.kdf.salt)?, decode(&self.nonce)?, decode(&self.wrapped)?);
+        if nonce.len() != 8 || wrapped.len() != KEY_LEN + TAG_LEN {
+            bail!("invalid key slot {}", self.id);
+        }
+        let kek = primitives::argon2id(secret, &salt, self.kdf.params())?;
+        let (encrypted, tag) = wrapped.split_at(KEY_LEN);
+        let mut key = SecretBytes::new(vec![0; KEY_LEN]);
+        if !ChaCha20Poly1305::new(kek.expose(), &nonce, &self.aad()).decrypt(encrypted, key.expose_mut(), tag) {
+            bail!("the secret doesn't open key slot {}", self.id);
+        }
+        Ok(key)
+    }
+}
+
+/// The key slots of a vault.
+#[derive(Serialize, Deserialize, Default)]
+pub struct KeySlots {
+    format: u32,
+    slots: Vec<Slot>,
+    /// the Argon2id parameters of new slots, but recovery codes.
+    #[serde(skip)]
+    kdf: Option<(u32, u32, u32)>,
+}
+
+/// a random data key.
+pub fn new_data_key() -> Result<SecretBytes> {
+    let mut rng = OsRng::new().chain_err(|| "can't open the OS random generator")?;
+    let mut key = SecretBytes::new(vec![0; KEY_LEN]);
+    rng.fill_bytes(key.expose_mut());
+    Ok(key)
+}
+
+/// the secret of a password and keyfile slot: the key of the keyfile, which
+/// has a fixed length, then the password.
+pub fn with_keyfile(password: &[u8], keyfile_key: &[u8]) -> SecretBytes {
+    let mut secret = Vec::with_capacity(keyfile_key.len() + password.len());
+    secret.extend_from_slice(keyfile_key);
+    secret.extend_from_slice(password);
+    SecretBytes::new(secret)
+}
+
+/// a random recovery code, like `ABCD-EFGH-...`.
+pub fn new_recovery_code() -> Result<SecretString> {
+    let mut rng = OsRng::new().chain_err(|| "can't open the OS random generator")?;
+    let mut code = String::new();
+    for i in 0..CODE_LEN {
+        if i > 0 && i % 4 == 0 {
+            code.push('-');
+        }
+        code.push(CODE_ALPHABET[rng.gen_range(0, CODE_ALPHABET.len())] as char);
+    }
+    Ok(SecretString::new(code))
+}
+
+/// a recovery code as typed: any case, with or without dashes and spaces.
+pub fn normalize_code(typed: &str) -> SecretString {
+    SecretString::new(
+        typed
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .flat_map(|c| c.to_uppercase())
+            .collect(),
+    )
+}
+
+impl KeySlots {
+    pub fn load<RW: RawStore>(rw: &mut RW) -> Result<KeySlots> {
+        let mut content = Vec::new();
+        rw.read_to_end(&mut content)?;
+        if content.is_empty() {
+            return Ok(KeySlots::default());
+        }
+        let slots: KeySlots = serde_json::from_slice(&content).chain_err(|| "invalid key slots")?;
+        if slots.format != FORMAT {
+            bail!("unsupported key slot format {}", slots.format);
+        }
+        Ok(slots)
+    }
+
+    pub fn save<RW: RawStore>(&self, rw: &mut RW) -> Result<()> {
+        let content = serde_json::to_vec_pretty(self).chain_err(|| "can't write the key slots")?;
+        rw.write_all(&content)
+    }
+
+    pub fn set_kdf(&mut self, params: (u32, u32, u32)) {
+        self.kdf = Some(params);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn slots(&self) -> &[Slot] {
+        &self.slots
+    }
+
+    /// add a slot wrapping `data_key` under `secret` and return its id.
+    pub fn add(&mut self, kind: Kind, label: &str, secret: &[u8], data_key: &[u8]) -> Result<u32> {
+        let params = match kind {
+            Kind::RecoveryCode => RECOVERY_CODE_KDF,
+            _ => self.kdf.unwrap_or(backup::KDF_PARAMS),
+        };
+        self.add_with(kind, label, secret, data_key, params)
+    }
+
+    fn add_with(
+        &mut self,
+        kind: Kind,
+        label: &str,
+        secret: &[u8],
+        data_key: &[u8],
+        (memory, iterations, lanes): (u32, u32, u32),
+    ) -> Result<u32> {
+        let mut rng = OsRng::new().chain_err(|| "can't open the OS random generator")?;
+        let mut salt = [0; 32];
+        let mut nonce = [0; 8];
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+
+        let mut slot = Slot {
+            id: self.slots.iter().map(|s| s.id).max().unwrap_or(0) + 1,
+            kind,
+            label: String::from(label),
+            created: clock::now(),
+            kdf: Kdf {
+                algorithm: String::from("argon2id"),
+                memory,
+                iterations,
+                lanes,
+                salt: base64::encode(salt),
+            },
+            nonce: base64::encode(nonce),
+            wrapped: String::new(),
+        };
+        let kek = primitives::argon2id(secret, &salt, slot.kdf.params())?;
+        let mut wrapped = vec![0; data_key.len() + TAG_LEN];
+        {
+            let (encrypted, tag) = wrapped.split_at_mut(data_key.len());
+            ChaCha20Poly1305::new(kek.expose(), &nonce, &slot.aad()).encrypt(data_key, encrypted, tag);
+        }
+        slot.wrapped = base64::encode(&wrapped);
+        self.slots.push(slot);
+        self.format = FORMAT;
+        Ok(self.slots[self.slots.len() - 1].id)
+    }
+
+    /// remove slot `id`, unless it is the last one.
+    pub fn remove(&mut self, id: u32) -> Result<Slot> {
+        let index = match self.slots.iter().position(|s| s.id == id) {
+            Some(index) => index,
+            None => bail!("no key slot {}", id),
+        };
+        if self.slots.len() == 1 {
+            bail!("key slot {} is the last one, add another before removing it", id);
+        }
+        Ok(self.slots.remove(index))
+    }
+
+    /// replace every password slot, with or without a keyfile, with one of
+    /// `password` and of the keyfile of `keyfile_key` if given.
+    pub fn replace_passwords(&mut self, password: &[u8], keyfile_key: Option<&[u8]>, data_key: &[u8]) -> Result<u32> {
+        let id = match keyfile_key {
+            Some(keyfile_key) => {
+                let secret = with_keyfile(password, keyfile_key);
+                self.add(Kind::PasswordKeyfile, "master password and keyfile", secret.expose(), data_key)?
+            }
+            None => self.add(Kind::Password, "master password", password, data_key)?,
+        };
+        self.slots
+            .retain(|s| (s.kind != Kind::Password && s.kind != Kind::PasswordKeyfile) || s.id == id);
+        Ok(id)
+    }
+
+    /// replace every password slot with one of `password` and the keyfile of
+    /// `keyfile_key`, so that the password alone no longer opens the vault.
+    pub fn require_keyfile(&mut self, password: &[u8], keyfile_key: &[u8], data_key: &[u8]) -> Result<u32> {
+        let secret = with_keyfile(password, keyfile_key);
+        let id = self.add(Kind::PasswordKeyfile, "master password and keyfile", secret.expose(), data_key)?;
+        self.slots.retain(|s| s.kind != Kind::Password);
+        Ok(id)
+    }
+
+    pub fn has(&self, kind: Kind) -> bool {
+        self.slots.iter().any(|s| s.kind == kind)
+    }
+
+    /// the data key of the first slot of one of `kinds` that `secret` opens.
+    pub fn open(&self, kinds: &[Kind], secret: &[u8]) -> Result<SecretBytes> {
+        for slot in self.slots.iter().filter(|s| kinds.contains(&s.kind)) {
+            if let Ok(key) = slot.unwrap_key(secret) {
+                return Ok(key);
+            }
+        }
+        let names: Vec<&str> = kinds.iter().map(|k| k.name()).collect();
+        bail!("no {} key slot opens the vault", names.join(" or "))
+    }
+
+    /// the data key of a typed password or recovery code.
+    pub fn open_typed(&self, typed: &[u8]) -> Result<SecretBytes> {
+        self.open(&[Kind::Password], typed).or_else(|_| {
+            let code = normalize_code(&String::from_utf8_lossy(typed));
+            self.open(&[Kind::RecoveryCode], code.expose().as_bytes())
+                .chain_err(|| "wrong password.")
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use keyslot::*;
+    use testutil::MemoryStore;
+
+    const TEST_KDF: (u32, u32, u32) = (64, 1, 1);
+
+    #[test]
+    fn slots_test() {
+        let data_key = new_data_key().unwrap();
+        let mut slots = KeySlots::default();
+        let password = slots.add_with(Kind::Password, "", b"master", data_key.expose(), TEST_KDF).unwrap();
+        let code = new_recovery_code().unwrap();
+        let normalized = normalize_code(code.expose());
+        assert_eq!(CODE_LEN, normalized.expose().len());
+        slots
+            .add_with(Kind::RecoveryCode, "safe", normalized.expose().as_bytes(), data_key.expose(), TEST_KDF)
+            .unwrap();
+        slots.add_with(Kind::Keyfile, "", b"key file", data_key.expose(), TEST_KDF).unwrap();
+
+        let mut store = MemoryStore::default();
+        slots.save(&mut store).unwrap();
+        let mut slots = KeySlots::load(&mut store).unwrap();
+        assert_eq!(vec![1, 2, 3], slots.slots().iter().map(|s| s.id).collect::<Vec<_>>());
+
+        assert_eq!(data_key.expose(), slots.open_typed(b"master").unwrap().expose());
+        let typed = code.expose().to_lowercase().replace('-', " ");
+        assert_eq!(data_key.expose(), slots.open_typed(typed.as_bytes()).unwrap().expose());
+        assert_eq!(data_key.expose(), slots.open(&[Kind::Keyfile], b"key file").unwrap().expose());
+        assert!(slots.open_typed(b"wrong").is_err());
+        assert!(slots.open_typed(b"key file").is_err());
+
+        // the id is authenticated.
+        slots.slots[0].id = 9;
+        assert!(slots.open_typed(b"master").is_err());
+        slots.slots[0].id = password;
+
+        slots.remove(2).unwrap();
+        slots.remove(3).unwrap();
+        assert!(slots.remove(password).is_err());
+
+        // a keyfile as a second factor replaces the password slot.
+        let both = slots
+            .add_with(Kind::PasswordKeyfile, "", with_keyfile(b"master", b"key").expose(), data_key.expose(), TEST_KDF)
+            .unwrap();
+        slots.slots.retain(|s| s.kind != Kind::Password);
+        assert!(slots.open_typed(b"master").is_err());
+        assert!(slots.open(&[Kind::PasswordKeyfile], b"master").is_err());
+        let secret = with_keyfile(b"master", b"key");
+        assert_eq!(data_key.expose(), slots.open(&[Kind::PasswordKeyfile], secret.expose()).unwrap().expose());
+        assert!(slots.has(Kind::PasswordKeyfile) && !slots.has(Kind::Password));
+        assert!(slots.remove(both).is_err());
+        assert!(slots.remove(7).is_err());
+        assert_eq!(vec![both], slots.slots().iter().map(|s| s.id).collect::<Vec<_>>());
+        assert!(KeySlots::load(&mut MemoryStore::default()).unwrap().is_empty());
+    }
+}
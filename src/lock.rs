@@ -0,0 +1,107 @@
+//! An advisory lock on a vault, held from loading the entries to saving
+//! them, so that two processes don't both change the vault and drop the
+//! change of the first.
+//!
+//! The lock is a `flock` of `lock` in the directory of the vault, which
+//! holds the PID of its holder. Filesystems without `flock` go unlocked, and
+//! `FileRawStore` still refuses to save over content changed since it was
+//! read.
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+use libc;
+use errors::*;
+
+pub const FILE: &str = "lock";
+const POLL: Duration = Duration::from_millis(100);
+
+/// The held lock of a vault, released when dropped.
+pub struct VaultLock {
+    file: File,
+    held: bool,
+}
+
+impl VaultLock {
+    /// lock the vault in `dir`, waiting up to `timeout` for another holder.
+    pub fn acquire(dir: &Path, timeout: Duration) -> Result<VaultLock> {
+        let path = dir.join(FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&path)
+            .chain_err(|| format!("can't open [{}]", path.display()))?;
+        let start = Instant::now();
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+                break;
+            }
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                Some(libc::EWOULDBLOCK) if start.elapsed() < timeout => thread::sleep(POLL),
+                Some(libc::EWOULDBLOCK) => {
+                    let mut holder = String::new();
+                    let _ = file.read_to_string(&mut holder);
+                    let holder = match holder.trim().parse::<u32>() {
+                        Ok(pid) => format!("PID {}", pid),
+                        Err(_) => String::from("another process"),
+                    };
+                    bail!(ErrorKind::VaultBusy(holder, timeout.as_secs()));
+                }
+                Some(libc::ENOLCK) | Some(libc::EOPNOTSUPP) => return Ok(VaultLock { file, held: false }),
+                _ => return Err(error).chain_err(|| format!("can't lock [{}]", path.display())),
+            }
+        }
+
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| writeln!(file, "{}", process::id()))
+            .chain_err(|| format!("can't write [{}]", path.display()))?;
+        Ok(VaultLock { file, held: true })
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        if self.held {
+            let _ = self.file.set_len(0);
+            unsafe {
+                libc::flock(self.file.as_raw_fd(), libc::LOCK_UN);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lock::*;
+    use std::fs;
+    use testutil::TempDir;
+
+    #[test]
+    fn lock_test() {
+        let dir = TempDir::new("lock");
+
+        let lock = VaultLock::acquire(&dir, Duration::from_secs(0)).unwrap();
+        assert_eq!(format!("{}\n", process::id()), fs::read_to_string(dir.join(FILE)).unwrap());
+        let start = Instant::now();
+        match VaultLock::acquire(&dir, Duration::from_millis(300)) {
+            Err(Error(ErrorKind::VaultBusy(ref holder, _), _)) => {
+                assert_eq!(format!("PID {}", process::id()), *holder)
+            }
+            _ => panic!("the vault should be busy"),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        drop(lock);
+        assert_eq!("", fs::read_to_string(dir.join(FILE)).unwrap());
+        VaultLock::acquire(&dir, Duration::from_secs(0)).unwrap();
+    }
+}
@@ -1,10 +1,23 @@
+extern crate argon2;
+extern crate base64;
+extern crate bip39;
 extern crate clap;
 extern crate crypto;
+extern crate csv;
+extern crate flate2;
+extern crate libc;
+extern crate p256;
+extern crate p384;
+extern crate quick_xml;
 extern crate rand;
 extern crate rpassword;
+extern crate rsa;
 
 extern crate serde;
 extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
+extern crate zip;
 
 #[macro_use]
 extern crate serde_derive;
@@ -13,40 +26,211 @@ extern crate serde_derive;
 extern crate error_chain;
 
 use std::env;
-use std::fs::DirBuilder;
-use std::path::Path;
-use clap::{App, Arg, SubCommand};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{stderr, stdin, stdout, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use agent::{Agent, AgentClient, SessionCipher};
 use commands::Command;
-use entrystore::{CliEntrySelector, EntryStore};
-use pwdcrypto::{AesCipher, KeyProvider};
-use rawio::FileRawStore;
+use csvfile::ColumnMap;
+use import::{ConflictPolicy, ImportReport, Item};
+use entrystore::{BatchEntrySelector, Cipher, CliEntrySelector, Entry, EntrySelector, EntryStore};
+use pwdcrypto::{AesCipher, KeyInput, KeyProvider};
+use recovery::Sheet;
+use shamir::Share;
+use keyslot::{KeySlots, Kind};
+use vault::{KdfParams, Settings, Vaults};
+use config::Config;
+use output::Format;
+use rawio::{FileRawStore, RawStore};
+use lock::VaultLock;
+use audit::AuditLog;
+use sync::{Repo, State};
+use rotation::Rotation;
+use secret::{SecretBytes, SecretString};
+use sshagent::SshAgent;
 use errors::*;
 
 mod errors {
-    error_chain!{}
+    error_chain!{
+        errors {
+            AmbiguousEntry(titles: Vec<String>) {
+                description("several entries match")
+                display("several entries match: {}", titles.join(", "))
+            }
+            EntryNotFound(title: String) {
+                description("no entry matches")
+                display("No entry matches [{}].", title)
+            }
+            VaultLocked {
+                description("the vault is locked")
+                display("the vault is locked. Start `pwwr agent` to use it without input.")
+            }
+            VaultBusy(holder: String, waited: u64) {
+                description("another process is changing the vault")
+                display("the vault is locked by {} (waited {} seconds), try again once it is done.", holder, waited)
+            }
+            VaultChanged {
+                description("the vault changed since it was read")
+                display("the vault changed on disk since it was read, nothing was saved. Run the command again.")
+            }
+        }
+    }
 }
 
 mod pwdcrypto;
 mod entrystore;
 mod commands;
 mod rawio;
+mod agent;
+mod secret;
+mod generator;
+mod output;
+mod secretref;
+mod run;
+mod template;
+mod gitcred;
+mod sshkey;
+mod sshagent;
+mod import;
+mod kdbx;
+mod csvfile;
+mod passstore;
+mod bitwarden;
+mod onepassword;
+mod backup;
+mod clock;
+mod primitives;
+mod rotation;
+mod recovery;
+mod shamir;
+mod keyslot;
+mod keyfile;
+mod vault;
+mod config;
+mod lock;
+mod audit;
+mod sync;
+#[cfg(test)]
+mod testutil;
 
-const PWWR_DIR: &'static str = ".pwwr";
-const ENTRY_FILE: &'static str = "entries";
-const USER_FILE: &'static str = "user";
+const ENTRY_FILE: &str = "entries";
+const USER_FILE: &str = "user";
+const KEYFILE_ENV: &str = "PWWR_KEYFILE";
+/// the files of a vault shared by `pwwr sync`.
+const SYNC_FILES: &[&str] = &[ENTRY_FILE, USER_FILE, keyslot::FILE, vault::SETTINGS_FILE];
 
-fn make_command() -> Result<Command<CliEntrySelector, FileRawStore, AesCipher>> {
-    let home_dir = env::home_dir().chain_err(|| "Can't find home directory.")?;
-    let pwwr_path = Path::new(&home_dir).join(PWWR_DIR);
+/// the data directory, `~/.pwwr` unless configured: the default vault and
+/// the directory of the others.
+fn pwwr_root(config: &Config) -> Result<PathBuf> {
+    let pwwr_path = config.data_dir()?;
     if !pwwr_path.exists() {
         DirBuilder::new()
-            .create(pwwr_path.clone())
+            .recursive(true)
+            .mode(0o700)
+            .create(&pwwr_path)
             .chain_err(|| "Can't create pwwr directory.")?;
     }
+    Ok(pwwr_path)
+}
+
+fn vaults() -> Result<Vaults> {
+    let config = Config::load()?;
+    Ok(Vaults::new(pwwr_root(&config)?, config.default_vault))
+}
+
+/// the directory of the vault of `--vault`, `PWWR_VAULT` or the default one.
+fn pwwr_dir() -> Result<PathBuf> {
+    let vaults = vaults()?;
+    vaults.open(&vaults.selected())
+}
+
+/// the key slots of the vault, empty before they were introduced.
+fn key_slots(pwwr_path: &Path) -> Result<KeySlots> {
+    let mut slots = KeySlots::load(&mut FileRawStore::new(pwwr_path.join(keyslot::FILE))?)?;
+    slots.set_kdf(Settings::load(pwwr_path)?.kdf(Config::load()?.kdf()).tuple());
+    Ok(slots)
+}
 
-    let entries_path = pwwr_path.join(ENTRY_FILE);
-    let entries_file = FileRawStore::new(entries_path).chain_err(|| "entries")?;
+fn save_key_slots(pwwr_path: &Path, slots: &KeySlots) -> Result<()> {
+    slots.save(&mut synced(FileRawStore::new(pwwr_path.join(keyslot::FILE))?, pwwr_path))
+}
 
+/// `store` committing each write when the vault is synced.
+fn synced(store: FileRawStore, pwwr_path: &Path) -> FileRawStore {
+    match Repo::open(pwwr_path) {
+        Some(repo) => store.with_sync(repo),
+        None => store,
+    }
+}
+
+/// the key of a keyfile.
+fn read_keyfile(path: &Path) -> Result<SecretBytes> {
+    let content = SecretBytes::new(fs::read(path).chain_err(|| format!("can't read [{}]", path.display()))?);
+    keyfile::read(content.expose()).chain_err(|| format!("can't read the keyfile [{}]", path.display()))
+}
+
+/// the keyfile of `--keyfile` or `PWWR_KEYFILE`.
+fn keyfile_path() -> Option<PathBuf> {
+    env::var_os(KEYFILE_ENV).map(PathBuf::from)
+}
+
+/// write a new random keyfile, which never replaces a file.
+fn generate_keyfile(path: &str) -> Result<()> {
+    if Path::new(path).exists() {
+        bail!("[{}] exists, it is not replaced by a new keyfile", path);
+    }
+    check_private_path(path)?;
+    create_private_file(path)?
+        .write_all(keyfile::generate()?.expose().as_bytes())
+        .chain_err(|| format!("can't write [{}]", path))
+}
+
+/// open the data key with the keyfile of `--keyfile` alone or with a typed
+/// password, or else with a typed password or recovery code.
+fn unlock(pwwr_path: &Path) -> Result<AesCipher> {
+    let slots = key_slots(pwwr_path)?;
+    if slots.is_empty() {
+        return upgrade(pwwr_path);
+    }
+    secret::disable_core_dumps()?;
+    let keyfile_key = match keyfile_path() {
+        Some(path) => Some(read_keyfile(&path)?),
+        None => None,
+    };
+    if let Some(ref key) = keyfile_key {
+        if let Ok(data_key) = slots.open(&[Kind::Keyfile], key.expose()) {
+            return Ok(AesCipher::from_key(data_key));
+        }
+    }
+
+    let input = CliEntrySelector::new();
+    let password = input.get_password()?;
+    let data_key = match keyfile_key {
+        Some(ref key) => slots
+            .open(&[Kind::PasswordKeyfile], keyslot::with_keyfile(password.expose(), key.expose()).expose())
+            .or_else(|_| slots.open_typed(password.expose())),
+        None if slots.has(Kind::PasswordKeyfile) => slots
+            .open_typed(password.expose())
+            .chain_err(|| format!("the vault may need a keyfile, give it with --keyfile or {}", KEYFILE_ENV)),
+        None => slots.open_typed(password.expose()),
+    };
+    let cipher = AesCipher::from_key(data_key?);
+    // an upgrade stopped before the vault was encrypted with the data key.
+    if check_key(pwwr_path, &cipher).is_err() {
+        let legacy = AesCipher::new(password.expose(), &pwdcrypto::salt(vault_user(pwwr_path)?.as_bytes()));
+        check_key(pwwr_path, &legacy)?;
+        rekey(pwwr_path, legacy, &cipher)?;
+    }
+    Ok(cipher)
+}
+
+/// open a vault without key slots, whose key is derived from the master
+/// password, and encrypt it with a random data key in a password slot.
+fn upgrade(pwwr_path: &Path) -> Result<AesCipher> {
     let user_path = pwwr_path.join(USER_FILE);
     let mut user_file = FileRawStore::new(user_path).chain_err(|| "user")?;
 
@@ -54,47 +238,1773 @@ fn make_command() -> Result<Command<CliEntrySelector, FileRawStore, AesCipher>>
 
     let mut key_provider = KeyProvider::new(&input, &mut user_file);
     let (password, salt) = key_provider.get()?;
-    let cipher = AesCipher::new(&password, &salt);
+    secret::disable_core_dumps()?;
+    let legacy = AesCipher::new(password.expose(), &salt);
+    check_key(pwwr_path, &legacy)?;
+
+    let data_key = keyslot::new_data_key()?;
+    let mut slots = KeySlots::default();
+    slots.add(Kind::Password, "master password", password.expose(), data_key.expose())?;
+    // the slots first: an interrupted upgrade is finished by the next unlock.
+    save_key_slots(pwwr_path, &slots)?;
+    let cipher = AesCipher::from_key(data_key);
+    rekey(pwwr_path, legacy, &cipher)?;
+    eprintln!("the vault now has key slots, recovery sheets and shares made before don't open it.");
+    Ok(cipher)
+}
+
+/// encrypt the vault opened by `from` with the key of `to`.
+fn rekey(pwwr_path: &Path, from: AesCipher, to: &AesCipher) -> Result<()> {
+    let mut store = EntryStore::load(entries_file(pwwr_path)?, from)?;
+    store.set_cipher(AesCipher::from_key(SecretBytes::from_slice(to.key().expose())));
+    store.save()
+}
+
+/// the copies of the vault kept before each save.
+fn rotation(pwwr_path: &Path) -> Result<Rotation> {
+    let retention = Settings::load(pwwr_path)?.retention().with_env()?;
+    Ok(Rotation::new(pwwr_path.join(rotation::DIR), retention))
+}
+
+fn lock_vault(pwwr_path: &Path) -> Result<VaultLock> {
+    let timeout = Duration::from_secs(Config::load()?.lock_timeout());
+    VaultLock::acquire(pwwr_path, timeout)
+}
+
+/// the entries of the vault, locked until the store is dropped.
+fn entries_file(pwwr_path: &Path) -> Result<FileRawStore> {
+    let lock = lock_vault(pwwr_path)?;
+    let store = FileRawStore::new(pwwr_path.join(ENTRY_FILE))
+        .chain_err(|| "entries")?
+        .with_lock(lock)
+        .with_rotation(rotation(pwwr_path)?);
+    Ok(synced(store, pwwr_path))
+}
+
+/// open the vault. Without `interactive` the master password is never
+/// prompted, so the vault must be unlocked by the agent.
+fn make_command<T: EntrySelector>(
+    input: T,
+    interactive: bool,
+) -> Result<Command<T, FileRawStore, SessionCipher>> {
+    let pwwr_path = pwwr_dir()?;
+
+    let cipher = match AgentClient::connect(&agent::socket_path(&pwwr_path)) {
+        Some(client) => SessionCipher::Agent(client),
+        None if interactive => SessionCipher::Local(unlock(&pwwr_path)?),
+        None => bail!(ErrorKind::VaultLocked),
+    };
+
+    // the decrypted entries live in this process, whichever path unlocked them.
+    secret::disable_core_dumps()?;
+    // opened after the unlock, which may replace the file.
+    let entry_store =
+        EntryStore::load(entries_file(&pwwr_path)?, cipher)?.with_audit(AuditLog::new(pwwr_path.join(audit::FILE)));
+    Ok(Command::new(input, entry_store))
+}
+
+fn entry_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("user")
+            .long("user")
+            .takes_value(true)
+            .help("set the user without prompting"),
+        Arg::with_name("password_stdin")
+            .long("password-stdin")
+            .conflicts_with("generate")
+            .help("read the password from stdin without prompting"),
+        Arg::with_name("field")
+            .long("field")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("NAME=VALUE")
+            .help("set a field such as other=... without prompting"),
+        Arg::with_name("field_file")
+            .long("field-file")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("NAME=PATH")
+            .help("set a field to the content of a file, such as ssh_key=~/.ssh/id_ed25519"),
+        Arg::with_name("generate")
+            .long("generate")
+            .help("set a random password without prompting"),
+        Arg::with_name("length")
+            .long("length")
+            .takes_value(true)
+            .requires("generate")
+            .help("length of the generated password"),
+        Arg::with_name("no_input")
+            .long("no-input")
+            .help("never prompt, fail instead"),
+    ]
+}
+
+/// build the selector for scripts if any of the non-interactive flags is given.
+fn batch_selector(matches: &ArgMatches) -> Result<Option<BatchEntrySelector>> {
+    let batch = ["user", "password_stdin", "field", "field_file", "generate", "no_input"]
+        .iter()
+        .any(|name| matches.is_present(name));
+    if !batch {
+        return Ok(None);
+    }
+
+    let password = if matches.is_present("password_stdin") {
+        let mut value = String::new();
+        stdin()
+            .read_to_string(&mut value)
+            .chain_err(|| "can't read the password from stdin")?;
+        let value = SecretString::new(value);
+        let trimmed = value.expose().trim_end_matches(&['\n', '\r'][..]);
+        Some(SecretString::from(trimmed))
+    } else if matches.is_present("generate") {
+        let config = Config::load()?;
+        let length = match matches.value_of("length") {
+            Some(v) => v
+                .parse::<usize>()
+                .chain_err(|| format!("invalid length [{}]", v))?,
+            None => config.generator_length(),
+        };
+        Some(generator::generate(length, config.generator_symbols())?)
+    } else {
+        None
+    };
+
+    let mut fields = Vec::new();
+    for field in matches.values_of("field").into_iter().flatten() {
+        match field.find('=') {
+            Some(i) => fields.push((String::from(&field[..i]), SecretString::from(&field[i + 1..]))),
+            None => bail!("invalid field [{}], expected NAME=VALUE", field),
+        }
+    }
+    for field in matches.values_of("field_file").into_iter().flatten() {
+        match field.find('=') {
+            Some(i) => {
+                let path = &field[i + 1..];
+                let value = fs::read_to_string(path).chain_err(|| format!("can't read [{}]", path))?;
+                fields.push((String::from(&field[..i]), SecretString::new(value)));
+            }
+            None => bail!("invalid field [{}], expected NAME=PATH", field),
+        }
+    }
+
+    let user = matches.value_of("user").map(String::from);
+    Ok(Some(BatchEntrySelector::new(user, password, fields)))
+}
+
+/// fail unless `cipher` opens the vault, when there is one.
+fn check_key(pwwr_path: &Path, cipher: &AesCipher) -> Result<()> {
+    let mut content = Vec::new();
+    FileRawStore::new(pwwr_path.join(ENTRY_FILE))?.read_to_end(&mut content)?;
+    if !content.is_empty() {
+        let plain = cipher.decrypt(&content).chain_err(|| "wrong password.")?;
+        ::std::str::from_utf8(plain.expose()).chain_err(|| "wrong password.")?;
+    }
+    Ok(())
+}
+
+fn start_agent(idle_timeout: u64, max_lifetime: u64, foreground: bool) -> Result<()> {
+    let pwwr_path = pwwr_dir()?;
+    let cipher = unlock(&pwwr_path)?;
+    check_key(&pwwr_path, &cipher)?;
+    serve_agent(&pwwr_path, cipher, idle_timeout, max_lifetime, foreground)
+}
+
+/// serve `cipher`, a key known to open the vault, from an agent.
+fn serve_agent(
+    pwwr_path: &Path,
+    cipher: AesCipher,
+    idle_timeout: u64,
+    max_lifetime: u64,
+    foreground: bool,
+) -> Result<()> {
+    let socket_path = agent::socket_path(pwwr_path);
+    let listener = Agent::bind(&socket_path)?;
+    if !foreground {
+        if let Some(pid) = agent::daemonize()? {
+            println!("pwwr agent started (pid {}).", pid);
+            return Ok(());
+        }
+    }
+    Agent::new(cipher, idle_timeout, max_lifetime).serve(listener, &socket_path)
+}
+
+fn start_ssh_agent(confirm: bool, idle_timeout: u64, max_lifetime: u64, foreground: bool) -> Result<()> {
+    let pwwr_path = pwwr_dir()?;
+    let command = make_command(BatchEntrySelector::new(None, None, Vec::new()), true)?;
+    let keys = sshagent::load_keys(&command.list_entries(""));
+    drop(command);
+    secret::disable_core_dumps()?;
+
+    let socket_path = sshagent::socket_path(&pwwr_path);
+    let listener = Agent::bind(&socket_path)?;
+    // the output of ssh-agent, so `eval $(pwwr ssh-agent)` works.
+    println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket_path.display());
+    if !foreground {
+        if let Some(pid) = agent::daemonize()? {
+            println!("echo pwwr ssh-agent pid {} serving {} keys;", pid, keys.len());
+            return Ok(());
+        }
+    }
+    SshAgent::new(keys, confirm, idle_timeout, max_lifetime).serve(listener, &socket_path)
+}
+
+/// lock the agent and the ssh-agent of the vault.
+fn lock_agent() -> Result<()> {
+    let pwwr_path = pwwr_dir()?;
+    let locked = match AgentClient::connect(&agent::socket_path(&pwwr_path)) {
+        Some(client) => client.lock().map(|_| true)?,
+        None => false,
+    };
+    if !sshagent::lock(&sshagent::socket_path(&pwwr_path))? && !locked {
+        println!("No agent is running.");
+    }
+    Ok(())
+}
+
+/// resolve every `--env NAME=TITLE:FIELD` before anything is started.
+fn resolve_env(matches: &ArgMatches) -> Result<Vec<(String, SecretString)>> {
+    let mut refs = Vec::new();
+    for env in matches.values_of("env").into_iter().flatten() {
+        match env.find('=') {
+            Some(i) if i > 0 => refs.push((String::from(&env[..i]), &env[i + 1..])),
+            _ => bail!("invalid env [{}], expected NAME=TITLE:FIELD", env),
+        }
+    }
+    if refs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let input = BatchEntrySelector::new(None, None, Vec::new());
+    let command = make_command(input, true)?;
+    let mut env = Vec::new();
+    for (name, reference) in refs {
+        let value = command.resolve(&command.parse_ref(reference)?)?;
+        env.push((name, value));
+    }
+    Ok(env)
+}
+
+/// create or truncate a file only the user can read.
+fn create_private_file(path: &str) -> Result<fs::File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .chain_err(|| format!("can't open [{}]", path))?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))
+        .chain_err(|| format!("can't set the permissions of [{}]", path))?;
+    Ok(file)
+}
+
+fn inject(input: &str, output: Option<&str>, dry_run: bool) -> Result<()> {
+    let source = fs::read_to_string(input).chain_err(|| format!("can't read [{}]", input))?;
+    let segments = template::parse(&source)?;
+    let command = make_command(BatchEntrySelector::new(None, None, Vec::new()), true)?;
 
-    let entry_store = EntryStore::load(entries_file, cipher)?;
-    Ok(Command::new(CliEntrySelector::new(), entry_store))
+    if dry_run {
+        for (reference, line) in template::references(&segments) {
+            command.resolve(reference)?;
+            let title = command.lookup(reference)?.title;
+            println!("{}:{}: {} -> {}", input, line, reference, title);
+        }
+        return Ok(());
+    }
+
+    let rendered = template::render(&segments, |reference| command.resolve(reference))?;
+    match output {
+        Some(path) => {
+            check_private_path(path)?;
+            let mut file = create_private_file(path)?;
+            file.write_all(rendered.expose().as_bytes())
+                .chain_err(|| format!("can't write [{}]", path))
+        }
+        None => stdout()
+            .write_all(rendered.expose().as_bytes())
+            .chain_err(|| "can't write to stdout."),
+    }
+}
+
+/// read the password of an imported or exported file, from stdin if asked to.
+fn file_password(prompt: &str, from_stdin: bool) -> Result<SecretString> {
+    if from_stdin {
+        let mut value = String::new();
+        stdin()
+            .read_to_string(&mut value)
+            .chain_err(|| "can't read the password from stdin")?;
+        let value = SecretString::new(value);
+        return Ok(SecretString::from(value.expose().trim_end_matches(&['\n', '\r'][..])));
+    }
+    let password = rpassword::prompt_password_stderr(prompt)
+        .chain_err(|| "failed to reading a password")?;
+    Ok(SecretString::new(password))
+}
+
+/// the column map of a CSV file, from `--map` or `--preset`.
+fn column_map(matches: &ArgMatches) -> Result<ColumnMap> {
+    match (matches.value_of("map"), matches.value_of("preset")) {
+        (Some(spec), _) => ColumnMap::parse(spec),
+        (None, Some(preset)) => ColumnMap::preset(preset),
+        (None, None) => bail!("CSV files need --preset or --map."),
+    }
+}
+
+/// print what an import would add and whether each title is taken.
+fn preview<T: EntrySelector>(
+    command: &Command<T, FileRawStore, SessionCipher>,
+    entries: &[Entry],
+) {
+    let taken = command.taken_titles(entries);
+    for (entry, &taken) in entries.iter().zip(&taken) {
+        let state = if taken { "taken" } else { "new" };
+        println!("{:<6}{} ({})", state, entry.title, entry.auth_info.user);
+    }
+    let count = taken.iter().filter(|&&t| t).count();
+    println!("{} rows, {} titles taken.", entries.len(), count);
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    stdout().flush().chain_err(|| "can't write to stdout.")?;
+    let mut answer = String::new();
+    stdin()
+        .read_line(&mut answer)
+        .chain_err(|| "can't read the answer")?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+/// read the password of a new file, typed twice unless it comes from stdin.
+fn new_file_password(prompt: &str, from_stdin: bool) -> Result<SecretString> {
+    let password = file_password(prompt, from_stdin)?;
+    if !from_stdin && file_password("retype it: ", false)?.expose() != password.expose() {
+        bail!("the passwords don't match");
+    }
+    Ok(password)
+}
+
+fn import(matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("file").unwrap_or("");
+    let policy = ConflictPolicy::parse(matches.value_of("on_conflict").unwrap_or("fail"))?;
+    let from = matches.value_of("from").unwrap_or("");
+    let entries = match from {
+        "kdbx" => {
+            let data = fs::read(path).chain_err(|| format!("can't read [{}]", path))?;
+            let from_stdin = matches.is_present("password_stdin");
+            let password = file_password("password of the imported file: ", from_stdin)?;
+            kdbx::read(&data, &password).chain_err(|| format!("can't read [{}]", path))?
+        }
+        "bitwarden" => {
+            let data = fs::read(path).chain_err(|| format!("can't read [{}]", path))?;
+            let from_stdin = matches.is_present("password_stdin");
+            let password = || file_password("password of the imported file: ", from_stdin);
+            let items = bitwarden::read(&data, password).chain_err(|| format!("can't read [{}]", path))?;
+            items.into_iter().map(Item::into_entry).collect()
+        }
+        "1pux" => {
+            let data = fs::read(path).chain_err(|| format!("can't read [{}]", path))?;
+            let items = onepassword::read(&data).chain_err(|| format!("can't read [{}]", path))?;
+            items.into_iter().map(Item::into_entry).collect()
+        }
+        "pass" => {
+            let command = matches
+                .value_of("decrypt_command")
+                .unwrap_or(passstore::DEFAULT_DECRYPT_COMMAND);
+            passstore::read(Path::new(path), command)?
+        }
+        "csv" => {
+            let map = column_map(matches)?;
+            let file = fs::File::open(path).chain_err(|| format!("can't read [{}]", path))?;
+            csvfile::read(file, &map).chain_err(|| format!("can't read [{}]", path))?
+        }
+        from => bail!("unknown import format [{}]", from),
+    };
+
+    let input = BatchEntrySelector::new(None, None, Vec::new());
+    let mut command = make_command(input, true)?;
+    // CSV rows are easily mapped wrong, so they are shown before importing.
+    let dry_run = matches.is_present("dry_run");
+    if dry_run || from == "csv" {
+        preview(&command, &entries);
+    }
+    if dry_run {
+        return Ok(());
+    }
+    if from == "csv" && !matches.is_present("yes") && !confirm("import these entries?")? {
+        bail!("import cancelled");
+    }
+
+    let report = command.import_entries(entries, policy)?;
+    print_report(&report);
+    Ok(())
+}
+
+fn print_report(report: &ImportReport) {
+    for (from, to) in &report.renamed {
+        println!("renamed: {} -> {}", from, to);
+    }
+    for title in &report.skipped {
+        println!("skipped: {}", title);
+    }
+    for title in &report.duplicates {
+        println!("duplicate: {}", title);
+    }
+    println!("{}", report.summary());
+}
+
+/// refuse to export in plain text where others could read the file: over a
+/// file readable by others or not ours, or in a directory where others could
+/// replace it.
+fn check_private_path(path: &str) -> Result<()> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if meta.file_type().is_symlink() || meta.uid() != unsafe { libc::geteuid() } {
+            bail!("[{}] is not a file of yours", path);
+        }
+        if meta.mode() & 0o004 != 0 {
+            bail!("[{}] is world-readable. Remove it or choose another path.", path);
+        }
+    }
+    let dir = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let meta = fs::metadata(dir).chain_err(|| format!("can't read [{}]", dir.display()))?;
+    // the sticky bit keeps others from replacing files, as in /tmp.
+    if meta.mode() & 0o002 != 0 && meta.mode() & 0o1000 == 0 {
+        bail!("[{}] is world-writable. Choose another path.", dir.display());
+    }
+    Ok(())
+}
+
+fn export(matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("file").unwrap_or("");
+    let entries = make_command(BatchEntrySelector::new(None, None, Vec::new()), true)?.list_entries("");
+    match matches.value_of("to").unwrap_or("") {
+        "kdbx" => {
+            let from_stdin = matches.is_present("password_stdin");
+            let password = new_file_password("password of the exported file: ", from_stdin)?;
+            let data = kdbx::write(&entries, &password)?;
+            create_private_file(path)?
+                .write_all(&data)
+                .chain_err(|| format!("can't write [{}]", path))?;
+        }
+        "csv" => {
+            let map = column_map(matches)?;
+            check_private_path(path)?;
+            csvfile::write(create_private_file(path)?, &entries, &map)
+                .chain_err(|| format!("can't write [{}]", path))?;
+        }
+        to => bail!("unknown export format [{}]", to),
+    }
+    println!("{} entries exported.", entries.len());
+    Ok(())
+}
+
+/// the user name the vault key is derived with, empty on a fresh machine.
+fn vault_user(pwwr_path: &Path) -> Result<String> {
+    let mut user = Vec::new();
+    FileRawStore::new(pwwr_path.join(USER_FILE))?.read_to_end(&mut user)?;
+    Ok(String::from_utf8_lossy(&user).into_owned())
+}
+
+fn backup_create(matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("file").unwrap_or("");
+    let input = BatchEntrySelector::new(None, None, Vec::new());
+    let entries = make_command(input, true)?.list_entries("");
+    let user = vault_user(&pwwr_dir()?)?;
+    let from_stdin = matches.is_present("passphrase_stdin");
+    let passphrase = new_file_password("passphrase of the backup: ", from_stdin)?;
+    let data = backup::create(&entries, &user, &passphrase)?;
+    create_private_file(path)?
+        .write_all(&data)
+        .chain_err(|| format!("can't write [{}]", path))?;
+    println!("{} entries backed up to {}.", entries.len(), path);
+    Ok(())
 }
 
-fn execute_cmd() -> Result<()> {
-    let matches = App::new("pwwr")
+fn backup_ls() -> Result<()> {
+    let snapshots = rotation(&pwwr_dir()?)?.list()?;
+    if snapshots.is_empty() {
+        println!("no backups.");
+    }
+    for snapshot in snapshots {
+        println!(
+            "{}  {} UTC  {} bytes",
+            snapshot.id,
+            clock::format_time(snapshot.created),
+            snapshot.size
+        );
+    }
+    Ok(())
+}
+
+fn backup_restore(matches: &ArgMatches) -> Result<()> {
+    let name = matches.value_of("backup").unwrap_or("");
+    let pwwr_path = pwwr_dir()?;
+    // an id of `backup ls` unless a file is named so.
+    let kept = rotation::is_id(name) && !Path::new(name).exists();
+
+    let portable = if kept {
+        None
+    } else {
+        let data = fs::read(name).chain_err(|| format!("can't read [{}]", name))?;
+        let passphrase = file_password("passphrase of the backup: ", matches.is_present("passphrase_stdin"))?;
+        let backup = backup::open(&data, &passphrase).chain_err(|| format!("can't restore [{}]", name))?;
+        println!(
+            "backup of {} with {} entries, created {} UTC.",
+            backup.metadata.user,
+            backup.metadata.entries,
+            clock::format_time(backup.metadata.created)
+        );
+
+        // a fresh vault is keyed with the user name of the backup.
+        if vault_user(&pwwr_path)?.is_empty() {
+            FileRawStore::new(pwwr_path.join(USER_FILE))?.write_all(backup.metadata.user.as_bytes())?;
+        }
+        Some(backup.entries)
+    };
+
+    let input = BatchEntrySelector::new(None, None, Vec::new());
+    let mut command = make_command(input, true)?;
+    let entries = match portable {
+        Some(entries) => entries,
+        None => {
+            let data = rotation(&pwwr_path)?.read(name)?;
+            let entries = command
+                .entries_of(&data)
+                .chain_err(|| format!("can't decrypt the backup [{}] with the current key", name))?;
+            println!("backup {} with {} entries.", name, entries.len());
+            entries
+        }
+    };
+
+    let report = if matches.is_present("merge") {
+        let policy = ConflictPolicy::parse(matches.value_of("on_conflict").unwrap_or("fail"))?;
+        command.import_entries(entries, policy)?
+    } else {
+        let current = command.list_entries("").len();
+        let question = format!("replace the {} entries of the vault?", current);
+        if current > 0 && !matches.is_present("yes") && !confirm(&question)? {
+            bail!("restore cancelled");
+        }
+        command.replace_entries(entries)?
+    };
+    print_report(&report);
+    Ok(())
+}
+
+/// share the vault through `remote`, or take the vault it holds when this
+/// one is empty.
+fn sync_init(remote: &str) -> Result<()> {
+    let pwwr_path = pwwr_dir()?;
+    let _lock = lock_vault(&pwwr_path)?;
+    let empty = fs::metadata(pwwr_path.join(ENTRY_FILE)).map_or(true, |meta| meta.len() == 0);
+    let remote_vault = sync::remote_has_vault(remote)?;
+    if remote_vault && !empty {
+        bail!("[{}] already holds a vault, init the sync in an empty vault to take it", remote);
+    }
+
+    let repo = Repo::init(&pwwr_path, remote, SYNC_FILES)?;
+    if remote_vault {
+        repo.fetch()?;
+        repo.reset_to_remote()?;
+        println!("vault taken from {}, it opens with its own master password.", remote);
+    } else {
+        repo.commit(&format!("share the vault of {}", audit::hostname()))?;
+        repo.push()?;
+        println!("vault pushed to {}.", remote);
+    }
+    Ok(())
+}
+
+/// merge the remote into the vault while git merges, returning the titles
+/// of the entries changed on both sides.
+fn merge_remote<T: EntrySelector>(
+    repo: &Repo,
+    base: &str,
+    pwwr_path: &Path,
+    command: &mut Command<T, FileRawStore, SessionCipher>,
+) -> Result<Vec<String>> {
+    for file in &[USER_FILE, keyslot::FILE, vault::SETTINGS_FILE] {
+        let ours = repo.show("HEAD", file)?;
+        match sync::merge_file(&repo.show(base, file)?, &ours, &repo.show("MERGE_HEAD", file)?) {
+            Some(ref merged) if *merged == ours => {}
+            Some(merged) => {
+                let path = pwwr_path.join(file);
+                fs::write(&path, merged).chain_err(|| format!("can't write [{}]", path.display()))?;
+            }
+            None => bail!("both sides changed [{}], sync can't merge it", file),
+        }
+    }
+
+    let ours = repo.show("HEAD", ENTRY_FILE)?;
+    let (base, theirs) = (repo.show(base, ENTRY_FILE)?, repo.show("MERGE_HEAD", ENTRY_FILE)?);
+    let mut conflicts = Vec::new();
+    match sync::merge_file(&base, &ours, &theirs) {
+        Some(ref merged) if *merged == ours => {}
+        Some(merged) => {
+            let path = pwwr_path.join(ENTRY_FILE);
+            fs::write(&path, merged).chain_err(|| format!("can't write [{}]", path.display()))?;
+        }
+        None => {
+            let base = command.entries_of(&base)?;
+            let theirs = command
+                .entries_of(&theirs)
+                .chain_err(|| "can't decrypt the remote entries with the key of the vault")?;
+            let (merged, both) = sync::merge_entries(base, command.list_entries(""), theirs);
+            // the remote changes are in the log of the machine that made them.
+            command.detach_audit();
+            command.replace_entries(merged)?;
+            conflicts = both;
+        }
+    }
+    repo.commit(&format!("merge the vault on {}", audit::hostname()))?;
+    Ok(conflicts)
+}
+
+/// commit the vault, then pull the changes of the remote and push.
+fn sync_vault() -> Result<()> {
+    let pwwr_path = pwwr_dir()?;
+    let repo = match Repo::open(&pwwr_path) {
+        Some(repo) => repo,
+        None => bail!("the vault isn't synced, start with `pwwr sync init REMOTE`"),
+    };
+    // holds the lock of the vault until the sync is done.
+    let mut command = make_command(CliEntrySelector::new(), true)?;
+    repo.commit(&format!("update the vault on {}", audit::hostname()))?;
+    if !repo.fetch()? {
+        repo.push()?;
+        println!("vault pushed.");
+        return Ok(());
+    }
+
+    match repo.state()? {
+        State::UpToDate => println!("the vault is up to date."),
+        State::Ahead => {
+            repo.push()?;
+            println!("vault pushed.");
+        }
+        State::Behind => {
+            repo.fast_forward()?;
+            println!("vault pulled.");
+        }
+        State::Diverged(base) => {
+            repo.start_merge()?;
+            let conflicts = match merge_remote(&repo, &base, &pwwr_path, &mut command) {
+                Ok(conflicts) => conflicts,
+                Err(e) => {
+                    let _ = repo.abort_merge();
+                    return Err(e);
+                }
+            };
+            repo.push()?;
+            println!("vault merged and pushed.");
+            for title in conflicts {
+                println!("[{}] changed on both sides, every version of it is kept.", title);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn recovery_sheet(matches: &ArgMatches) -> Result<()> {
+    let pwwr_path = pwwr_dir()?;
+    let cipher = unlock(&pwwr_path)?;
+    check_key(&pwwr_path, &cipher)?;
+    let kdf = key_slots(&pwwr_path)?
+        .slots()
+        .iter()
+        .map(|slot| {
+            format!(
+                "slot {} {}: {}, {} KiB, {} iterations, {} lanes, salt {}",
+                slot.id,
+                slot.kind.name(),
+                slot.kdf.algorithm,
+                slot.kdf.memory,
+                slot.kdf.iterations,
+                slot.kdf.lanes,
+                slot.kdf.salt
+            )
+        })
+        .collect();
+    let sheet = Sheet {
+        user: vault_user(&pwwr_path)?,
+        created: clock::now(),
+        key: SecretBytes::from_slice(cipher.key().expose()),
+        kdf,
+    };
+    let data = match matches.value_of("to").unwrap_or("text") {
+        "svg" => SecretBytes::from_slice(sheet.svg()?.expose().as_bytes()),
+        "pdf" => sheet.pdf()?,
+        _ => SecretBytes::from_slice(sheet.text()?.expose().as_bytes()),
+    };
+    match matches.value_of("file") {
+        Some(path) => {
+            check_private_path(path)?;
+            create_private_file(path)?
+                .write_all(data.expose())
+                .chain_err(|| format!("can't write [{}]", path))?;
+            println!("recovery sheet written to {}.", path);
+        }
+        None => stdout()
+            .write_all(data.expose())
+            .chain_err(|| "can't write to stdout.")?,
+    }
+    Ok(())
+}
+
+/// read a line typed after `prompt`, failing at the end of the input.
+fn prompt_line(prompt: &str) -> Result<SecretString> {
+    eprint!("{}", prompt);
+    stderr().flush().chain_err(|| "can't write to stderr.")?;
+    let mut line = String::new();
+    let read = stdin().read_line(&mut line).chain_err(|| "can't read a line")?;
+    let line = SecretString::new(line);
+    if read == 0 {
+        bail!("unexpected end of input");
+    }
+    Ok(SecretString::from(line.expose().trim()))
+}
+
+/// read `count` words typed line by line from a sheet, asking again for a
+/// line that doesn't match its check.
+fn read_sheet_words(label: &str, count: usize) -> Result<Vec<&'static str>> {
+    let mut words = Vec::new();
+    let mut number = 1;
+    while words.len() < count {
+        let line = prompt_line(&format!("{}line {}: ", label, number))?;
+        match recovery::read_line(number, line.expose()) {
+            Ok(line_words) => {
+                words.extend(line_words);
+                number += 1;
+            }
+            Err(e) => eprintln!("{}, type it again.", e),
+        }
+    }
+    Ok(words)
+}
+
+/// fail unless there is a vault to open without its master password.
+fn check_vault(pwwr_path: &Path) -> Result<()> {
+    let mut content = Vec::new();
+    FileRawStore::new(pwwr_path.join(ENTRY_FILE))?.read_to_end(&mut content)?;
+    if content.is_empty() {
+        bail!("no vault to recover in [{}]", pwwr_path.display());
+    }
+    Ok(())
+}
+
+/// replace the password slots with one of a new master password. A vault
+/// that needs a keyfile with its password keeps needing the one of `--keyfile`.
+fn reset_master_password(pwwr_path: &Path, cipher: AesCipher, from_stdin: bool) -> Result<()> {
+    let mut slots = key_slots(pwwr_path)?;
+    let keyfile_key = match keyfile_path() {
+        Some(path) if slots.has(Kind::PasswordKeyfile) => Some(read_keyfile(&path)?),
+        None if slots.has(Kind::PasswordKeyfile) => bail!(
+            "the vault needs a keyfile with its master password, give it with --keyfile or {}",
+            KEYFILE_ENV
+        ),
+        _ => None,
+    };
+    let mut user = vault_user(pwwr_path)?;
+    if user.is_empty() {
+        user = String::from(prompt_line("vault user: ")?.expose());
+        FileRawStore::new(pwwr_path.join(USER_FILE))?.write_all(user.as_bytes())?;
+    }
+    let password = new_file_password("new master password: ", from_stdin)?;
+    let id = slots.replace_passwords(
+        password.expose().as_bytes(),
+        keyfile_key.as_ref().map(|k| k.expose()),
+        cipher.key().expose(),
+    )?;
+    save_key_slots(pwwr_path, &slots)?;
+    let factors = if keyfile_key.is_some() { "master password and keyfile are" } else { "master password is" };
+    println!("vault of {} recovered, the {} changed in key slot {}.", user, factors, id);
+    Ok(())
+}
+
+fn recover(matches: &ArgMatches) -> Result<()> {
+    let pwwr_path = pwwr_dir()?;
+    check_vault(&pwwr_path)?;
+    println!("type each line of the vault key as printed, the words then the check.");
+    let words = read_sheet_words("", recovery::LINES * recovery::WORDS_PER_LINE)?;
+    let cipher = AesCipher::from_key(recovery::decode(&words)?);
+    check_key(&pwwr_path, &cipher).chain_err(|| "the sheet doesn't open this vault")?;
+    reset_master_password(&pwwr_path, cipher, matches.is_present("password_stdin"))
+}
+
+fn shares_split(matches: &ArgMatches) -> Result<()> {
+    let number = |name: &str| -> Result<u8> {
+        let value = matches.value_of(name).unwrap_or("");
+        value.parse().chain_err(|| format!("invalid {} [{}]", name, value))
+    };
+    let (threshold, count) = (number("threshold")?, number("shares")?);
+    let pwwr_path = pwwr_dir()?;
+    let cipher = unlock(&pwwr_path)?;
+    check_key(&pwwr_path, &cipher)?;
+    let user = vault_user(&pwwr_path)?;
+    let shares = shamir::split(cipher.key().expose(), threshold, count)?;
+
+    match matches.value_of("dir") {
+        Some(dir) => {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .chain_err(|| format!("can't create [{}]", dir))?;
+            for share in &shares {
+                let path = format!("{}/share-{}.txt", dir.trim_end_matches('/'), share.index);
+                check_private_path(&path)?;
+                create_private_file(&path)?
+                    .write_all(share.text(&user, count)?.expose().as_bytes())
+                    .chain_err(|| format!("can't write [{}]", path))?;
+                println!("share {} written to {}.", share.index, path);
+            }
+        }
+        None => {
+            for (i, share) in shares.iter().enumerate() {
+                if i > 0 {
+                    println!("\n{}\n", "-".repeat(70));
+                }
+                print!("{}", share.text(&user, count)?.expose());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the share printed in a file written by `shares split`.
+fn read_share_file(path: &str) -> Result<Share> {
+    let text = SecretString::new(fs::read_to_string(path).chain_err(|| format!("can't read [{}]", path))?);
+    let mut words = Vec::new();
+    for line in text.expose().lines() {
+        let line = line.trim();
+        let (number, rest) = match line.find(' ') {
+            Some(i) => (&line[..i], &line[i..]),
+            None => continue,
+        };
+        if let Ok(number) = number.parse() {
+            words.extend(recovery::read_line(number, rest).chain_err(|| format!("invalid share [{}]", path))?);
+        }
+    }
+    let data = recovery::decode(&words).chain_err(|| format!("invalid share [{}]", path))?;
+    Share::from_bytes(data.expose())
+}
+
+fn shares_recover(matches: &ArgMatches) -> Result<()> {
+    let pwwr_path = pwwr_dir()?;
+    check_vault(&pwwr_path)?;
+    let mut shares = Vec::new();
+    for path in matches.values_of("file").into_iter().flatten() {
+        shares.push(read_share_file(path)?);
+    }
+    // the first share tells how many are needed.
+    while shares.len() < shares.first().map_or(2, |s: &Share| s.threshold as usize) {
+        let label = format!("share {}, ", shares.len() + 1);
+        if shares.is_empty() {
+            println!("type each line of the shares as printed, the words then the check.");
+        }
+        let words = read_sheet_words(&label, Share::word_count(pwdcrypto::KEY_LEN))?;
+        shares.push(Share::from_bytes(recovery::decode(&words)?.expose())?);
+    }
+
+    let cipher = AesCipher::from_key(shamir::combine(&shares)?);
+    check_key(&pwwr_path, &cipher).chain_err(|| "the shares don't open this vault")?;
+    if matches.is_present("new_password") {
+        reset_master_password(&pwwr_path, cipher, matches.is_present("password_stdin"))
+    } else {
+        serve_agent(
+            &pwwr_path,
+            cipher,
+            agent::DEFAULT_IDLE_TIMEOUT,
+            agent::DEFAULT_MAX_LIFETIME,
+            false,
+        )
+    }
+}
+
+fn keyslot_list() -> Result<()> {
+    let slots = key_slots(&pwwr_dir()?)?;
+    if slots.is_empty() {
+        println!("no key slots, the next unlock moves the master password to one.");
+    }
+    for slot in slots.slots() {
+        println!(
+            "{}  {:<16}  {} UTC  {}",
+            slot.id,
+            slot.kind.name(),
+            clock::format_time(slot.created),
+            slot.label
+        );
+    }
+    Ok(())
+}
+
+fn keyslot_add(matches: &ArgMatches) -> Result<()> {
+    let pwwr_path = pwwr_dir()?;
+    let cipher = unlock(&pwwr_path)?;
+    check_key(&pwwr_path, &cipher)?;
+    let key = cipher.key().expose();
+    let label = matches.value_of("label").unwrap_or("");
+    let mut slots = key_slots(&pwwr_path)?;
+
+    let mut code = None;
+    let id = if let Some(path) = matches.value_of("new_keyfile") {
+        if matches.is_present("generate") {
+            generate_keyfile(path)?;
+        }
+        slots.add(Kind::Keyfile, label, read_keyfile(Path::new(path))?.expose(), key)?
+    } else if matches.is_present("recovery_code") {
+        let new_code = keyslot::new_recovery_code()?;
+        let id = slots.add(
+            Kind::RecoveryCode,
+            label,
+            keyslot::normalize_code(new_code.expose()).expose().as_bytes(),
+            key,
+        )?;
+        code = Some(new_code);
+        id
+    } else {
+        let password = new_file_password("new password: ", false)?;
+        slots.add(Kind::Password, label, password.expose().as_bytes(), key)?
+    };
+    save_key_slots(&pwwr_path, &slots)?;
+
+    println!("key slot {} added.", id);
+    if let Some(code) = code {
+        println!("recovery code: {}", code.expose());
+        println!("it is shown only once. Type it at the password prompt to open the vault.");
+    }
+    Ok(())
+}
+
+fn vault_create(matches: &ArgMatches) -> Result<()> {
+    let name = matches.value_of("name").unwrap_or("");
+    let number = |arg: &str| -> Result<Option<u32>> {
+        match matches.value_of(arg) {
+            Some(value) => Ok(Some(value.parse().chain_err(|| format!("invalid {} [{}]", arg, value))?)),
+            None => Ok(None),
+        }
+    };
+    let (memory, iterations, lanes) = (number("kdf_memory")?, number("kdf_iterations")?, number("kdf_lanes")?);
+    let config = Config::load()?;
+    let mut settings = Settings::default();
+    if memory.is_some() || iterations.is_some() || lanes.is_some() {
+        let default = config.kdf();
+        settings.kdf = Some(KdfParams {
+            memory: memory.unwrap_or(default.memory),
+            iterations: iterations.unwrap_or(default.iterations),
+            lanes: lanes.unwrap_or(default.lanes),
+        });
+    }
+    settings.validate()?;
+    let vaults = vaults()?;
+    if vaults.exists(name) {
+        bail!("the vault [{}] exists", name);
+    }
+
+    let input = CliEntrySelector::new();
+    let user = input.required("user name")?;
+    let password = new_file_password("master password: ", false)?;
+    secret::disable_core_dumps()?;
+    let data_key = keyslot::new_data_key()?;
+    let mut slots = KeySlots::default();
+    slots.set_kdf(settings.kdf(config.kdf()).tuple());
+    slots.add(Kind::Password, "master password", password.expose().as_bytes(), data_key.expose())?;
+
+    let pwwr_path = vaults.create(name, &settings)?;
+    FileRawStore::new(pwwr_path.join(USER_FILE))?.write_all(&user)?;
+    save_key_slots(&pwwr_path, &slots)?;
+    println!("vault {} created, open it with --vault {}.", name, name);
+    Ok(())
+}
+
+fn vault_list() -> Result<()> {
+    let vaults = vaults()?;
+    let default = vaults.default_name();
+    for name in vaults.list()? {
+        let marker = if name == default { "*" } else { " " };
+        println!("{} {:<16} {}", marker, name, vaults.path(&name)?.display());
+    }
+    Ok(())
+}
+
+fn vault_default(matches: &ArgMatches) -> Result<()> {
+    let vaults = vaults()?;
+    match matches.value_of("name") {
+        Some(name) => {
+            vaults.open(name)?;
+            set_config("default_vault", Some(name).filter(|&name| name != vault::DEFAULT))?;
+            println!("the default vault is {}.", name);
+        }
+        None => println!("{}", vaults.default_name()),
+    }
+    Ok(())
+}
+
+/// set `key` in the config file, or unset it without `value`.
+fn set_config(key: &str, value: Option<&str>) -> Result<()> {
+    let path = config::path()?;
+    let mut config = Config::read_from(&path)?;
+    config.set(key, value)?;
+    config.save_to(&path)
+}
+
+fn vault_remove(matches: &ArgMatches) -> Result<()> {
+    let name = matches.value_of("name").unwrap_or("");
+    let vaults = vaults()?;
+    let pwwr_path = vaults.open(name)?;
+    if AgentClient::connect(&agent::socket_path(&pwwr_path)).is_some() {
+        bail!("the agent of the vault [{}] is running, run `pwwr --vault {} lock` first", name, name);
+    }
+    let count = rotation(&pwwr_path)?.list()?.len();
+    let question = format!("remove the vault {} with its entries and {} kept copies?", name, count);
+    if !matches.is_present("yes") && !confirm(&question)? {
+        bail!("removal cancelled");
+    }
+    vaults.remove(name)?;
+    if vaults.default_name() == name {
+        set_config("default_vault", None)?;
+    }
+    println!("vault {} removed.", name);
+    Ok(())
+}
+
+fn keyfile_require() -> Result<()> {
+    let pwwr_path = pwwr_dir()?;
+    let path = match keyfile_path() {
+        Some(path) => path,
+        None => bail!("give the keyfile with --keyfile or {}", KEYFILE_ENV),
+    };
+    let keyfile_key = read_keyfile(&path)?;
+    if key_slots(&pwwr_path)?.is_empty() {
+        upgrade(&pwwr_path)?;
+    }
+    let mut slots = key_slots(&pwwr_path)?;
+    if !slots.has(Kind::Password) {
+        bail!("no password key slot to replace, see `pwwr keyslot list`");
+    }
+
+    secret::disable_core_dumps()?;
+    let password = CliEntrySelector::new().get_password()?;
+    let data_key = slots
+        .open(&[Kind::Password], password.expose())
+        .chain_err(|| "wrong password.")?;
+    let id = slots.require_keyfile(password.expose(), keyfile_key.expose(), data_key.expose())?;
+    save_key_slots(&pwwr_path, &slots)?;
+    println!(
+        "key slot {} needs the master password and {}, the password alone no longer opens the vault.",
+        id,
+        path.display()
+    );
+    Ok(())
+}
+
+fn keyslot_remove(matches: &ArgMatches) -> Result<()> {
+    let value = matches.value_of("id").unwrap_or("");
+    let id: u32 = value.parse().chain_err(|| format!("invalid key slot [{}]", value))?;
+    let pwwr_path = pwwr_dir()?;
+    unlock(&pwwr_path)?;
+    let mut slots = key_slots(&pwwr_path)?;
+    let slot = slots.remove(id)?;
+    let question = format!("remove key slot {} ({})?", id, slot.kind.name());
+    if !matches.is_present("yes") && !confirm(&question)? {
+        bail!("removal cancelled");
+    }
+    save_key_slots(&pwwr_path, &slots)?;
+    println!("key slot {} removed.", id);
+    Ok(())
+}
+
+fn parse_secs(value: Option<&str>, default: u64) -> Result<u64> {
+    match value {
+        Some(v) => v.parse::<u64>().chain_err(|| format!("invalid seconds [{}]", v)),
+        None => Ok(default),
+    }
+}
+
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("pwwr")
         .version("0.1.0")
         .about("Rust Password Wound")
         .author("meganehouser <sleepy.st818@gmail.com>")
         .subcommand(
             SubCommand::with_name("add")
                 .about("add a password entry.")
-                .arg(Arg::with_name("name_for_add").index(1)),
+                .arg(Arg::with_name("name_for_add").index(1))
+                .args(&entry_args()),
         )
         .subcommand(
             SubCommand::with_name("change")
                 .about("change the password entry.")
-                .arg(Arg::with_name("name_for_change").index(1)),
+                .arg(Arg::with_name("name_for_change").index(1))
+                .args(&entry_args()),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .global(true)
+                .takes_value(true)
+                .possible_values(Format::NAMES)
+                .help("output format, see the documentation for the json/yaml schema"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .global(true)
+                .takes_value(true)
+                .value_name("PATH")
+                .help("the config file instead of $XDG_CONFIG_HOME/pwwr/config.toml [env: PWWR_CONFIG]"),
+        )
+        .arg(
+            Arg::with_name("vault")
+                .long("vault")
+                .global(true)
+                .takes_value(true)
+                .value_name("NAME")
+                .help("the vault to open instead of the default one [env: PWWR_VAULT]"),
+        )
+        .arg(
+            Arg::with_name("keyfile")
+                .long("keyfile")
+                .global(true)
+                .takes_value(true)
+                .value_name("PATH")
+                .help("the keyfile opening the vault, alone or with the master password [env: PWWR_KEYFILE]"),
         )
         .subcommand(
             SubCommand::with_name("show")
                 .about("show the password entry.")
-                .arg(Arg::with_name("name_for_show").index(1)),
+                .arg(Arg::with_name("name_for_show").index(1))
+                .arg(reveal_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("list the entries.")
+                .arg(Arg::with_name("name_for_list").index(1))
+                .arg(reveal_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("log")
+                .about("show the audit log of the changes saved on this machine, oldest first.")
+                .arg(
+                    Arg::with_name("entry")
+                        .long("entry")
+                        .takes_value(true)
+                        .value_name("TITLE")
+                        .help("only the changes to this entry, under this title or as renamed from it"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .takes_value(true)
+                        .value_name("TIME")
+                        .help("only the changes since a date YYYY-MM-DD in UTC, or since an age as 30m, 12h or 7d"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("pull the changes of the git remote of the vault, merge them and push.")
+                .subcommand(
+                    SubCommand::with_name("init")
+                        .about("share the vault through a git remote, or take the vault it holds.")
+                        .arg(Arg::with_name("remote").index(1).required(true)),
+                ),
         )
-        .get_matches();
+        .subcommand(
+            SubCommand::with_name("agent")
+                .about("keep the vault unlocked in a background agent.")
+                .arg(
+                    Arg::with_name("idle_timeout")
+                        .long("idle-timeout")
+                        .takes_value(true)
+                        .value_name("SECS")
+                        .help("lock after this many seconds without requests"),
+                )
+                .arg(
+                    Arg::with_name("max_lifetime")
+                        .long("max-lifetime")
+                        .takes_value(true)
+                        .value_name("SECS")
+                        .help("lock this many seconds after starting"),
+                )
+                .arg(
+                    Arg::with_name("foreground")
+                        .long("foreground")
+                        .help("don't detach from the terminal"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("lock").about("wipe the keys held by the agent and the ssh-agent."))
+        .subcommand(
+            SubCommand::with_name("ssh-agent")
+                .about("serve the ssh_key fields of the entries to ssh.")
+                .arg(
+                    Arg::with_name("confirm")
+                        .long("confirm")
+                        .help("confirm every signature through $SSH_ASKPASS"),
+                )
+                .arg(
+                    Arg::with_name("idle_timeout")
+                        .long("idle-timeout")
+                        .takes_value(true)
+                        .value_name("SECS")
+                        .help("wipe the keys after this many seconds without requests"),
+                )
+                .arg(
+                    Arg::with_name("max_lifetime")
+                        .long("max-lifetime")
+                        .takes_value(true)
+                        .value_name("SECS")
+                        .help("wipe the keys this many seconds after starting"),
+                )
+                .arg(
+                    Arg::with_name("foreground")
+                        .long("foreground")
+                        .help("don't detach from the terminal"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("run a command with secrets in its environment.")
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("env")
+                        .long("env")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("NAME=TITLE:FIELD")
+                        .help("set NAME to the field of the entry, the password without :FIELD"),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .multiple(true)
+                        .required(true)
+                        .help("the command and its arguments, after --"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("import the entries of another password manager.")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["kdbx", "csv", "bitwarden", "1pux", "pass"])
+                        .help("the format of the file, or pass for a password-store directory"),
+                )
+                .args(&csv_args())
+                .arg(
+                    Arg::with_name("decrypt_command")
+                        .long("decrypt-command")
+                        .takes_value(true)
+                        .help("the command decrypting the files of pass, split on whitespace, gpg --decrypt by default"),
+                )
+                .arg(
+                    Arg::with_name("on_conflict")
+                        .long("on-conflict")
+                        .takes_value(true)
+                        .possible_values(ConflictPolicy::NAMES)
+                        .help("what to do when a title is taken, fail by default"),
+                )
+                .arg(
+                    Arg::with_name("password_stdin")
+                        .long("password-stdin")
+                        .help("read the password of the file from stdin"),
+                )
+                .arg(
+                    Arg::with_name("dry_run")
+                        .long("dry-run")
+                        .help("show the entries to import without importing them"),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .help("import CSV rows without asking"),
+                )
+                .arg(Arg::with_name("file").index(1).required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("export the entries for another password manager.")
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&["kdbx", "csv"])
+                        .help("the format of the file"),
+                )
+                .args(&csv_args())
+                .arg(
+                    Arg::with_name("password_stdin")
+                        .long("password-stdin")
+                        .help("read the password protecting the file from stdin"),
+                )
+                .arg(Arg::with_name("file").index(1).required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("recovery-sheet")
+                .about("print a sheet to open the vault without the master password.")
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .possible_values(&["text", "svg", "pdf"])
+                        .help("the format of the sheet, text by default"),
+                )
+                .arg(Arg::with_name("file").index(1).help("write the sheet to FILE instead of stdout")),
+        )
+        .subcommand(
+            SubCommand::with_name("recover")
+                .about("open the vault with a recovery sheet and set a new master password.")
+                .arg(
+                    Arg::with_name("password_stdin")
+                        .long("password-stdin")
+                        .help("read the new master password from stdin after the lines of the sheet"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("keyslot")
+                .about("manage the secrets opening the vault: passwords, keyfiles and recovery codes.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("list the key slots."))
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("add a key slot, a password unless told otherwise.")
+                        .arg(
+                            Arg::with_name("new_keyfile")
+                                .long("new-keyfile")
+                                .takes_value(true)
+                                .value_name("PATH")
+                                .conflicts_with("recovery_code")
+                                .help("open the vault with a keyfile alone, given by --keyfile"),
+                        )
+                        .arg(
+                            Arg::with_name("generate")
+                                .long("generate")
+                                .requires("new_keyfile")
+                                .help("write a new random keyfile to PATH"),
+                        )
+                        .arg(
+                            Arg::with_name("recovery_code")
+                                .long("recovery-code")
+                                .help("open the vault with a random code shown once"),
+                        )
+                        .arg(
+                            Arg::with_name("label")
+                                .long("label")
+                                .takes_value(true)
+                                .help("a note shown by `keyslot list`"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("remove a key slot, unless it is the last one.")
+                        .arg(
+                            Arg::with_name("yes")
+                                .long("yes")
+                                .help("remove it without asking"),
+                        )
+                        .arg(Arg::with_name("id").index(1).required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("show or change the settings of the config file.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("print the value of a key, or of every key.")
+                        .arg(Arg::with_name("key").index(1).possible_values(config::KEYS)),
+                )
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("set a key, or set it back to its default without a value.")
+                        .arg(Arg::with_name("key").index(1).required(true).possible_values(config::KEYS))
+                        .arg(Arg::with_name("value").index(2)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vault")
+                .about("manage named vaults, each with its own master password and settings.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("create a vault and set its master password.")
+                        .arg(
+                            Arg::with_name("kdf_memory")
+                                .long("kdf-memory")
+                                .takes_value(true)
+                                .value_name("KIB")
+                                .help("the Argon2id memory of its key slots"),
+                        )
+                        .arg(
+                            Arg::with_name("kdf_iterations")
+                                .long("kdf-iterations")
+                                .takes_value(true)
+                                .help("the Argon2id iterations of its key slots"),
+                        )
+                        .arg(
+                            Arg::with_name("kdf_lanes")
+                                .long("kdf-lanes")
+                                .takes_value(true)
+                                .help("the Argon2id lanes of its key slots"),
+                        )
+                        .arg(Arg::with_name("name").index(1).required(true)),
+                )
+                .subcommand(SubCommand::with_name("list").about("list the vaults, the default one marked with *."))
+                .subcommand(
+                    SubCommand::with_name("default")
+                        .about("show the default vault, or set it.")
+                        .arg(Arg::with_name("name").index(1)),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("remove a vault with its entries and kept copies.")
+                        .arg(
+                            Arg::with_name("yes")
+                                .long("yes")
+                                .help("remove it without asking"),
+                        )
+                        .arg(Arg::with_name("name").index(1).required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("keyfile")
+                .about("require a keyfile with the master password to open the vault.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("generate")
+                        .about("write a new random keyfile in the format of KeePass.")
+                        .arg(Arg::with_name("file").index(1).required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("require")
+                        .about("replace the password key slots with slots needing the keyfile of --keyfile too."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("shares")
+                .about("split the vault key among holders, any threshold of whom can open the vault.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("split")
+                        .about("print the shares of the vault key, or write them to files.")
+                        .arg(
+                            Arg::with_name("threshold")
+                                .long("threshold")
+                                .takes_value(true)
+                                .required(true)
+                                .help("how many shares open the vault"),
+                        )
+                        .arg(
+                            Arg::with_name("shares")
+                                .long("shares")
+                                .takes_value(true)
+                                .required(true)
+                                .help("how many shares to make, up to 255"),
+                        )
+                        .arg(
+                            Arg::with_name("dir")
+                                .long("dir")
+                                .takes_value(true)
+                                .help("write share-N.txt files to DIR instead of stdout"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("recover")
+                        .about("unlock the vault in the agent with enough shares.")
+                        .arg(
+                            Arg::with_name("new_password")
+                                .long("new-password")
+                                .help("set a new master password instead of starting the agent"),
+                        )
+                        .arg(
+                            Arg::with_name("password_stdin")
+                                .long("password-stdin")
+                                .requires("new_password")
+                                .help("read the new master password from stdin after the shares"),
+                        )
+                        .arg(
+                            Arg::with_name("file")
+                                .index(1)
+                                .multiple(true)
+                                .help("share files written by `shares split`, the others are typed"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("back up the vault, or roll it back to a copy kept before a save.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("write a backup of every entry.")
+                        .arg(passphrase_stdin_arg())
+                        .arg(Arg::with_name("file").index(1).required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("ls").about("list the copies of the vault kept before each save."),
+                )
+                .subcommand(
+                    SubCommand::with_name("restore")
+                        .about("replace the entries of the vault with those of a backup file or a kept copy.")
+                        .arg(passphrase_stdin_arg())
+                        .arg(
+                            Arg::with_name("merge")
+                                .long("merge")
+                                .help("add the entries of the backup to those of the vault instead"),
+                        )
+                        .arg(
+                            Arg::with_name("on_conflict")
+                                .long("on-conflict")
+                                .takes_value(true)
+                                .requires("merge")
+                                .possible_values(ConflictPolicy::NAMES)
+                                .help("what to do when a title is taken, fail by default"),
+                        )
+                        .arg(
+                            Arg::with_name("yes")
+                                .long("yes")
+                                .help("replace the entries without asking"),
+                        )
+                        .arg(
+                            Arg::with_name("backup")
+                                .index(1)
+                                .required(true)
+                                .value_name("FILE|ID")
+                                .help("a backup file or the id of a copy listed by `backup ls`"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("git-credential")
+                .about("git credential helper, see gitcredentials(7).")
+                .arg(
+                    Arg::with_name("action")
+                        .index(1)
+                        .required(true)
+                        .help("get, store or erase"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("inject")
+                .about("render a template, replacing {{ pwwr \"TITLE\" \"FIELD\" }} with secrets.")
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("input")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("TEMPLATE"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("written with mode 0600, stdout if omitted"),
+                )
+                .arg(
+                    Arg::with_name("dry_run")
+                        .long("dry-run")
+                        .help("list the referenced entries without revealing values"),
+                ),
+        )
+}
+
+fn csv_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("preset")
+            .long("preset")
+            .takes_value(true)
+            .possible_values(csvfile::PRESETS)
+            .help("the columns of a CSV file exported by that manager"),
+        Arg::with_name("map")
+            .long("map")
+            .takes_value(true)
+            .value_name("PART=COLUMN,...")
+            .conflicts_with("preset")
+            .help("the columns of a CSV file, e.g. title=name,user=username,password=password"),
+    ]
+}
 
+fn passphrase_stdin_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("passphrase_stdin")
+        .long("passphrase-stdin")
+        .help("read the passphrase of the backup from stdin")
+}
+
+fn reveal_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("reveal")
+        .long("reveal")
+        .help("include secrets in the output")
+}
+
+/// the selector of the read commands. Structured output never prompts for a choice.
+fn read_selector(format: Format) -> Option<BatchEntrySelector> {
+    match format {
+        Format::Text => None,
+        _ => Some(BatchEntrySelector::new(None, None, Vec::new())),
+    }
+}
 
-    if let Some(ref sub_matches) = matches.subcommand_matches("add") {
+fn execute_cmd(matches: &ArgMatches, format: Format) -> Result<()> {
+    if let Some(sub_matches) = matches.subcommand_matches("add") {
         let title = sub_matches.value_of("name_for_add").unwrap_or("");
-        make_command()?.add_entry(title)?;
-    } else if let Some(ref sub_matches) = matches.subcommand_matches("change") {
+        // only --no-input forbids prompting the master password.
+        let interactive = !sub_matches.is_present("no_input");
+        match batch_selector(sub_matches)? {
+            Some(_) if title.is_empty() => bail!("a title is required without input."),
+            Some(input) => make_command(input, interactive)?.add_entry(title)?,
+            None => make_command(CliEntrySelector::new(), true)?.add_entry(title)?,
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("change") {
         let title = sub_matches.value_of("name_for_change").unwrap_or("");
-        make_command()?.change_entry(title)?;
-    } else if let Some(ref sub_matches) = matches.subcommand_matches("show") {
+        let interactive = !sub_matches.is_present("no_input");
+        match batch_selector(sub_matches)? {
+            Some(input) => make_command(input, interactive)?.change_entry(title)?,
+            None => make_command(CliEntrySelector::new(), true)?.change_entry(title)?,
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("show") {
         let title = sub_matches.value_of("name_for_show").unwrap_or("");
-        match make_command()?.get_entry(title)? {
-            Some(ref entry) => println!("{}", entry),
-            None => bail!("No entry."),
+        let entry = match read_selector(format) {
+            Some(input) => make_command(input, true)?.get_entry(title)?,
+            None => make_command(CliEntrySelector::new(), true)?.get_entry(title)?,
+        };
+        match entry {
+            Some(ref entry) => output::print_entry(entry, format, sub_matches.is_present("reveal"))?,
+            None => bail!(ErrorKind::EntryNotFound(String::from(title))),
         };
+    } else if let Some(sub_matches) = matches.subcommand_matches("list") {
+        let title = sub_matches.value_of("name_for_list").unwrap_or("");
+        let entries = make_command(CliEntrySelector::new(), true)?.list_entries(title);
+        output::print_entries(&entries, format, sub_matches.is_present("reveal"))?;
+    } else if let Some(sub_matches) = matches.subcommand_matches("log") {
+        let since = match sub_matches.value_of("since") {
+            Some(text) => audit::parse_since(text, clock::now())?,
+            None => 0,
+        };
+        let command = make_command(CliEntrySelector::new(), true)?;
+        let records = command.audit_records(sub_matches.value_of("entry"), since)?;
+        output::print_records(&records, format)?;
+    } else if let Some(sync_matches) = matches.subcommand_matches("sync") {
+        match sync_matches.subcommand_matches("init") {
+            Some(sub_matches) => sync_init(sub_matches.value_of("remote").unwrap_or(""))?,
+            None => sync_vault()?,
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("agent") {
+        let idle_timeout = parse_secs(
+            sub_matches.value_of("idle_timeout"),
+            agent::DEFAULT_IDLE_TIMEOUT,
+        )?;
+        let max_lifetime = parse_secs(
+            sub_matches.value_of("max_lifetime"),
+            agent::DEFAULT_MAX_LIFETIME,
+        )?;
+        start_agent(idle_timeout, max_lifetime, sub_matches.is_present("foreground"))?;
+    } else if matches.subcommand_matches("lock").is_some() {
+        lock_agent()?;
+    } else if let Some(sub_matches) = matches.subcommand_matches("ssh-agent") {
+        let idle_timeout = parse_secs(
+            sub_matches.value_of("idle_timeout"),
+            agent::DEFAULT_IDLE_TIMEOUT,
+        )?;
+        let max_lifetime = parse_secs(
+            sub_matches.value_of("max_lifetime"),
+            agent::DEFAULT_MAX_LIFETIME,
+        )?;
+        start_ssh_agent(
+            sub_matches.is_present("confirm"),
+            idle_timeout,
+            max_lifetime,
+            sub_matches.is_present("foreground"),
+        )?;
+    } else if let Some(sub_matches) = matches.subcommand_matches("import") {
+        import(sub_matches)?;
+    } else if let Some(sub_matches) = matches.subcommand_matches("export") {
+        export(sub_matches)?;
+    } else if let Some(sub_matches) = matches.subcommand_matches("recovery-sheet") {
+        recovery_sheet(sub_matches)?;
+    } else if let Some(sub_matches) = matches.subcommand_matches("recover") {
+        recover(sub_matches)?;
+    } else if let Some(keyslot_matches) = matches.subcommand_matches("keyslot") {
+        if keyslot_matches.subcommand_matches("list").is_some() {
+            keyslot_list()?;
+        } else if let Some(sub_matches) = keyslot_matches.subcommand_matches("add") {
+            keyslot_add(sub_matches)?;
+        } else if let Some(sub_matches) = keyslot_matches.subcommand_matches("remove") {
+            keyslot_remove(sub_matches)?;
+        }
+    } else if let Some(config_matches) = matches.subcommand_matches("config") {
+        if let Some(sub_matches) = config_matches.subcommand_matches("get") {
+            let config = Config::read_from(&config::path()?)?;
+            match sub_matches.value_of("key") {
+                Some(key) => println!("{}", config.get(key)?),
+                None => {
+                    println!("# {}", config::path()?.display());
+                    for key in config::KEYS {
+                        println!("{} = {}", key, config.get(key)?);
+                    }
+                }
+            }
+        } else if let Some(sub_matches) = config_matches.subcommand_matches("set") {
+            set_config(sub_matches.value_of("key").unwrap_or(""), sub_matches.value_of("value"))?;
+        }
+    } else if let Some(vault_matches) = matches.subcommand_matches("vault") {
+        if let Some(sub_matches) = vault_matches.subcommand_matches("create") {
+            vault_create(sub_matches)?;
+        } else if vault_matches.subcommand_matches("list").is_some() {
+            vault_list()?;
+        } else if let Some(sub_matches) = vault_matches.subcommand_matches("default") {
+            vault_default(sub_matches)?;
+        } else if let Some(sub_matches) = vault_matches.subcommand_matches("remove") {
+            vault_remove(sub_matches)?;
+        }
+    } else if let Some(keyfile_matches) = matches.subcommand_matches("keyfile") {
+        if let Some(sub_matches) = keyfile_matches.subcommand_matches("generate") {
+            let path = sub_matches.value_of("file").unwrap_or("");
+            generate_keyfile(path)?;
+            println!("keyfile written to {}.", path);
+        } else if keyfile_matches.subcommand_matches("require").is_some() {
+            keyfile_require()?;
+        }
+    } else if let Some(shares_matches) = matches.subcommand_matches("shares") {
+        if let Some(sub_matches) = shares_matches.subcommand_matches("split") {
+            shares_split(sub_matches)?;
+        } else if let Some(sub_matches) = shares_matches.subcommand_matches("recover") {
+            shares_recover(sub_matches)?;
+        }
+    } else if let Some(backup_matches) = matches.subcommand_matches("backup") {
+        if let Some(sub_matches) = backup_matches.subcommand_matches("create") {
+            backup_create(sub_matches)?;
+        } else if backup_matches.subcommand_matches("ls").is_some() {
+            backup_ls()?;
+        } else if let Some(sub_matches) = backup_matches.subcommand_matches("restore") {
+            backup_restore(sub_matches)?;
+        }
+    } else if let Some(sub_matches) = matches.subcommand_matches("git-credential") {
+        // stdin carries the protocol, so the vault must be unlocked by the agent.
+        let action = sub_matches.value_of("action").unwrap_or("");
+        let input = BatchEntrySelector::new(None, None, Vec::new());
+        let sin = stdin();
+        make_command(input, false)?.git_credential(action, sin.lock(), stdout())?;
+    } else if let Some(sub_matches) = matches.subcommand_matches("inject") {
+        inject(
+            sub_matches.value_of("input").unwrap_or(""),
+            sub_matches.value_of("output"),
+            sub_matches.is_present("dry_run"),
+        )?;
+    } else if let Some(sub_matches) = matches.subcommand_matches("run") {
+        let env = resolve_env(sub_matches)?;
+        let command: Vec<&str> = sub_matches.values_of("command").into_iter().flatten().collect();
+        let code = run::run(command[0], &command[1..], env)?;
+        process::exit(code);
     } else {
         println!("{}", matches.usage());
     };
@@ -103,10 +2013,28 @@ fn execute_cmd() -> Result<()> {
 }
 
 fn main() {
-    match execute_cmd() {
+    let matches = app().get_matches();
+    // the unlock and the agents it starts read the keyfile from the environment.
+    if let Some(path) = matches.value_of("keyfile") {
+        env::set_var(KEYFILE_ENV, path);
+    }
+    if let Some(name) = matches.value_of("vault") {
+        env::set_var(vault::ENV, name);
+    }
+    if let Some(path) = matches.value_of("config") {
+        env::set_var(config::ENV, path);
+    }
+    // commands reading the config fail on an invalid one, `config` fixes it.
+    let format = match matches.value_of("format") {
+        Some(name) => Format::parse(name).unwrap_or(Format::Text),
+        None => Config::load().map_or(Format::Text, |config| config.format()),
+    };
+
+    match execute_cmd(&matches, format) {
         Ok(_) => {}
         Err(e) => {
-            println!("{:?}", e);
+            output::print_error(&e, format);
+            process::exit(output::exit_code(&e));
         }
     };
 }
@@ -0,0 +1,231 @@
+//! 1Password `.1pux` exports.
+//!
+//! Items of every category are imported with the fields of their sections,
+//! and the vault of an item is its folder. A one-time password is the `otp`
+//! field and an SSH key the `ssh_key` field. Archived items are left out.
+use std::io::{Cursor, Read};
+use serde_json::{self, Value};
+use zip::ZipArchive;
+use import::Item;
+use secret::{SecretBytes, SecretString};
+use sshagent::KEY_FIELD;
+use errors::*;
+
+const DATA_FILE: &str = "export.data";
+
+#[derive(Deserialize)]
+struct Export {
+    accounts: Vec<Account>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    vaults: Vec<Vault>,
+}
+
+#[derive(Deserialize)]
+struct Vault {
+    attrs: VaultAttrs,
+    items: Vec<ExportItem>,
+}
+
+#[derive(Deserialize)]
+struct VaultAttrs {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ExportItem {
+    #[serde(default)]
+    state: String,
+    details: Details,
+    overview: Overview,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Details {
+    #[serde(default)]
+    login_fields: Vec<LoginField>,
+    notes_plain: Option<SecretString>,
+    #[serde(default)]
+    sections: Vec<Section>,
+    /// the password of items of the password category.
+    password: Option<SecretString>,
+}
+
+#[derive(Deserialize)]
+struct LoginField {
+    value: SecretString,
+    #[serde(default)]
+    name: String,
+    designation: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Section {
+    #[serde(default)]
+    fields: Vec<SectionField>,
+}
+
+#[derive(Deserialize)]
+struct SectionField {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    id: String,
+    value: Value,
+}
+
+#[derive(Deserialize)]
+struct Overview {
+    #[serde(default)]
+    title: String,
+    url: Option<String>,
+    #[serde(default)]
+    urls: Vec<Url>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Url {
+    url: String,
+}
+
+/// the text of a JSON value, empty for null and for objects.
+fn plain(value: &Value) -> String {
+    match *value {
+        Value::String(ref s) => s.clone(),
+        Value::Number(ref n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// the field name and text of a section field value like
+/// `{"concealed": "..."}`. The name is `None` for the title of the field.
+fn field_value(value: &Value) -> Option<(Option<&'static str>, String)> {
+    let (kind, inner) = match *value {
+        Value::Object(ref map) if map.len() == 1 => map.iter().next().unwrap(),
+        _ => return None,
+    };
+    let text = match (kind.as_str(), inner) {
+        ("totp", _) => return Some((Some("otp"), plain(inner))),
+        ("sshKey", Value::Object(key)) => {
+            return Some((Some(KEY_FIELD), key.get("privateKey").map(plain).unwrap_or_default()))
+        }
+        ("email", Value::Object(email)) => email.get("email_address").map(plain).unwrap_or_default(),
+        ("address", Value::Object(address)) => ["street", "city", "state", "zip", "country"]
+            .iter()
+            .filter_map(|part| address.get(*part).map(plain))
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<String>>()
+            .join(", "),
+        ("monthYear", Value::Number(n)) => {
+            let n = n.as_u64().unwrap_or(0);
+            format!("{:02}/{}", n % 100, n / 100)
+        }
+        _ => plain(inner),
+    };
+    Some((None, text))
+}
+
+fn to_item(exported: ExportItem, vault: &str) -> Item {
+    let details = exported.details;
+    let overview = exported.overview;
+    let mut item = Item {
+        folder: String::from(vault),
+        name: overview.title,
+        password: details.password.unwrap_or_default(),
+        notes: details.notes_plain.unwrap_or_default(),
+        tags: overview.tags,
+        ..Item::default()
+    };
+
+    for field in details.login_fields {
+        match field.designation.as_deref() {
+            Some("username") => item.user = String::from(field.value.expose()),
+            Some("password") => item.password = field.value,
+            _ => item.add_field(&field.name, field.value),
+        }
+    }
+
+    let urls: Vec<String> = if overview.urls.is_empty() {
+        overview.url.into_iter().collect()
+    } else {
+        overview.urls.into_iter().map(|u| u.url).collect()
+    };
+    for url in urls {
+        item.add_field("url", SecretString::new(url));
+    }
+
+    for field in details.sections.into_iter().flat_map(|s| s.fields) {
+        if let Some((name, text)) = field_value(&field.value) {
+            let title = if field.title.is_empty() { &field.id } else { &field.title };
+            item.add_field(name.unwrap_or(title), SecretString::new(text));
+        }
+    }
+    item
+}
+
+/// read the items of a `.1pux` archive.
+pub fn read(data: &[u8]) -> Result<Vec<Item>> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).chain_err(|| "not a 1pux archive")?;
+    let mut content = Vec::new();
+    archive
+        .by_name(DATA_FILE)
+        .chain_err(|| format!("no {} in the archive", DATA_FILE))?
+        .read_to_end(&mut content)
+        .chain_err(|| format!("can't read {}", DATA_FILE))?;
+    let content = SecretBytes::new(content);
+    let export: Export = serde_json::from_slice(content.expose()).chain_err(|| format!("invalid {}", DATA_FILE))?;
+
+    let mut items = Vec::new();
+    for vault in export.accounts.into_iter().flat_map(|a| a.vaults) {
+        for exported in vault.items {
+            if exported.state != "archived" {
+                items.push(to_item(exported, &vault.attrs.name));
+            }
+        }
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod test {
+    use onepassword::*;
+
+    #[test]
+    fn onepux_test() {
+        let items = read(include_bytes!("../testdata/export.1pux")).unwrap();
+        let entries: Vec<_> = items.into_iter().map(Item::into_entry).collect();
+        let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(
+            vec!["Private/GitHub", "Private/Visa", "Shared/me", "Shared/wifi", "Shared/router"],
+            titles
+        );
+
+        let github = &entries[0].auth_info;
+        assert_eq!("alice", github.user);
+        assert_eq!("s3cret", github.password.expose());
+        assert_eq!("memo", github.other.expose());
+        assert_eq!(vec!["work", "dev"], github.tags);
+        assert_eq!("https://gist.github.com", github.fields["url (2)"].expose());
+        assert_eq!("otpauth://totp/github?secret=JBSWY3DP", github.fields["otp"].expose());
+        assert_eq!("r-e-c", github.fields["recovery"].expose());
+        assert_eq!("untitled field", github.fields["custom1"].expose());
+        assert!(!github.fields.contains_key("remember"));
+
+        let card = &entries[1].auth_info.fields;
+        assert_eq!("4111111111111111", card["number"].expose());
+        assert_eq!("04/2030", card["expiry date"].expose());
+        let me = &entries[2].auth_info.fields;
+        assert_eq!("alice@example.com", me["email"].expose());
+        assert_eq!("1 Main St, Oxford, OX1, uk", me["address"].expose());
+        assert!(!me.contains_key("birth date"));
+        assert_eq!("routerpw", entries[4].auth_info.password.expose());
+
+        assert!(read(b"not a zip").is_err());
+    }
+}
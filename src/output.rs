@@ -0,0 +1,347 @@
+//! Machine-readable output of the read commands and of errors.
+//!
+//! With `--format json` or `--format yaml` every command prints one document
+//! on stdout, and a failure prints one document on stderr. The schema is
+//! stable: fields are only ever added.
+//!
+//! `show` prints `{"entry": ENTRY}` and `list` prints `{"entries": [ENTRY, ...]}`.
+//! `ENTRY` always has `title`, `user`, `field_names` (the sorted names of
+//! the custom fields), `tags` and `attachment_names`. With `--reveal` it also
+//! has `password`, `other` and `fields` (a map from field name to value);
+//! secrets are never printed otherwise, and the text of `show` masks them.
+//!
+//! `log` prints `{"records": [RECORD, ...]}`. `RECORD` has `time` (seconds
+//! since the unix epoch), `host`, `os_user`, `action` (`add`, `change` or
+//! `remove`), `entry`, `fields` (the names of the fields changed) and `prev`
+//! (the hash chaining it to the record before), plus `from` for a rename.
+//!
+//! An error prints `{"error": {"code": CODE, "message": MESSAGE, "causes": [...]}}`,
+//! plus `candidates` for `ambiguous_entry`. `CODE` and the exit status are:
+//!
+//! | code              | exit |
+//! |-------------------|------|
+//! | `error`           | 1    |
+//! | `entry_not_found` | 2    |
+//! | `ambiguous_entry` | 3    |
+//! | `vault_locked`    | 4    |
+//! | `vault_busy`      | 5    |
+//! | `vault_changed`   | 6    |
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use serde::Serialize;
+use serde_json;
+use serde_yaml;
+use audit::Record;
+use clock;
+use entrystore::Entry;
+use secret::SecretString;
+use errors::*;
+
+/// what the text of `show` prints instead of a secret.
+const MASK: &str = "********";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Text,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    pub const NAMES: &'static [&'static str] = &["text", "json", "yaml"];
+
+    pub fn parse(name: &str) -> Result<Format> {
+        match name {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "yaml" => Ok(Format::Yaml),
+            _ => bail!("unknown format [{}]", name),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct EntryView<'a> {
+    title: &'a str,
+    user: &'a str,
+    field_names: Vec<&'a str>,
+    tags: &'a [String],
+    attachment_names: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    other: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<BTreeMap<&'a str, &'a str>>,
+}
+
+impl<'a> EntryView<'a> {
+    pub fn new(entry: &'a Entry, reveal: bool) -> EntryView<'a> {
+        let auth = &entry.auth_info;
+        EntryView {
+            title: &entry.title,
+            user: &auth.user,
+            field_names: auth.fields.keys().map(|k| k.as_str()).collect(),
+            tags: &auth.tags,
+            attachment_names: auth.attachments.keys().map(|k| k.as_str()).collect(),
+            password: if reveal { Some(auth.password.expose()) } else { None },
+            other: if reveal { Some(auth.other.expose()) } else { None },
+            fields: if reveal {
+                Some(auth.fields.iter().map(|(k, v)| (k.as_str(), v.expose())).collect())
+            } else {
+                None
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ShowDocument<'a> {
+    entry: EntryView<'a>,
+}
+
+#[derive(Serialize)]
+struct ListDocument<'a> {
+    entries: Vec<EntryView<'a>>,
+}
+
+#[derive(Serialize)]
+struct LogDocument<'a> {
+    records: &'a [Record],
+}
+
+#[derive(Serialize)]
+struct ErrorView {
+    code: &'static str,
+    message: String,
+    causes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    candidates: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct ErrorDocument {
+    error: ErrorView,
+}
+
+fn write_document<W: Write, T: Serialize>(out: &mut W, format: Format, doc: &T) -> Result<()> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, doc).chain_err(|| "can't write json")?;
+            writeln!(out).chain_err(|| "can't write to stdout.")
+        }
+        Format::Yaml => serde_yaml::to_writer(&mut *out, doc).chain_err(|| "can't write yaml"),
+        Format::Text => bail!("text is not a document format"),
+    }
+}
+
+/// print one entry. Secrets are only printed with `reveal`.
+pub fn print_entry(entry: &Entry, format: Format, reveal: bool) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Text => write_entry_text(&mut out, entry, reveal),
+        _ => write_document(
+            &mut out,
+            format,
+            &ShowDocument {
+                entry: EntryView::new(entry, reveal),
+            },
+        ),
+    }
+}
+
+/// the text of `show`, with every secret value masked unless `reveal`.
+fn write_entry_text<W: Write>(out: &mut W, entry: &Entry, reveal: bool) -> Result<()> {
+    if reveal {
+        return writeln!(out, "{}", entry).chain_err(|| "can't write to stdout.");
+    }
+
+    let mut masked = entry.clone();
+    {
+        let auth = &mut masked.auth_info;
+        auth.password = mask(&auth.password);
+        auth.other = mask(&auth.other);
+        for value in auth.fields.values_mut() {
+            *value = mask(value);
+        }
+    }
+    writeln!(out, "{}", masked).chain_err(|| "can't write to stdout.")
+}
+
+fn mask(secret: &SecretString) -> SecretString {
+    if secret.is_empty() {
+        SecretString::default()
+    } else {
+        SecretString::from(MASK)
+    }
+}
+
+/// print entries sorted by title. Text prints one title per line.
+pub fn print_entries(entries: &[Entry], format: Format, reveal: bool) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Text => {
+            for entry in entries {
+                writeln!(out, "{}", entry.title).chain_err(|| "can't write to stdout.")?;
+            }
+            Ok(())
+        }
+        _ => write_document(
+            &mut out,
+            format,
+            &ListDocument {
+                entries: entries.iter().map(|e| EntryView::new(e, reveal)).collect(),
+            },
+        ),
+    }
+}
+
+/// print records of the audit log. Text prints one record per line.
+pub fn print_records(records: &[Record], format: Format) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Text => {
+            for r in records {
+                let entry = match r.from {
+                    Some(ref from) => format!("{} (was {})", r.entry, from),
+                    None => r.entry.clone(),
+                };
+                let line = format!(
+                    "{} UTC  {}@{}  {}  {}  {}",
+                    clock::format_time(r.time),
+                    r.os_user,
+                    r.host,
+                    r.action.name(),
+                    entry,
+                    r.fields.join(", ")
+                );
+                writeln!(out, "{}", line.trim_end()).chain_err(|| "can't write to stdout.")?;
+            }
+            Ok(())
+        }
+        _ => write_document(&mut out, format, &LogDocument { records }),
+    }
+}
+
+/// the stable code of an error, see the module documentation.
+pub fn error_code(e: &Error) -> &'static str {
+    match *e.kind() {
+        ErrorKind::EntryNotFound(_) => "entry_not_found",
+        ErrorKind::AmbiguousEntry(_) => "ambiguous_entry",
+        ErrorKind::VaultLocked => "vault_locked",
+        ErrorKind::VaultBusy(..) => "vault_busy",
+        ErrorKind::VaultChanged => "vault_changed",
+        _ => "error",
+    }
+}
+
+/// the exit status of an error, see the module documentation.
+pub fn exit_code(e: &Error) -> i32 {
+    match *e.kind() {
+        ErrorKind::EntryNotFound(_) => 2,
+        ErrorKind::AmbiguousEntry(_) => 3,
+        ErrorKind::VaultLocked => 4,
+        ErrorKind::VaultBusy(..) => 5,
+        ErrorKind::VaultChanged => 6,
+        _ => 1,
+    }
+}
+
+/// report an error on stderr.
+pub fn print_error(e: &Error, format: Format) {
+    let stderr = io::stderr();
+    let mut err = stderr.lock();
+    if format == Format::Text {
+        let _ = writeln!(err, "error: {}", e);
+        for cause in e.iter().skip(1) {
+            let _ = writeln!(err, "caused by: {}", cause);
+        }
+        return;
+    }
+
+    let candidates = match *e.kind() {
+        ErrorKind::AmbiguousEntry(ref titles) => Some(titles.clone()),
+        _ => None,
+    };
+    let doc = ErrorDocument {
+        error: ErrorView {
+            code: error_code(e),
+            message: e.to_string(),
+            causes: e.iter().skip(1).map(|c| c.to_string()).collect(),
+            candidates,
+        },
+    };
+    let _ = write_document(&mut err, format, &doc);
+}
+
+#[cfg(test)]
+mod test {
+    use output::*;
+    use entrystore::AuthInfo;
+    use serde_json;
+
+    fn entry() -> Entry {
+        let mut auth = AuthInfo::new("bob", SecretString::from("hunter2"), SecretString::from("memo"));
+        auth.set_field("url", SecretString::from("https://example.com")).unwrap();
+        Entry::new("github", auth)
+    }
+
+    #[test]
+    fn entry_view_hides_secrets_test() {
+        let entry = entry();
+        let json = serde_json::to_string(&EntryView::new(&entry, false)).unwrap();
+        assert_eq!(r#"{"title":"github","user":"bob","field_names":["url"],"tags":[],"attachment_names":[]}"#, json);
+    }
+
+    #[test]
+    fn entry_view_reveal_test() {
+        let entry = entry();
+        let json = serde_json::to_string(&EntryView::new(&entry, true)).unwrap();
+        assert_eq!(
+            r#"{"title":"github","user":"bob","field_names":["url"],"tags":[],"attachment_names":[],"password":"hunter2","other":"memo","fields":{"url":"https://example.com"}}"#,
+            json
+        );
+    }
+
+    #[test]
+    fn entry_text_test() {
+        let mut out = Vec::new();
+        write_entry_text(&mut out, &entry(), false).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("user: bob\n"));
+        assert!(text.contains("password: ********\n"));
+        assert!(text.contains("url: ********\n"));
+        assert!(!text.contains("hunter2") && !text.contains("memo") && !text.contains("example.com"));
+
+        let mut out = Vec::new();
+        write_entry_text(&mut out, &entry(), true).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("password: hunter2\n"));
+        assert!(text.contains("other: memo\n"));
+        assert!(text.contains("url: https://example.com\n"));
+    }
+
+    #[test]
+    fn error_code_test() {
+        let e: Error = ErrorKind::AmbiguousEntry(vec![String::from("a")]).into();
+        assert_eq!("ambiguous_entry", error_code(&e));
+        assert_eq!(3, exit_code(&e));
+        let e: Error = ErrorKind::VaultChanged.into();
+        assert_eq!("vault_changed", error_code(&e));
+        assert_eq!(6, exit_code(&e));
+        let e: Error = "other".into();
+        assert_eq!("error", error_code(&e));
+        assert_eq!(1, exit_code(&e));
+    }
+}
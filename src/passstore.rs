@@ -0,0 +1,193 @@
+//! Stores of `pass`, the standard unix password manager: a tree of files
+//! encrypted by GnuPG.
+//!
+//! `Work/db.gpg` is imported as the entry `Work/db`. The first line of a file
+//! is the password and the later `key: value` lines are fields, where `user`,
+//! `username` and `login` are the user and an `otpauth://` line is `otp`.
+//! Any other line, including a `title`, `password` or `other` one, goes to
+//! the other of the entry.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use entrystore::{AuthInfo, Entry};
+use secret::{SecretBytes, SecretString};
+use errors::*;
+
+pub const DEFAULT_DECRYPT_COMMAND: &str = "gpg --decrypt";
+
+/// the `.gpg` files under `dir` by title, sorted. Hidden files and
+/// directories like `.git` are left out, and symlinked directories aren't
+/// followed, they could loop.
+fn find_files(dir: &Path, folder: &str, files: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    let mut paths = Vec::new();
+    for dir_entry in fs::read_dir(dir).chain_err(|| format!("can't read [{}]", dir.display()))? {
+        paths.push(dir_entry.chain_err(|| format!("can't read [{}]", dir.display()))?.path());
+    }
+    paths.sort();
+    for path in paths {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if !name.starts_with('.') => String::from(name),
+            _ => continue,
+        };
+        let file_type = fs::symlink_metadata(&path)
+            .chain_err(|| format!("can't read [{}]", path.display()))?
+            .file_type();
+        if file_type.is_dir() {
+            find_files(&path, &format!("{}{}/", folder, name), files)?;
+        } else if name.ends_with(".gpg") && path.is_file() {
+            files.push((format!("{}{}", folder, name.trim_end_matches(".gpg")), path));
+        }
+    }
+    Ok(())
+}
+
+/// run `command` with `path` appended and return what it prints.
+fn decrypt(command: &[&str], path: &Path) -> Result<SecretString> {
+    let output = Command::new(command[0])
+        .args(&command[1..])
+        .arg(path)
+        .stderr(Stdio::inherit())
+        .output()
+        .chain_err(|| format!("can't start [{}]", command[0]))?;
+    let stdout = SecretBytes::new(output.stdout);
+    if !output.status.success() {
+        bail!("[{}] failed on [{}]", command.join(" "), path.display());
+    }
+    match ::std::str::from_utf8(stdout.expose()) {
+        Ok(text) => Ok(SecretString::from(text)),
+        Err(_) => bail!("[{}] is not text", path.display()),
+    }
+}
+
+/// the entry of the decrypted content of a file.
+pub fn parse(content: &str) -> AuthInfo {
+    let mut lines = content.lines();
+    let mut auth = AuthInfo::blank();
+    auth.password = SecretString::from(lines.next().unwrap_or(""));
+
+    let mut other = Vec::new();
+    for line in lines {
+        if line.starts_with("otpauth://") {
+            auth.fields.insert(String::from("otp"), SecretString::from(line));
+            continue;
+        }
+        let (key, value) = match line.find(": ") {
+            Some(i) if !line[..i].is_empty() && !line[..i].contains(char::is_whitespace) => {
+                (&line[..i], line[i + 2..].trim())
+            }
+            _ => {
+                other.push(line);
+                continue;
+            }
+        };
+        match key.to_lowercase().as_str() {
+            "user" | "username" | "login" => auth.user = String::from(value),
+            "title" | "password" | "other" => other.push(line),
+            _ => {
+                auth.fields.insert(String::from(key), SecretString::from(value));
+            }
+        }
+    }
+    auth.other = SecretString::new(other.join("\n").trim().to_owned());
+    auth
+}
+
+/// read every entry of the store in `dir`, decrypting files with
+/// `command`, a program and its arguments split on whitespace. There is no
+/// quoting, so none of them can contain a space.
+pub fn read(dir: &Path, command: &str) -> Result<Vec<Entry>> {
+    let command: Vec<&str> = command.split_whitespace().collect();
+    if command.is_empty() {
+        bail!("the decrypt command is empty");
+    }
+    let mut files = Vec::new();
+    find_files(dir, "", &mut files)?;
+
+    let mut entries = Vec::new();
+    for (title, path) in files {
+        let content = decrypt(&command, &path)?;
+        entries.push(Entry::new(&title, parse(content.expose())));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use passstore::*;
+    use testutil::TempDir;
+
+    #[test]
+    fn parse_test() {
+        let auth = parse("s3cret\nlogin: alice\nurl: https://x.com\nnote line\notpauth://totp/x?secret=AB\nother: memo\n");
+        assert_eq!("s3cret", auth.password.expose());
+        assert_eq!("alice", auth.user);
+        assert_eq!("https://x.com", auth.fields["url"].expose());
+        assert_eq!("otpauth://totp/x?secret=AB", auth.fields["otp"].expose());
+        assert_eq!("note line\nother: memo", auth.other.expose());
+        assert!(!auth.fields.contains_key("other"));
+    }
+
+    #[test]
+    fn symlink_test() {
+        let dir = TempDir::new("pass-links");
+        fs::create_dir_all(dir.join("Work")).unwrap();
+        fs::write(dir.join("Work/db.gpg"), b"").unwrap();
+        ::std::os::unix::fs::symlink(&*dir, dir.join("Work/loop")).unwrap();
+        ::std::os::unix::fs::symlink(dir.join("Work/db.gpg"), dir.join("db.gpg")).unwrap();
+
+        let mut files = Vec::new();
+        find_files(&dir, "", &mut files).unwrap();
+        let titles: Vec<&str> = files.iter().map(|f| f.0.as_str()).collect();
+        assert_eq!(vec!["Work/db", "db"], titles);
+    }
+
+    fn gpg(home: &Path, args: &[&str], input: &[u8]) {
+        let mut child = Command::new("gpg")
+            .arg("--homedir")
+            .arg(home)
+            .args(["--batch", "--quiet", "--pinentry-mode", "loopback", "--passphrase", ""])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    #[ignore = "needs gpg"]
+    fn gpg_store_test() {
+        let dir = TempDir::new("pass");
+        let (home, store) = (dir.join("gnupg"), dir.join("store"));
+        fs::create_dir_all(home.join("private-keys-v1.d")).unwrap();
+        fs::set_permissions(&home, fs::Permissions::from_mode(0o700)).unwrap();
+        fs::create_dir_all(store.join("Work/.hidden")).unwrap();
+        fs::create_dir_all(store.join(".git")).unwrap();
+        gpg(&home, &["--quick-gen-key", "test@example.com", "future-default", "default", "never"], b"");
+
+        let encrypt = |path: &str, content: &str| {
+            let out = store.join(path);
+            let out = out.to_str().unwrap();
+            gpg(&home, &["-r", "test@example.com", "-o", out, "--encrypt"], content.as_bytes());
+        };
+        encrypt("github.gpg", "s3cret\nuser: alice\n");
+        encrypt("Work/db.gpg", "dbpw\n");
+        encrypt("Work/.hidden/x.gpg", "no\n");
+        encrypt(".git/y.gpg", "no\n");
+
+        let command = format!("gpg --homedir {} --batch --quiet --decrypt", home.display());
+        let entries = read(&store, &command);
+        let failed = read(&store, "false");
+        let _ = Command::new("gpgconf").arg("--homedir").arg(&home).args(["--kill", "all"]).status();
+        let entries = entries.unwrap();
+        assert!(failed.is_err());
+
+        let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(vec!["Work/db", "github"], titles);
+        assert_eq!("dbpw", entries[0].auth_info.password.expose());
+        assert_eq!("alice", entries[1].auth_info.user);
+    }
+}
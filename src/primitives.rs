@@ -0,0 +1,81 @@
+//! The cryptographic primitives shared by the vault and the file formats.
+use argon2::{self, Argon2};
+use crypto::aes::{self, KeySize};
+use crypto::blockmodes::PkcsPadding;
+use crypto::buffer::{BufferResult, ReadBuffer, RefReadBuffer, RefWriteBuffer, WriteBuffer};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use secret::{self, SecretBytes};
+use errors::*;
+
+/// The most expensive key derivation accepted from a file, well above what
+/// KeePass, KeePassXC and Bitwarden pick, so that a crafted file can't hang
+/// or exhaust the machine. The Argon2 memory is in KiB.
+pub const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+pub const MAX_ARGON2_MEMORY: u64 = 1024 * 1024;
+pub const MAX_ARGON2_ITERATIONS: u64 = 100;
+pub const MAX_ARGON2_LANES: u64 = 255;
+
+/// fail if Argon2 of `memory` KiB, `iterations` and `lanes` is above the limits.
+pub fn check_argon2(memory: u64, iterations: u64, lanes: u64) -> Result<()> {
+    if memory > MAX_ARGON2_MEMORY || iterations > MAX_ARGON2_ITERATIONS || lanes > MAX_ARGON2_LANES {
+        bail!(
+            "the kdf asks for {} MiB, {} iterations and {} lanes, at most {} MiB, {} and {} are accepted",
+            memory / 1024,
+            iterations,
+            lanes,
+            MAX_ARGON2_MEMORY / 1024,
+            MAX_ARGON2_ITERATIONS,
+            MAX_ARGON2_LANES
+        );
+    }
+    Ok(())
+}
+
+/// the SHA-256 of the concatenated `parts`.
+pub fn sha256(parts: &[&[u8]]) -> SecretBytes {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.input(part);
+    }
+    let mut out = SecretBytes::new(vec![0; 32]);
+    hasher.result(out.expose_mut());
+    out
+}
+
+/// a 32 byte key of `secret` with Argon2id of memory in KiB, iterations and lanes.
+pub fn argon2id(secret: &[u8], salt: &[u8], (memory, iterations, lanes): (u32, u32, u32)) -> Result<SecretBytes> {
+    let params = argon2::Params::new(memory, iterations, lanes, Some(32))
+        .map_err(|e| Error::from(format!("invalid argon2 parameters: {}", e)))?;
+    let mut key = SecretBytes::new(vec![0; 32]);
+    Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(secret, salt, key.expose_mut())
+        .map_err(|e| Error::from(format!("argon2 failed: {}", e)))?;
+    Ok(key)
+}
+
+/// AES-256-CBC with PKCS#7 padding, as KeePass and Bitwarden use it.
+pub fn decrypt_aes(key: &[u8], iv: &[u8], data: &[u8]) -> Result<SecretBytes> {
+    let mut decryptor = aes::cbc_decryptor(KeySize::KeySize256, key, iv, PkcsPadding);
+    let mut decrypted = Vec::with_capacity(data.len());
+    let mut buffer = [0; 4096];
+    {
+        let mut read_buffer = RefReadBuffer::new(data);
+        let mut write_buffer = RefWriteBuffer::new(&mut buffer);
+        loop {
+            let result = match decryptor.decrypt(&mut read_buffer, &mut write_buffer, true) {
+                Ok(result) => result,
+                Err(_) => {
+                    secret::wipe(&mut decrypted);
+                    bail!("wrong password");
+                }
+            };
+            decrypted.extend(write_buffer.take_read_buffer().take_remaining().iter());
+            if let BufferResult::BufferUnderflow = result {
+                break;
+            }
+        }
+    }
+    secret::wipe(&mut buffer);
+    Ok(SecretBytes::new(decrypted))
+}
@@ -1,4 +1,4 @@
-use std::io::{stdin, stdout};
+use std::io::{stderr, stdin};
 use std::io::Write;
 use rand::{OsRng, Rng};
 use crypto::{aes, blockmodes};
@@ -11,11 +11,25 @@ use crypto::digest::Digest;
 use rpassword::read_password;
 use rawio::RawStore;
 use entrystore::{Cipher, CliEntrySelector};
+use secret::{self, SecretBytes, SecretString};
 use errors::*;
 
+/// PBKDF2 iterations of the vault key.
+pub const ITERATIONS: u32 = 1000;
+/// AES-128 only uses 16 bytes of the key, and PBKDF2 derives the same first
+/// bytes whatever the length asked.
+pub const KEY_LEN: usize = 16;
+
+/// the salt of the vault key of `user`: the first 8 hex digits of its MD5.
+pub fn salt(user: &[u8]) -> Vec<u8> {
+    let mut md5 = Md5::new();
+    md5.input(user);
+    Vec::from(&md5.result_str().as_bytes()[..8])
+}
+
 pub trait KeyInput {
     fn required(&self, name: &str) -> Result<Vec<u8>>;
-    fn get_password(&self) -> Result<Vec<u8>>;
+    fn get_password(&self) -> Result<SecretBytes>;
 }
 
 pub struct KeyProvider<'a, T: 'a + KeyInput, RW: 'a + RawStore> {
@@ -30,50 +44,56 @@ where
 {
     pub fn new(input: &'a T, name_holder: &'a mut RW) -> KeyProvider<'a, T, RW> {
         KeyProvider {
-            name_holder: name_holder,
-            input: input,
+            name_holder,
+            input,
         }
     }
 
     /// get password and salt
-    pub fn get(&mut self) -> Result<(Vec<u8>, Vec<u8>)> {
+    pub fn get(&mut self) -> Result<(SecretBytes, Vec<u8>)> {
         let mut user_name = Vec::new();
         let _ = self.name_holder.read_to_end(&mut user_name);
-        if user_name.len() == 0 {
+        if user_name.is_empty() {
             user_name = self.input.required("user name")?;
-            let _ = self.name_holder.write_all(&user_name.as_ref());
+            let _ = self.name_holder.write_all(&user_name);
         }
 
-        let mut salt: [u8; 8] = [0; 8];
-        self.make_salt(user_name.as_ref(), &mut salt);
-
         let password = self.input.get_password()?;
-        Ok((password, Vec::from(&salt as &[u8])))
-    }
-
-    fn make_salt(&self, source: &[u8], dest: &mut [u8; 8]) {
-        let mut md5 = Md5::new();
-        md5.input(source);
-        let salt = &md5.result_str()[..8];
-        dest.clone_from_slice(salt.as_bytes());
+        Ok((password, salt(&user_name)))
     }
 }
 
 pub struct AesCipher {
-    key: Vec<u8>,
+    key: SecretBytes,
 }
 
 impl AesCipher {
     pub fn new(password: &[u8], salt: &[u8]) -> AesCipher {
-        let key = Self::gen_key(password, salt);
-        AesCipher { key: key }
+        let mut key = Self::gen_key(password, salt);
+        key.lock_memory();
+        AesCipher { key }
+    }
+
+    /// a cipher with a key derived before, e.g. typed from a recovery sheet.
+    pub fn from_key(mut key: SecretBytes) -> AesCipher {
+        key.lock_memory();
+        AesCipher { key }
+    }
+
+    pub fn key(&self) -> &SecretBytes {
+        &self.key
     }
 
-    fn gen_key(password: &[u8], salt: &[u8]) -> Vec<u8> {
+    /// keep the key out of swap. Failure is ignored as the limit may be too small.
+    pub fn lock_memory(&mut self) {
+        self.key.lock_memory();
+    }
+
+    fn gen_key(password: &[u8], salt: &[u8]) -> SecretBytes {
         let mut mac = Hmac::new(Sha1::new(), password);
-        let mut key: [u8; 128] = [0; 128];
-        pbkdf2::pbkdf2(&mut mac, &salt, 1000, &mut key);
-        Vec::<u8>::from(key.as_ref())
+        let mut key = SecretBytes::new(vec![0; KEY_LEN]);
+        pbkdf2::pbkdf2(&mut mac, salt, ITERATIONS, key.expose_mut());
+        key
     }
 }
 
@@ -85,8 +105,8 @@ impl Cipher for AesCipher {
 
         let mut enc = aes::cbc_encryptor(
             aes::KeySize::KeySize128,
-            &self.key,
-            &mut iv,
+            self.key.expose(),
+            &iv,
             blockmodes::PkcsPadding,
         );
 
@@ -106,7 +126,7 @@ impl Cipher for AesCipher {
                     .take_read_buffer()
                     .take_remaining()
                     .iter()
-                    .map(|&i| i),
+                    .copied(),
             );
 
             match result {
@@ -120,74 +140,84 @@ impl Cipher for AesCipher {
         Ok(final_result)
     }
 
-    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+    fn decrypt(&self, data: &[u8]) -> Result<SecretBytes> {
+        if data.len() < 16 {
+            bail!("failed to decrypt");
+        }
         let iv: &[u8] = &data[0..16];
         let mut decryptor = aes::cbc_decryptor(
             aes::KeySize::KeySize128,
-            &self.key,
-            &iv,
+            self.key.expose(),
+            iv,
             blockmodes::PkcsPadding,
         );
 
-        let mut decrypted = Vec::<u8>::new();
-        let mut read_buffer = RefReadBuffer::new(&data[16..]);
+        // the plain text is never longer than the cipher text, so this never reallocates.
+        let mut decrypted = Vec::<u8>::with_capacity(data.len());
         let mut buffer = [0; 4096];
-        let mut write_buffer = RefWriteBuffer::new(&mut buffer);
-
-        loop {
-            let result = match decryptor.decrypt(&mut read_buffer, &mut write_buffer, true) {
-                Ok(bresult) => bresult,
-                Err(_) => bail!("failed to decrypt"),
-            };
-            decrypted.extend(
-                write_buffer
-                    .take_read_buffer()
-                    .take_remaining()
-                    .iter()
-                    .map(|&i| i),
-            );
-
-            match result {
-                BufferResult::BufferUnderflow => break,
-                BufferResult::BufferOverflow => {}
+        {
+            let mut read_buffer = RefReadBuffer::new(&data[16..]);
+            let mut write_buffer = RefWriteBuffer::new(&mut buffer);
+
+            loop {
+                let result = match decryptor.decrypt(&mut read_buffer, &mut write_buffer, true) {
+                    Ok(bresult) => bresult,
+                    Err(_) => {
+                        secret::wipe(&mut decrypted);
+                        bail!("failed to decrypt")
+                    }
+                };
+                decrypted.extend(write_buffer.take_read_buffer().take_remaining().iter());
+
+                match result {
+                    BufferResult::BufferUnderflow => break,
+                    BufferResult::BufferOverflow => {}
+                }
             }
         }
+        secret::wipe(&mut buffer);
 
-        Ok(decrypted)
+        Ok(SecretBytes::new(decrypted))
     }
 }
 
+// The prompts go to stderr so they don't mix with json/yaml output on stdout.
 impl KeyInput for CliEntrySelector {
     fn required(&self, name: &str) -> Result<Vec<u8>> {
-        let mut sout = stdout();
+        let mut serr = stderr();
         let sin = stdin();
         let mut value = String::new();
 
         while value.len() <= 1 {
             value.clear();
-            sout.write_all([name, ": "].concat().as_bytes())
-                .chain_err(|| "can't write to stdout")?;
-            sout.flush().chain_err(|| "flush fail")?;
-            sin.read_line(&mut value)
+            serr.write_all([name, ": "].concat().as_bytes())
+                .chain_err(|| "can't write to stderr")?;
+            serr.flush().chain_err(|| "flush fail")?;
+            let size = sin.read_line(&mut value)
                 .chain_err(|| "can't read line from stdin")?;
+            if size == 0 {
+                bail!("unexpected end of input");
+            }
         }
 
-        Ok(Vec::from(value.as_str().trim_right().as_bytes()))
+        Ok(Vec::from(value.as_str().trim_end().as_bytes()))
     }
 
-    fn get_password(&self) -> Result<Vec<u8>> {
-        let mut sout = stdout();
-        let mut password = String::new();
+    fn get_password(&self) -> Result<SecretBytes> {
+        let mut serr = stderr();
 
-        while password.len() == 0 {
-            sout.write_all("password: ".as_bytes())
+        loop {
+            serr.write_all("password: ".as_bytes())
                 .chain_err(|| "print exception")?;
-            sout.flush().chain_err(|| "print flush exception")?;
+            serr.flush().chain_err(|| "print flush exception")?;
 
-            password = read_password().chain_err(|| "failed to reading a password")?;
+            let password = SecretString::new(
+                read_password().chain_err(|| "failed to reading a password")?,
+            );
+            if !password.is_empty() {
+                return Ok(SecretBytes::from_slice(password.expose().as_bytes()));
+            }
         }
-
-        Ok(Vec::from(password.as_bytes()))
     }
 }
 
@@ -196,8 +226,6 @@ impl KeyInput for CliEntrySelector {
 mod test {
     use std;
     use pwdcrypto::*;
-    use rawio::*;
-    use errors::*;
 
     struct DummyKeyInput {
         key: String,
@@ -205,12 +233,12 @@ mod test {
     }
 
     impl KeyInput for DummyKeyInput {
-        fn required(&self, name: &str) -> Result<Vec<u8>> {
+        fn required(&self, _name: &str) -> Result<Vec<u8>> {
             Ok(Vec::from(self.key.as_str()))
         }
 
-        fn get_password(&self) -> Result<Vec<u8>> {
-            Ok(Vec::from(self.password.as_str()))
+        fn get_password(&self) -> Result<SecretBytes> {
+            Ok(SecretBytes::from_slice(self.password.as_bytes()))
         }
     }
 
@@ -232,7 +260,7 @@ mod test {
 
     #[test]
     fn keyprovider_test() {
-        let mut input = DummyKeyInput {
+        let input = DummyKeyInput {
             key: String::from("testuser"),
             password: String::from("password"),
         };
@@ -240,12 +268,12 @@ mod test {
             buf: Vec::from("sample"),
         };
 
-        let mut kp = KeyProvider::new(&mut input, &mut rw);
+        let mut kp = KeyProvider::new(&input, &mut rw);
         let (password, salt) = kp.get().unwrap();
 
         assert_eq!(
             "password",
-            std::str::from_utf8(password.as_slice()).unwrap()
+            std::str::from_utf8(password.expose()).unwrap()
         );
         assert_eq!("5e8ff9bf", std::str::from_utf8(salt.as_slice()).unwrap());
     }
@@ -259,6 +287,20 @@ mod test {
         let data = String::from("hello world, hello world, hello world, hello world, hello world, hello world}}{{");
         let encrypted = cipher.encrypt(data.as_bytes());
         let decrypted = cipher.decrypt(encrypted.unwrap().as_slice());
-        assert_eq!(decrypted.unwrap().as_slice(), data.as_bytes());
+        assert_eq!(decrypted.unwrap().expose(), data.as_bytes());
+    }
+
+    #[test]
+    fn key_len_test() {
+        // vaults saved with the 128 byte keys of earlier versions still open.
+        let mut mac = Hmac::new(Sha1::new(), b"pasword123");
+        let mut long_key = vec![0; 128];
+        pbkdf2::pbkdf2(&mut mac, b"salttlas", ITERATIONS, &mut long_key);
+        let cipher = AesCipher::new(b"pasword123", b"salttlas");
+        assert_eq!(&long_key[..KEY_LEN], cipher.key().expose());
+
+        let encrypted = cipher.encrypt(b"data").unwrap();
+        let recovered = AesCipher::from_key(SecretBytes::from_slice(&long_key[..KEY_LEN]));
+        assert_eq!(b"data", recovered.decrypt(&encrypted).unwrap().expose());
     }
 }
@@ -1,6 +1,12 @@
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::fs::{File, OpenOptions};
-use std::path::PathBuf;
+use std::io::{self, Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use audit;
+use lock::VaultLock;
+use primitives;
+use rotation::{self, Rotation};
+use sync::Repo;
 use errors::*;
 
 pub trait RawStore {
@@ -10,33 +16,180 @@ pub trait RawStore {
 
 pub struct FileRawStore {
     file: File,
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    lock: Option<VaultLock>,
+    sync: Option<Repo>,
+    /// the SHA-256 of the content last read or written, to refuse writing
+    /// over a change made by another process since.
+    loaded: Option<Vec<u8>>,
 }
 
 impl FileRawStore {
     pub fn new(path: PathBuf) -> Result<FileRawStore> {
-        let file: File = OpenOptions::new()
+        let file = FileRawStore::open(&path)?;
+        Ok(FileRawStore {
+            file,
+            path,
+            rotation: None,
+            lock: None,
+            sync: None,
+            loaded: None,
+        })
+    }
+
+    /// keep the previous content in `rotation` before each write.
+    pub fn with_rotation(mut self, rotation: Rotation) -> FileRawStore {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    /// hold `lock` until the store is dropped.
+    pub fn with_lock(mut self, lock: VaultLock) -> FileRawStore {
+        self.lock = Some(lock);
+        self
+    }
+
+    /// commit the file in `repo` after each write.
+    pub fn with_sync(mut self, repo: Repo) -> FileRawStore {
+        self.sync = Some(repo);
+        self
+    }
+
+    /// the content of the file at the path now, empty when there is none.
+    fn current(&self) -> Result<Vec<u8>> {
+        match fs::read(&self.path) {
+            Ok(content) => Ok(content),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e).chain_err(|| "can't read the previous content of the raw store file."),
+        }
+    }
+
+    fn open(path: &Path) -> Result<File> {
+        OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(path.as_path())
-            .chain_err(|| format!("can't open the raw store file [{}]", path.display()))?;
-        Ok(FileRawStore { file: file })
+            .truncate(false)
+            .open(path)
+            .chain_err(|| format!("can't open the raw store file [{}]", path.display()))
     }
 }
 
 impl RawStore for FileRawStore {
     fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
-        self.file
+        // reopened, another process may have replaced the file.
+        self.file = FileRawStore::open(&self.path)?;
+        let start = buf.len();
+        let size = self
+            .file
             .read_to_end(buf)
-            .chain_err(|| "can't read a buffer from the raw store file.")
+            .chain_err(|| "can't read a buffer from the raw store file.")?;
+        self.loaded = Some(primitives::sha256(&[&buf[start..]]).expose().to_vec());
+        Ok(size)
     }
 
     fn write_all(&mut self, buf: &[u8]) -> Result<()> {
-        self.file
-            .seek(SeekFrom::Start(0))
-            .chain_err(|| "failed to seek file")?;
-        self.file
-            .write_all(buf)
-            .chain_err(|| "can't write a buffer to the raw store file.")
+        // read at the path, another process may have replaced the file.
+        let previous = self.current()?;
+        if let Some(ref loaded) = self.loaded {
+            if *loaded != primitives::sha256(&[&previous]).expose() {
+                bail!(ErrorKind::VaultChanged);
+            }
+        }
+        if let Some(ref rotation) = self.rotation {
+            if !previous.is_empty() && previous != buf {
+                rotation.keep(&previous).chain_err(|| "can't back up the raw store file.")?;
+            }
+        }
+
+        // write a sibling then rename it, so that a failed write leaves the
+        // previous content whole.
+        let name = self.path.file_name().map_or_else(String::new, |n| n.to_string_lossy().into_owned());
+        let temp_path = self.path.with_file_name(format!(".{}.tmp", name));
+        let mut temp = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp_path)
+            .chain_err(|| format!("can't create [{}]", temp_path.display()))?;
+        temp.write_all(buf)
+            .and_then(|_| temp.sync_all())
+            .chain_err(|| "can't write a buffer to the raw store file.")?;
+        fs::rename(&temp_path, &self.path).chain_err(|| format!("can't replace [{}]", self.path.display()))?;
+        if let Some(dir) = self.path.parent() {
+            rotation::sync_dir(dir)?;
+        }
+        self.file = FileRawStore::open(&self.path)?;
+        self.loaded = Some(primitives::sha256(&[buf]).expose().to_vec());
+        if let Some(ref repo) = self.sync {
+            repo.commit(&format!("update {} on {}", name, audit::hostname()))
+                .chain_err(|| "the vault was saved, but it couldn't be committed for sync")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rawio::*;
+    use rotation::Retention;
+    use testutil::TempDir;
+
+    #[test]
+    fn rotation_test() {
+        let dir = TempDir::new("rawio");
+        let rotation = Rotation::new(dir.join("backups"), Retention::default());
+        let mut store = FileRawStore::new(dir.join("entries")).unwrap().with_rotation(rotation);
+        store.write_all(b"first").unwrap();
+        store.write_all(b"second").unwrap();
+        store.write_all(b"second").unwrap();
+
+        let mut content = Vec::new();
+        store.read_to_end(&mut content).unwrap();
+        assert_eq!(b"second".to_vec(), content);
+        let kept: Vec<_> = fs::read_dir(dir.join("backups")).unwrap().collect();
+        assert_eq!(1, kept.len());
+        assert_eq!(b"first".to_vec(), fs::read(kept[0].as_ref().unwrap().path()).unwrap());
+        assert!(!dir.join(".entries.tmp").exists());
+    }
+
+    #[test]
+    fn change_test() {
+        let dir = TempDir::new("rawio-change");
+        let path = dir.join("entries");
+        let mut first = FileRawStore::new(path.clone()).unwrap();
+        let mut second = FileRawStore::new(path.clone()).unwrap();
+        let mut content = Vec::new();
+        first.read_to_end(&mut content).unwrap();
+        second.read_to_end(&mut content).unwrap();
+
+        first.write_all(b"first").unwrap();
+        match second.write_all(b"second") {
+            Err(Error(ErrorKind::VaultChanged, _)) => {}
+            _ => panic!("the change of the first store should be detected"),
+        }
+        assert_eq!(b"first".to_vec(), fs::read(&path).unwrap());
+        first.write_all(b"again").unwrap();
+
+        let mut content = Vec::new();
+        second.read_to_end(&mut content).unwrap();
+        assert_eq!(b"again".to_vec(), content);
+        second.write_all(b"second").unwrap();
+    }
+
+    #[test]
+    fn shorter_content_test() {
+        let dir = TempDir::new("rawio-shorter");
+        let path = dir.join("entries");
+        let mut store = FileRawStore::new(path.clone()).unwrap();
+        store.write_all(b"the longer first content").unwrap();
+        store.write_all(b"short").unwrap();
+        assert_eq!(b"short".to_vec(), fs::read(&path).unwrap());
+
+        let mut content = Vec::new();
+        FileRawStore::new(path).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(b"short".to_vec(), content);
     }
 }
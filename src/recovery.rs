@@ -0,0 +1,270 @@
+//! Paper recovery sheets: the vault key written as words to type back in
+//! when the master password is lost.
+//!
+//! The 16 byte key is encoded as 12 BIP39 words, which carry a checksum of
+//! their own, printed 4 per line. Each line ends with a check of its number
+//! and its words, so that a mistyped line is caught on its own.
+use bip39::{Language, Mnemonic};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use clock;
+use secret::{SecretBytes, SecretString};
+use errors::*;
+
+pub const WORDS_PER_LINE: usize = 4;
+/// the lines of words of a key of `pwdcrypto::KEY_LEN` bytes.
+pub const LINES: usize = 3;
+
+/// A4 in PDF points, and the layout of the text on it.
+const PAGE: (u32, u32) = (595, 842);
+const MARGIN: u32 = 56;
+const FONT_SIZE: u32 = 10;
+const LEADING: u32 = 14;
+
+/// What a sheet is printed from.
+pub struct Sheet {
+    pub user: String,
+    /// seconds since the unix epoch.
+    pub created: u64,
+    pub key: SecretBytes,
+    /// how the key slots derive their keys, one line each.
+    pub kdf: Vec<String>,
+}
+
+/// the check of a line: 4 hex digits of the SHA-256 of its number and words.
+fn check(number: usize, words: &[&str]) -> String {
+    let mut sha = Sha256::new();
+    sha.input_str(&format!("{} {}", number, words.join(" ")));
+    sha.result_str()[..4].to_string()
+}
+
+/// the lines of words of `key`, each followed by its check.
+pub fn key_lines(key: &[u8]) -> Result<Vec<SecretString>> {
+    let mnemonic = Mnemonic::from_entropy(key).map_err(|e| Error::from(format!("can't encode the key: {}", e)))?;
+    let words: Vec<&str> = mnemonic.words().collect();
+    Ok(words
+        .chunks(WORDS_PER_LINE)
+        .enumerate()
+        .map(|(i, line)| SecretString::new(format!("{}  {}", line.join(" "), check(i + 1, line))))
+        .collect())
+}
+
+/// the words of line `number` as typed from a sheet, the words then the
+/// check. A word may be shortened to its first four letters, and the last
+/// line may hold fewer words.
+pub fn read_line(number: usize, typed: &str) -> Result<Vec<&'static str>> {
+    let typed = SecretString::new(typed.to_lowercase());
+    let parts: Vec<&str> = typed.expose().split_whitespace().collect();
+    if parts.len() < 2 || parts.len() > WORDS_PER_LINE + 1 {
+        bail!("expected up to {} words and a check", WORDS_PER_LINE);
+    }
+    let (check_part, word_parts) = parts.split_last().unwrap();
+    let mut words = Vec::new();
+    for part in word_parts {
+        let english = Language::English;
+        let word = match english.find_word(part) {
+            Some(i) => english.word_list()[i as usize],
+            None => match english.words_by_prefix(part) {
+                [word] if part.len() >= 4 => word,
+                _ => bail!("[{}] is not a word of the sheet", part),
+            },
+        };
+        words.push(word);
+    }
+    if check(number, &words) != *check_part {
+        bail!("the words don't match the check of line {}", number);
+    }
+    Ok(words)
+}
+
+/// the key of the words of every line.
+pub fn decode(words: &[&str]) -> Result<SecretBytes> {
+    let phrase = SecretString::new(words.join(" "));
+    let mnemonic = Mnemonic::parse_in_normalized(Language::English, phrase.expose())
+        .map_err(|e| Error::from(format!("the words don't make a key: {}", e)))?;
+    Ok(SecretBytes::new(mnemonic.to_entropy()))
+}
+
+impl Sheet {
+    fn lines(&self) -> Result<Vec<SecretString>> {
+        let mut lines: Vec<SecretString> = vec![
+            String::from("PWWR RECOVERY SHEET"),
+            format!("created {} UTC", clock::format_time(self.created)),
+            String::new(),
+            format!("vault user  {}", self.user),
+            String::from("key slots wrapping the vault key:"),
+        ]
+        .into_iter()
+        .chain(self.kdf.iter().map(|line| format!("  {}", line)))
+        .chain(vec![
+            String::new(),
+            String::from("vault key, 4 words then the check of the line:"),
+        ])
+        .map(SecretString::new)
+        .collect();
+        for (i, line) in key_lines(self.key.expose())?.into_iter().enumerate() {
+            lines.push(SecretString::new(format!("  {}  {}", i + 1, line.expose())));
+        }
+        lines.extend(
+            vec![
+                "",
+                "To open the vault without the master password:",
+                "  1. put the vault in ~/.pwwr, from this machine or a backup,",
+                "  2. run `pwwr recover` and type each line as printed, the",
+                "     words then the check. Words may be cut to 4 letters,",
+                "  3. choose a new master password, which replaces the",
+                "     password key slots.",
+                "",
+                "Anyone holding this sheet can open the vault. Keep it as safe",
+                "as the vault itself.",
+            ]
+            .into_iter()
+            .map(SecretString::from),
+        );
+        Ok(lines)
+    }
+
+    pub fn text(&self) -> Result<SecretString> {
+        let lines = self.lines()?;
+        let mut text = String::new();
+        for line in &lines {
+            text.push_str(line.expose());
+            text.push('\n');
+        }
+        Ok(SecretString::new(text))
+    }
+
+    pub fn svg(&self) -> Result<SecretString> {
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"210mm\" height=\"297mm\" viewBox=\"0 0 {} {}\">\n\
+             <rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n\
+             <g font-family=\"monospace\" font-size=\"{}\" xml:space=\"preserve\">\n",
+            PAGE.0, PAGE.1, FONT_SIZE
+        );
+        for (i, line) in self.lines()?.iter().enumerate() {
+            let escaped = line
+                .expose()
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            svg.push_str(&format!(
+                "<text x=\"{}\" y=\"{}\">{}</text>\n",
+                MARGIN,
+                MARGIN + (i as u32 + 1) * LEADING,
+                escaped
+            ));
+        }
+        svg.push_str("</g>\n</svg>\n");
+        Ok(SecretString::new(svg))
+    }
+
+    /// a one page PDF in Courier, the only font every reader has.
+    pub fn pdf(&self) -> Result<SecretBytes> {
+        let mut content = format!(
+            "BT /F1 {} Tf {} TL {} {} Td\n",
+            FONT_SIZE,
+            LEADING,
+            MARGIN,
+            PAGE.1 - MARGIN - LEADING
+        );
+        for line in self.lines()? {
+            let escaped: String = line
+                .expose()
+                .chars()
+                .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '?' })
+                .collect::<String>()
+                .replace('\\', "\\\\")
+                .replace('(', "\\(")
+                .replace(')', "\\)");
+            content.push_str(&format!("({}) Tj T*\n", escaped));
+        }
+        content.push_str("ET\n");
+
+        let objects = [
+            String::from("<< /Type /Catalog /Pages 2 0 R >>"),
+            String::from("<< /Type /Pages /Kids [3 0 R] /Count 1 >>"),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 5 0 R >> >> /Contents 4 0 R >>",
+                PAGE.0, PAGE.1
+            ),
+            format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content),
+            String::from("<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>"),
+        ];
+        let mut pdf = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+        }
+        let xref = pdf.len();
+        pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+        for offset in offsets {
+            pdf.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        pdf.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        ));
+        Ok(SecretBytes::new(pdf.into_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use recovery::*;
+
+    #[test]
+    fn key_lines_test() {
+        let key: Vec<u8> = (0..16).collect();
+        let lines = key_lines(&key).unwrap();
+        assert_eq!(LINES, lines.len());
+
+        let mut words = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            words.extend(read_line(i + 1, &line.expose().to_uppercase()).unwrap());
+        }
+        assert_eq!(key, decode(&words).unwrap().expose());
+
+        // shortened words are read, a wrong word, a wrong check and a line
+        // typed as another are not.
+        let first = lines[0].expose();
+        let short: Vec<String> = first.split(' ').map(|w| w.chars().take(4).collect()).collect();
+        assert_eq!(words[..4].to_vec(), read_line(1, &short.join(" ")).unwrap());
+        let english = Language::English;
+        let other = english.word_list()[(english.find_word(words[0]).unwrap() as usize + 1) % 2048];
+        assert!(read_line(1, &first.replacen(words[0], other, 1)).is_err());
+        let last = if first.ends_with('0') { "1" } else { "0" };
+        assert!(read_line(1, &format!("{}{}", &first[..first.len() - 1], last)).is_err());
+        assert!(read_line(2, first).is_err());
+        assert!(read_line(1, "abandon").is_err());
+
+        let mut swapped = words.clone();
+        swapped.swap(0, 4);
+        assert!(decode(&swapped).is_err());
+    }
+
+    #[test]
+    fn render_test() {
+        let sheet = Sheet {
+            user: String::from("alice"),
+            created: 0,
+            key: SecretBytes::new(vec![7; 16]),
+            kdf: vec![String::from("slot 1 password: Argon2id")],
+        };
+        let text = sheet.text().unwrap();
+        let line = key_lines(sheet.key.expose()).unwrap().remove(0);
+        assert!(text.expose().contains(line.expose()));
+        assert!(text.expose().contains("1970-01-01 00:00:00"));
+        assert!(text.expose().contains("  slot 1 password: Argon2id\n"));
+        assert!(sheet.svg().unwrap().expose().contains(line.expose()));
+
+        let pdf = sheet.pdf().unwrap();
+        let pdf = String::from_utf8_lossy(pdf.expose());
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.contains(line.expose()));
+        let xref: usize = pdf.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse().unwrap();
+        assert!(pdf[xref..].starts_with("xref"));
+    }
+}
@@ -0,0 +1,243 @@
+//! Copies of the encrypted vault kept before every save, to roll back one
+//! that went wrong.
+//!
+//! Each copy is a file of `~/.pwwr/backups/` named by the UTC time it was
+//! taken, like `20261018-203244`. The newest copies are kept up to a count
+//! and an age, and the newest one is never dropped.
+use std::env;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use clock;
+use errors::*;
+
+pub const DIR: &str = "backups";
+const COUNT_ENV: &str = "PWWR_BACKUP_KEEP";
+const AGE_ENV: &str = "PWWR_BACKUP_MAX_AGE_DAYS";
+pub const DAY: u64 = 86_400;
+
+/// How many copies to keep and for how long.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retention {
+    pub count: usize,
+    /// in seconds.
+    pub max_age: u64,
+}
+
+impl Default for Retention {
+    fn default() -> Retention {
+        Retention {
+            count: 20,
+            max_age: 90 * DAY,
+        }
+    }
+}
+
+impl Retention {
+    /// the retention overridden by `PWWR_BACKUP_KEEP` and
+    /// `PWWR_BACKUP_MAX_AGE_DAYS`.
+    pub fn with_env(self) -> Result<Retention> {
+        let mut retention = self;
+        if let Ok(count) = env::var(COUNT_ENV) {
+            retention.count = count
+                .parse()
+                .chain_err(|| format!("invalid {} [{}]", COUNT_ENV, count))?;
+        }
+        if let Ok(days) = env::var(AGE_ENV) {
+            let days: u64 = days
+                .parse()
+                .chain_err(|| format!("invalid {} [{}]", AGE_ENV, days))?;
+            retention.max_age = days * DAY;
+        }
+        Ok(retention)
+    }
+}
+
+/// A kept copy of the vault.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub id: String,
+    /// seconds since the unix epoch.
+    pub created: u64,
+    pub size: u64,
+}
+
+/// whether `name` looks like the id of a copy, e.g. `20261018-203244` or
+/// `20261018-203244-2` for a second one within the same second.
+pub fn is_id(name: &str) -> bool {
+    let parts: Vec<&str> = name.split('-').collect();
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    match parts.len() {
+        2 | 3 => {
+            parts[0].len() == 8 && parts[1].len() == 6 && parts.iter().all(|p| digits(p))
+        }
+        _ => false,
+    }
+}
+
+/// the time part and the number of an id, 1 without a suffix.
+fn sort_key(id: &str) -> (&str, u32) {
+    match id.get(15..) {
+        Some(n) if id.len() > 16 => (&id[..15], n[1..].parse().unwrap_or(1)),
+        _ => (id, 1),
+    }
+}
+
+/// the id of a copy taken at `secs` since the unix epoch.
+fn id_at(secs: u64) -> String {
+    let digits: String = clock::format_time(secs)
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect();
+    format!("{}-{}", &digits[..8], &digits[8..])
+}
+
+/// The directory of the copies and their retention.
+pub struct Rotation {
+    dir: PathBuf,
+    retention: Retention,
+}
+
+impl Rotation {
+    pub fn new(dir: PathBuf, retention: Retention) -> Rotation {
+        Rotation { dir, retention }
+    }
+
+    /// keep `data` as the newest copy, drop those out of the retention and
+    /// return the id of the new one.
+    pub fn keep(&self, data: &[u8]) -> Result<String> {
+        if !self.dir.exists() {
+            DirBuilder::new()
+                .mode(0o700)
+                .create(&self.dir)
+                .chain_err(|| format!("can't create [{}]", self.dir.display()))?;
+        }
+        let now = clock::now();
+        let base = id_at(now);
+        // number copies within a second after the last one, even a dropped one.
+        let last = self
+            .list()?
+            .iter()
+            .map(|s| sort_key(&s.id))
+            .filter(|&(time, _)| time == base)
+            .map(|(_, n)| n)
+            .max();
+        let id = match last {
+            Some(n) => format!("{}-{}", base, n + 1),
+            None => base,
+        };
+
+        let path = self.dir.join(&id);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .chain_err(|| format!("can't create [{}]", path.display()))?;
+        file.write_all(data)
+            .and_then(|_| file.sync_all())
+            .chain_err(|| format!("can't write [{}]", path.display()))?;
+        self.prune(now)?;
+        Ok(id)
+    }
+
+    /// the kept copies, oldest first.
+    pub fn list(&self) -> Result<Vec<Snapshot>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut snapshots = Vec::new();
+        for dir_entry in fs::read_dir(&self.dir).chain_err(|| format!("can't read [{}]", self.dir.display()))? {
+            let dir_entry = dir_entry.chain_err(|| format!("can't read [{}]", self.dir.display()))?;
+            let id = dir_entry.file_name().to_string_lossy().into_owned();
+            if !is_id(&id) {
+                continue;
+            }
+            let metadata = dir_entry
+                .metadata()
+                .chain_err(|| format!("can't read [{}]", dir_entry.path().display()))?;
+            let created = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            snapshots.push(Snapshot {
+                id,
+                created,
+                size: metadata.len(),
+            });
+        }
+        snapshots.sort_by(|a, b| sort_key(&a.id).cmp(&sort_key(&b.id)));
+        Ok(snapshots)
+    }
+
+    /// the content of the copy `id`.
+    pub fn read(&self, id: &str) -> Result<Vec<u8>> {
+        if !is_id(id) {
+            bail!("invalid backup id [{}]", id);
+        }
+        let path = self.dir.join(id);
+        if !path.exists() {
+            bail!("no backup [{}]", id);
+        }
+        fs::read(&path).chain_err(|| format!("can't read [{}]", path.display()))
+    }
+
+    /// drop the copies beyond the count or older than the age at `now`.
+    fn prune(&self, now: u64) -> Result<()> {
+        let snapshots = self.list()?;
+        let newest = snapshots.len().saturating_sub(1);
+        for (i, snapshot) in snapshots.iter().enumerate() {
+            let too_many = snapshots.len() - i > self.retention.count;
+            let too_old = now.saturating_sub(snapshot.created) > self.retention.max_age;
+            if i < newest && (too_many || too_old) {
+                let path = self.dir.join(&snapshot.id);
+                fs::remove_file(&path).chain_err(|| format!("can't remove [{}]", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// sync a directory so that a rename in it is durable.
+pub fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .chain_err(|| format!("can't sync [{}]", dir.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use rotation::*;
+    use testutil::TempDir;
+
+    #[test]
+    fn keep_test() {
+        let dir = TempDir::new("rotation");
+        let rotation = Rotation::new(dir.join("backups"), Retention { count: 3, max_age: DAY });
+        let ids: Vec<String> = (0..5).map(|i| rotation.keep(&[i]).unwrap()).collect();
+        assert!(ids.iter().all(|id| is_id(id)));
+        let kept: Vec<String> = rotation.list().unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids[2..].to_vec(), kept);
+        assert_eq!(vec![4], rotation.read(&ids[4]).unwrap());
+        assert!(rotation.read(&ids[0]).is_err());
+        assert!(rotation.read("../entries").is_err());
+
+        // the newest copy outlives the age.
+        rotation.prune(clock::now() + 2 * DAY).unwrap();
+        let kept: Vec<String> = rotation.list().unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(vec![ids[4].clone()], kept);
+    }
+
+    #[test]
+    fn id_test() {
+        assert_eq!("20000229-123456", id_at(951_827_696));
+        assert!(is_id("20000229-123456-2"));
+        assert!(sort_key("20000229-123456-2") < sort_key("20000229-123456-10"));
+        assert!(sort_key("20000229-123456") < sort_key("20000229-123456-2"));
+        assert!(!is_id("20000229-12345"));
+        assert!(!is_id("backup.pwwr"));
+    }
+}
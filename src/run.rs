@@ -0,0 +1,113 @@
+use std::mem;
+use std::process::{Command, ExitStatus};
+use std::os::unix::process::ExitStatusExt;
+use std::sync::atomic::{AtomicI32, Ordering};
+use libc;
+use secret::SecretString;
+use errors::*;
+
+/// Signals relayed to the child. Terminal generated ones already reach it
+/// through the process group, so only those sent with kill(2) are relayed.
+const FORWARDED: &[libc::c_int] = &[
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGWINCH,
+];
+
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward(sig: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    let pid = CHILD.load(Ordering::SeqCst);
+    let sent_by_process = info.is_null() || unsafe { (*info).si_code } <= 0;
+    if pid > 0 && sent_by_process {
+        unsafe { libc::kill(pid, sig) };
+    }
+}
+
+fn set_handler(sig: libc::c_int, handler: libc::sighandler_t, flags: libc::c_int) {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handler;
+        action.sa_flags = flags;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(sig, &action, ::std::ptr::null_mut());
+    }
+}
+
+/// run `program` with `env` added to its environment only, relaying
+/// signals to it. Returns its exit status as this process should report it.
+pub fn run(program: &str, args: &[&str], env: Vec<(String, SecretString)>) -> Result<i32> {
+    let status = wait_for(program, args, env)?;
+    if let Some(sig) = status.signal() {
+        // die the same way so our parent sees the signal, too.
+        unsafe { libc::kill(libc::getpid(), sig) };
+    }
+    Ok(exit_code(status))
+}
+
+fn wait_for(program: &str, args: &[&str], env: Vec<(String, SecretString)>) -> Result<ExitStatus> {
+    let handler = forward as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void);
+    for &sig in FORWARDED {
+        set_handler(sig, handler as libc::sighandler_t, libc::SA_SIGINFO | libc::SA_RESTART);
+    }
+
+    let mut command = Command::new(program);
+    command.args(args);
+    for (name, value) in &env {
+        command.env(name, value.expose());
+    }
+    let spawned = command.spawn();
+    drop(command);
+    drop(env);
+    let mut child = spawned.chain_err(|| format!("can't start [{}]", program))?;
+    CHILD.store(child.id() as i32, Ordering::SeqCst);
+
+    let status = child.wait().chain_err(|| "can't wait for the child");
+    CHILD.store(0, Ordering::SeqCst);
+    for &sig in FORWARDED {
+        set_handler(sig, libc::SIG_DFL, 0);
+    }
+    status
+}
+
+/// the exit code of `status`, 128 + the signal for a killed child as shells report it.
+fn exit_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(sig)) => 128 + sig,
+        (None, None) => 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use run::*;
+    use std::env;
+
+    #[test]
+    fn exit_code_test() {
+        assert_eq!(0, run("sh", &["-c", "exit 0"], vec![]).unwrap());
+        assert_eq!(3, run("sh", &["-c", "exit 3"], vec![]).unwrap());
+        assert!(run("pwwr-no-such-program", &[], vec![]).is_err());
+    }
+
+    #[test]
+    fn signal_test() {
+        let status = wait_for("sh", &["-c", "kill -KILL $$"], vec![]).unwrap();
+        assert_eq!(Some(libc::SIGKILL), status.signal());
+        assert_eq!(128 + libc::SIGKILL, exit_code(status));
+    }
+
+    #[test]
+    fn env_test() {
+        let env = vec![(String::from("PWWR_RUN_TEST"), SecretString::from("value"))];
+        let check = r#"test "$PWWR_RUN_TEST" = value"#;
+        assert_eq!(0, run("sh", &["-c", check], env).unwrap());
+        assert!(env::var_os("PWWR_RUN_TEST").is_none());
+        assert_eq!(1, run("sh", &["-c", check], vec![]).unwrap());
+    }
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
+use base64;
+use libc;
+use serde::de::Error as DeError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use errors::*;
+
+/// overwrite `buf` with zeros in a way the optimizer can't elide.
+pub fn wipe(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Bytes that are zeroed on drop and never printed.
+///
+/// The buffer is taken over as is, so build it with its final capacity:
+/// a reallocation before the wrap leaves an unwiped copy behind.
+pub struct SecretBytes {
+    buf: Vec<u8>,
+    locked: bool,
+}
+
+impl SecretBytes {
+    pub fn new(buf: Vec<u8>) -> SecretBytes {
+        SecretBytes { buf, locked: false }
+    }
+
+    pub fn from_slice(data: &[u8]) -> SecretBytes {
+        SecretBytes::new(Vec::from(data))
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn expose_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// keep the pages out of swap. Failure is ignored as the limit may be too small.
+    pub fn lock_memory(&mut self) {
+        if !self.locked && !self.buf.is_empty() {
+            let ret = unsafe {
+                libc::mlock(self.buf.as_ptr() as *const libc::c_void, self.buf.len())
+            };
+            self.locked = ret == 0;
+        }
+    }
+}
+
+impl Clone for SecretBytes {
+    fn clone(&self) -> SecretBytes {
+        let mut cloned = SecretBytes::from_slice(&self.buf);
+        if self.locked {
+            cloned.lock_memory();
+        }
+        cloned
+    }
+}
+
+impl PartialEq for SecretBytes {
+    fn eq(&self, other: &SecretBytes) -> bool {
+        self.buf == other.buf
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        wipe(&mut self.buf);
+        if self.locked {
+            unsafe {
+                libc::munlock(self.buf.as_ptr() as *const libc::c_void, self.buf.len());
+            }
+        }
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBytes([REDACTED])")
+    }
+}
+
+/// A string that is zeroed on drop and never printed.
+/// It (de)serializes as a plain string so the vault format is unchanged.
+#[derive(Clone, PartialEq, Default)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(s: String) -> SecretString {
+        SecretString(s)
+    }
+
+    pub fn expose(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<'a> From<&'a str> for SecretString {
+    fn from(s: &'a str) -> SecretString {
+        SecretString(String::from(s))
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        wipe(unsafe { self.0.as_mut_vec() });
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretString([REDACTED])")
+    }
+}
+
+/// serialized as base64 text, which keeps binary attachments compact in json.
+impl Serialize for SecretBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        let encoded = SecretString::new(base64::encode(&self.buf));
+        serializer.serialize_str(encoded.expose())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretBytes {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> ::std::result::Result<SecretBytes, D::Error> {
+        let encoded = SecretString::deserialize(deserializer)?;
+        base64::decode(encoded.expose())
+            .map(SecretBytes::new)
+            .map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.expose())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> ::std::result::Result<SecretString, D::Error> {
+        String::deserialize(deserializer).map(SecretString)
+    }
+}
+
+/// keep secrets out of core files while the vault is unlocked.
+pub fn disable_core_dumps() -> Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        bail!("can't disable core dumps");
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe {
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use secret::*;
+    use serde_json;
+
+    #[test]
+    fn secrets_are_redacted_test() {
+        let bytes = SecretBytes::from_slice(b"hunter2");
+        let string = SecretString::from("hunter2");
+        assert!(!format!("{:?}", bytes).contains("hunter2"));
+        assert!(!format!("{:?}", string).contains("hunter2"));
+    }
+
+    #[test]
+    fn secret_serde_test() {
+        let string = SecretString::from("hunter2");
+        let json = serde_json::to_string(&string).unwrap();
+        assert_eq!("\"hunter2\"", json);
+        let back: SecretString = serde_json::from_str(&json).unwrap();
+        assert_eq!("hunter2", back.expose());
+
+        let bytes = SecretBytes::from_slice(&[0, 1, 255]);
+        let json = serde_json::to_string(&bytes).unwrap();
+        assert_eq!("\"AAH/\"", json);
+        let back: SecretBytes = serde_json::from_str(&json).unwrap();
+        assert_eq!(bytes, back);
+    }
+}
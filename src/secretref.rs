@@ -0,0 +1,110 @@
+use std::fmt;
+use entrystore::{Cipher, Entry, EntrySelector, EntryStore};
+use rawio::RawStore;
+use secret::SecretString;
+use errors::*;
+
+const BUILT_IN_FIELDS: &[&str] = &["user", "password", "other"];
+
+/// A reference to one field of an entry, written `TITLE:FIELD`.
+/// The title is matched like `EntryStore::select_one` and the field defaults to `password`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SecretRef {
+    pub title: String,
+    pub field: String,
+}
+
+impl SecretRef {
+    pub fn new(title: &str, field: &str) -> SecretRef {
+        SecretRef {
+            title: String::from(title),
+            field: String::from(field),
+        }
+    }
+
+    /// parse `TITLE:FIELD` against the entries of `store`. What follows the
+    /// last ':' is a field only when it is a built-in one or a field of an
+    /// entry the rest matches, so a title such as `host:8080` stands alone.
+    pub fn parse<RW, CP>(reference: &str, store: &EntryStore<RW, CP>) -> Result<SecretRef>
+    where
+        RW: RawStore,
+        CP: Cipher,
+    {
+        if reference.is_empty() {
+            bail!("invalid reference [{}], expected TITLE:FIELD", reference);
+        }
+        if !store.contains(reference) {
+            if let Some(i) = reference.rfind(':') {
+                let (title, field) = (&reference[..i], &reference[i + 1..]);
+                let known = BUILT_IN_FIELDS.contains(&field)
+                    || store.search(title).iter().any(|e| e.auth_info.fields.contains_key(field));
+                if !title.is_empty() && known {
+                    return Ok(SecretRef::new(title, field));
+                }
+            }
+        }
+        Ok(SecretRef::new(reference, "password"))
+    }
+
+    /// the entry the title refers to.
+    pub fn lookup<RW, CP, T>(&self, store: &EntryStore<RW, CP>, input: &T) -> Result<Entry>
+    where
+        RW: RawStore,
+        CP: Cipher,
+        T: EntrySelector,
+    {
+        match store.select_one(&self.title, input)? {
+            Some(entry) => Ok(entry),
+            None => bail!(ErrorKind::EntryNotFound(self.title.clone())),
+        }
+    }
+
+    /// look the value up. A missing entry or field is an error, and so is
+    /// an ambiguous title unless `input` chooses one.
+    pub fn resolve<RW, CP, T>(&self, store: &EntryStore<RW, CP>, input: &T) -> Result<SecretString>
+    where
+        RW: RawStore,
+        CP: Cipher,
+        T: EntrySelector,
+    {
+        let entry = self.lookup(store, input)?;
+        match entry.auth_info.get_field(&self.field) {
+            Some(value) => Ok(value),
+            None => bail!("the entry [{}] has no field [{}]", entry.title, self.field),
+        }
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.title, self.field)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secretref::*;
+    use entrystore::AuthInfo;
+    use testutil::empty_store;
+
+    #[test]
+    fn parse_test() {
+        let mut store = empty_store();
+        let mut auth = AuthInfo::blank();
+        auth.set_field("api_key", SecretString::from("k")).unwrap();
+        store.add(Entry::new("prod/db", auth)).unwrap();
+        store.add(Entry::new("host:8080", AuthInfo::blank())).unwrap();
+        store.add(Entry::new("a:user", AuthInfo::blank())).unwrap();
+        let parse = |reference| SecretRef::parse(reference, &store).unwrap();
+
+        assert_eq!(SecretRef::new("prod/db", "password"), parse("prod/db:password"));
+        assert_eq!(SecretRef::new("prod/db", "api_key"), parse("prod/db:api_key"));
+        assert_eq!(SecretRef::new("stripe", "password"), parse("stripe"));
+        assert_eq!(SecretRef::new("a:b", "user"), parse("a:b:user"));
+        assert_eq!(SecretRef::new("host:8080", "password"), parse("host:8080"));
+        assert_eq!(SecretRef::new("host:8080", "other"), parse("host:8080:other"));
+        assert_eq!(SecretRef::new("prod/db:port", "password"), parse("prod/db:port"));
+        assert_eq!(SecretRef::new("a:user", "password"), parse("a:user"));
+        assert!(SecretRef::parse("", &store).is_err());
+    }
+}
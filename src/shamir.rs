@@ -0,0 +1,220 @@
+//! Shamir secret sharing of the vault key over GF(256), so that any
+//! `threshold` of the shares open the vault and fewer tell nothing of it.
+//!
+//! Each byte of the key is the constant term of a random polynomial of
+//! degree `threshold - 1`, and share `i` holds the values at `x = i`. A share
+//! also carries its index, the threshold and a random id of the split, so
+//! that shares of different splits are not mixed up.
+//!
+//! The shared key is the data key of the vault, which outlives changes of
+//! the master password and the other key slots. Vaults without key slots
+//! have no data key, their shares hold the key derived from the master
+//! password, and they stop opening the vault once it is upgraded to key
+//! slots or the master password changes.
+use rand::{OsRng, Rng};
+use recovery;
+use secret::{SecretBytes, SecretString};
+use errors::*;
+
+/// index, threshold and the two bytes of the id of the split.
+const HEADER_LEN: usize = 4;
+
+/// One share of a key.
+pub struct Share {
+    pub index: u8,
+    pub threshold: u8,
+    pub set: [u8; 2],
+    pub data: SecretBytes,
+}
+
+/// multiply in GF(256) with the AES polynomial, without branches on the values.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// the inverse in GF(256): `a^254`.
+fn inv(a: u8) -> u8 {
+    let mut result = 1;
+    let mut base = a;
+    let mut exponent = 254;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul(result, base);
+        }
+        base = mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// split `secret` into `count` shares, any `threshold` of them rebuilding it.
+pub fn split(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<Share>> {
+    if threshold < 2 || threshold > count {
+        bail!("the threshold must be between 2 and the number of shares");
+    }
+    let mut rng = OsRng::new().chain_err(|| "can't open the OS random generator")?;
+    let mut set = [0; 2];
+    rng.fill_bytes(&mut set);
+
+    let mut shares: Vec<Share> = (1..=count)
+        .map(|index| Share {
+            index,
+            threshold,
+            set,
+            data: SecretBytes::new(vec![0; secret.len()]),
+        })
+        .collect();
+    let mut coefficients = SecretBytes::new(vec![0; threshold as usize]);
+    for (i, &byte) in secret.iter().enumerate() {
+        coefficients.expose_mut()[0] = byte;
+        rng.fill_bytes(&mut coefficients.expose_mut()[1..]);
+        for share in &mut shares {
+            let y = coefficients
+                .expose()
+                .iter()
+                .rev()
+                .fold(0, |y, &c| mul(y, share.index) ^ c);
+            share.data.expose_mut()[i] = y;
+        }
+    }
+    Ok(shares)
+}
+
+/// rebuild the secret of at least `threshold` shares of the same split.
+pub fn combine(shares: &[Share]) -> Result<SecretBytes> {
+    let first = match shares.first() {
+        Some(first) => first,
+        None => bail!("no shares"),
+    };
+    for (i, share) in shares.iter().enumerate() {
+        if share.set != first.set || share.threshold != first.threshold || share.data.expose().len() != first.data.expose().len() {
+            bail!("the shares come from different splits");
+        }
+        if share.index == 0 || shares[..i].iter().any(|s| s.index == share.index) {
+            bail!("share {} is given twice", share.index);
+        }
+    }
+    if shares.len() < first.threshold as usize {
+        bail!("{} shares are needed, {} given", first.threshold, shares.len());
+    }
+
+    // Lagrange interpolation at x = 0, where subtraction is xor.
+    let shares = &shares[..first.threshold as usize];
+    let mut secret = SecretBytes::new(vec![0; first.data.expose().len()]);
+    for share in shares {
+        let mut basis = 1;
+        for other in shares.iter().filter(|s| s.index != share.index) {
+            basis = mul(basis, mul(other.index, inv(other.index ^ share.index)));
+        }
+        for (byte, &y) in secret.expose_mut().iter_mut().zip(share.data.expose()) {
+            *byte ^= mul(y, basis);
+        }
+    }
+    Ok(secret)
+}
+
+impl Share {
+    pub fn to_bytes(&self) -> SecretBytes {
+        let mut bytes = vec![self.index, self.threshold, self.set[0], self.set[1]];
+        bytes.extend_from_slice(self.data.expose());
+        SecretBytes::new(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Share> {
+        if bytes.len() <= HEADER_LEN {
+            bail!("invalid share");
+        }
+        // one share would be the secret itself, no split makes such shares.
+        if bytes[1] < 2 {
+            bail!("invalid share threshold {}", bytes[1]);
+        }
+        Ok(Share {
+            index: bytes[0],
+            threshold: bytes[1],
+            set: [bytes[2], bytes[3]],
+            data: SecretBytes::from_slice(&bytes[HEADER_LEN..]),
+        })
+    }
+
+    /// the number of words of the share of a key of `key_len` bytes.
+    pub fn word_count(key_len: usize) -> usize {
+        (HEADER_LEN + key_len) * 3 / 4
+    }
+
+    /// the share as printed or saved for its holder.
+    pub fn text(&self, user: &str, count: u8) -> Result<SecretString> {
+        let mut text = format!(
+            "PWWR KEY SHARE {} of {}, {} needed to open the vault\n\
+             vault user {}, split {:02x}{:02x}\n\n",
+            self.index, count, self.threshold, user, self.set[0], self.set[1]
+        );
+        for (i, line) in recovery::key_lines(self.to_bytes().expose())?.iter().enumerate() {
+            text.push_str(&format!("  {}  {}\n", i + 1, line.expose()));
+        }
+        text.push_str(
+            "\nRun `pwwr shares recover` with the holders of the other shares and\n\
+             type each line as printed, the words then the check.\n",
+        );
+        Ok(SecretString::new(text))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use shamir::*;
+
+    #[test]
+    fn gf256_test() {
+        assert_eq!(0xc1, mul(0x57, 0x83));
+        for a in 1..=255u8 {
+            assert_eq!(1, mul(a, inv(a)));
+        }
+    }
+
+    #[test]
+    fn split_test() {
+        let secret: Vec<u8> = (0..16).map(|i| i * 17).collect();
+        let shares = split(&secret, 3, 5).unwrap();
+        assert_eq!(5, shares.len());
+        assert!(shares.iter().all(|s| s.data.expose() != &secret[..]));
+
+        // every 3 of the 5 shares.
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let some: Vec<Share> = [a, b, c]
+                        .iter()
+                        .map(|&i| Share::from_bytes(shares[i].to_bytes().expose()).unwrap())
+                        .collect();
+                    assert_eq!(secret, combine(&some).unwrap().expose());
+                }
+            }
+        }
+
+        let two: Vec<Share> = shares.iter().take(2).map(|s| Share::from_bytes(s.to_bytes().expose()).unwrap()).collect();
+        assert!(combine(&two).is_err());
+        let mut other = shares[2].to_bytes().expose().to_vec();
+        other[2] ^= 1;
+        let mixed = vec![
+            Share::from_bytes(shares[0].to_bytes().expose()).unwrap(),
+            Share::from_bytes(shares[1].to_bytes().expose()).unwrap(),
+            Share::from_bytes(&other).unwrap(),
+        ];
+        assert!(combine(&mixed).is_err());
+        for threshold in 0..2 {
+            let mut alone = shares[0].to_bytes().expose().to_vec();
+            alone[1] = threshold;
+            assert!(Share::from_bytes(&alone).is_err());
+        }
+        assert!(split(&secret, 1, 5).is_err());
+        assert!(split(&secret, 4, 3).is_err());
+        assert_eq!(15, Share::word_count(16));
+    }
+}
@@ -0,0 +1,347 @@
+//! Syncing a vault between machines through a git remote.
+//!
+//! `pwwr sync init REMOTE` makes the directory of the vault a git repository
+//! sharing only the encrypted files of the vault, listed in its
+//! `.gitignore`; the audit log, the kept copies and the agent stay local.
+//! Each save is committed, and `pwwr sync` pulls and pushes. When both sides
+//! changed the entries, they are merged entry by entry: an entry changed on
+//! one side only takes that change, and an entry changed on both is kept
+//! twice, the remote one titled `TITLE (sync conflict)`, or
+//! `TITLE (sync conflict 2)` and so on when an earlier conflict holds it.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use audit;
+use entrystore::Entry;
+use errors::*;
+
+const REMOTE: &str = "origin";
+const BRANCH: &str = "main";
+const REMOTE_BRANCH: &str = "refs/remotes/origin/main";
+
+/// Where the vault stands against the remote.
+#[derive(Clone, PartialEq, Debug)]
+pub enum State {
+    UpToDate,
+    Ahead,
+    Behind,
+    /// both sides have commits since their common one.
+    Diverged(String),
+}
+
+fn run_git(dir: Option<&Path>, args: &[&str]) -> Result<(bool, String)> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let output = command.args(args).output().chain_err(|| "can't run git, is it installed?")?;
+    if output.status.success() {
+        Ok((true, String::from_utf8_lossy(&output.stdout).trim().to_string()))
+    } else {
+        Ok((false, String::from_utf8_lossy(&output.stderr).trim().to_string()))
+    }
+}
+
+/// whether `remote` already holds a synced vault.
+pub fn remote_has_vault(remote: &str) -> Result<bool> {
+    let head = format!("refs/heads/{}", BRANCH);
+    match run_git(None, &["ls-remote", remote, &head])? {
+        (true, out) => Ok(!out.is_empty()),
+        (false, err) => bail!("can't reach [{}]: {}", remote, err),
+    }
+}
+
+/// The git repository of a synced vault.
+pub struct Repo {
+    dir: PathBuf,
+}
+
+impl Repo {
+    /// the repository of the vault in `dir`, `None` unless it is synced.
+    pub fn open(dir: &Path) -> Option<Repo> {
+        if dir.join(".git").is_dir() {
+            Some(Repo { dir: dir.to_path_buf() })
+        } else {
+            None
+        }
+    }
+
+    /// make the vault in `dir` a repository sharing `files` with `remote`.
+    pub fn init(dir: &Path, remote: &str, files: &[&str]) -> Result<Repo> {
+        if Repo::open(dir).is_some() {
+            bail!("the vault is already synced, see [{}]", dir.join(".git").display());
+        }
+        let repo = Repo { dir: dir.to_path_buf() };
+        repo.git(&["init", "-q", "-b", BRANCH])?;
+        let mut ignore = String::from("# written by pwwr sync: only the encrypted vault is shared.\n");
+        ignore.push_str("/*\n!/.gitignore\n");
+        for file in files {
+            ignore.push_str(&format!("!/{}\n", file));
+        }
+        let path = dir.join(".gitignore");
+        fs::write(&path, ignore).chain_err(|| format!("can't write [{}]", path.display()))?;
+        repo.git(&["remote", "add", REMOTE, remote])?;
+        if !run_git(Some(dir), &["config", "user.email"])?.0 {
+            let user = audit::os_user();
+            repo.git(&["config", "user.name", &user])?;
+            repo.git(&["config", "user.email", &format!("{}@{}", user, audit::hostname())])?;
+        }
+        Ok(repo)
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String> {
+        match run_git(Some(&self.dir), args)? {
+            (true, out) => Ok(out),
+            (false, err) => bail!("git {} failed: {}", args[0], err),
+        }
+    }
+
+    fn has(&self, rev: &str) -> Result<bool> {
+        Ok(run_git(Some(&self.dir), &["rev-parse", "-q", "--verify", rev])?.0)
+    }
+
+    /// commit the shared files, unless nothing changed.
+    pub fn commit(&self, message: &str) -> Result<()> {
+        self.git(&["add", "-A"])?;
+        let changed = !run_git(Some(&self.dir), &["diff", "--cached", "--quiet"])?.0;
+        if changed || self.has("MERGE_HEAD")? {
+            self.git(&["commit", "-q", "-m", message])?;
+        }
+        Ok(())
+    }
+
+    /// fetch the remote, `false` when it has no vault yet.
+    pub fn fetch(&self) -> Result<bool> {
+        self.git(&["fetch", "-q", REMOTE])?;
+        self.has(REMOTE_BRANCH)
+    }
+
+    /// where the vault stands against the remote fetched last.
+    pub fn state(&self) -> Result<State> {
+        if !self.has("HEAD")? {
+            return Ok(State::Behind);
+        }
+        let head = self.git(&["rev-parse", "HEAD"])?;
+        let remote = self.git(&["rev-parse", REMOTE_BRANCH])?;
+        let base = match run_git(Some(&self.dir), &["merge-base", "HEAD", REMOTE_BRANCH])? {
+            (true, base) => base,
+            (false, _) => bail!("the vault and the remote share no history"),
+        };
+        Ok(if head == remote {
+            State::UpToDate
+        } else if base == remote {
+            State::Ahead
+        } else if base == head {
+            State::Behind
+        } else {
+            State::Diverged(base)
+        })
+    }
+
+    /// take the files of the remote, replacing the local ones.
+    pub fn reset_to_remote(&self) -> Result<()> {
+        self.git(&["reset", "-q", "--hard", REMOTE_BRANCH])?;
+        self.git(&["branch", "-q", "-u", REMOTE_BRANCH])?;
+        Ok(())
+    }
+
+    pub fn fast_forward(&self) -> Result<()> {
+        self.git(&["merge", "-q", "--ff-only", REMOTE_BRANCH]).map(|_| ())
+    }
+
+    pub fn push(&self) -> Result<()> {
+        self.git(&["push", "-q", "-u", REMOTE, BRANCH]).map(|_| ())
+    }
+
+    /// start merging the remote, keeping the local files until they are
+    /// replaced and committed.
+    pub fn start_merge(&self) -> Result<()> {
+        self.git(&["merge", "-q", "-s", "ours", "--no-commit", "--no-ff", REMOTE_BRANCH])
+            .map(|_| ())
+    }
+
+    pub fn abort_merge(&self) -> Result<()> {
+        self.git(&["merge", "--abort"]).map(|_| ())
+    }
+
+    /// the content of `file` at `rev`, empty when it has none.
+    pub fn show(&self, rev: &str, file: &str) -> Result<Vec<u8>> {
+        let object = format!("{}:{}", rev, file);
+        if !run_git(Some(&self.dir), &["cat-file", "-e", &object])?.0 {
+            return Ok(Vec::new());
+        }
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(["show", &object])
+            .output()
+            .chain_err(|| "can't run git, is it installed?")?;
+        if !output.status.success() {
+            bail!("can't read [{}]", object);
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// the three-way merge of a whole file, `None` when both sides changed it.
+pub fn merge_file(base: &[u8], ours: &[u8], theirs: &[u8]) -> Option<Vec<u8>> {
+    if ours == theirs || theirs == base {
+        Some(ours.to_vec())
+    } else if ours == base {
+        Some(theirs.to_vec())
+    } else {
+        None
+    }
+}
+
+/// the three-way merge of entries, with the titles changed on both sides.
+pub fn merge_entries(base: Vec<Entry>, ours: Vec<Entry>, theirs: Vec<Entry>) -> (Vec<Entry>, Vec<String>) {
+    fn by_title(entries: Vec<Entry>) -> BTreeMap<String, Entry> {
+        entries.into_iter().map(|e| (e.title.clone(), e)).collect()
+    }
+    let (base, mut ours, mut theirs) = (by_title(base), by_title(ours), by_title(theirs));
+    let titles: BTreeSet<String> = base.keys().chain(ours.keys()).chain(theirs.keys()).cloned().collect();
+
+    let mut merged = BTreeMap::new();
+    let mut conflicts = Vec::new();
+    for title in titles.iter().cloned() {
+        let (b, o, t) = (base.get(&title), ours.remove(&title), theirs.remove(&title));
+        if o == t || t.as_ref() == b {
+            merged.extend(o.map(|e| (title.clone(), e)));
+        } else if o.as_ref() == b {
+            merged.extend(t.map(|e| (title.clone(), e)));
+        } else {
+            // changed on both sides, or changed on one and removed on the
+            // other: keep every version left.
+            merged.extend(o.map(|e| (title.clone(), e)));
+            if let Some(mut e) = t {
+                if merged.contains_key(&title) {
+                    // free on both sides, an earlier conflict may be kept.
+                    e.title = format!("{} (sync conflict)", title);
+                    let mut n = 1;
+                    while titles.contains(&e.title) || merged.contains_key(&e.title) {
+                        n += 1;
+                        e.title = format!("{} (sync conflict {})", title, n);
+                    }
+                }
+                merged.insert(e.title.clone(), e);
+            }
+            conflicts.push(title);
+        }
+    }
+    (merged.into_values().collect(), conflicts)
+}
+
+#[cfg(test)]
+mod test {
+    use sync::*;
+    use entrystore::AuthInfo;
+    use secret::SecretString;
+    use testutil::TempDir;
+
+    fn entry(title: &str, password: &str) -> Entry {
+        Entry::new(title, AuthInfo::new("u", SecretString::from(password), SecretString::default()))
+    }
+
+    #[test]
+    fn merge_test() {
+        assert_eq!(Some(b"b".to_vec()), merge_file(b"a", b"a", b"b"));
+        assert_eq!(Some(b"b".to_vec()), merge_file(b"a", b"b", b"a"));
+        assert_eq!(Some(b"b".to_vec()), merge_file(b"a", b"b", b"b"));
+        assert_eq!(None, merge_file(b"a", b"b", b"c"));
+
+        let entries = |list: &[(&str, &str)]| list.iter().map(|&(t, p)| entry(t, p)).collect::<Vec<_>>();
+        let passwords = |merged: &[Entry]| {
+            merged
+                .iter()
+                .map(|e| format!("{}={}", e.title, e.auth_info.password.expose()))
+                .collect::<Vec<_>>()
+        };
+        let base = entries(&[("same", "1"), ("ours", "1"), ("theirs", "1"), ("both", "1"), ("gone", "1")]);
+        let ours = entries(&[("same", "1"), ("ours", "2"), ("theirs", "1"), ("both", "2"), ("new", "1")]);
+        let theirs = entries(&[("same", "1"), ("ours", "1"), ("theirs", "2"), ("both", "3")]);
+        let (merged, conflicts) = merge_entries(base, ours, theirs);
+        assert_eq!(
+            vec!["both=2", "both (sync conflict)=3", "new=1", "ours=2", "same=1", "theirs=2"],
+            passwords(&merged)
+        );
+        assert_eq!(vec!["both"], conflicts);
+
+        let (merged, conflicts) = merge_entries(entries(&[("a", "1")]), vec![], entries(&[("a", "2")]));
+        assert_eq!(vec!["a=2"], passwords(&merged));
+        assert_eq!(vec!["a"], conflicts);
+
+        // both sides kept the copy of an earlier conflict.
+        let old = ("a (sync conflict)", "0");
+        let (merged, conflicts) = merge_entries(
+            entries(&[("a", "1"), old]),
+            entries(&[("a", "2"), old]),
+            entries(&[("a", "3"), old]),
+        );
+        assert_eq!(vec!["a=2", "a (sync conflict 2)=3", "a (sync conflict)=0"], passwords(&merged));
+        assert_eq!(vec!["a"], conflicts);
+    }
+
+    #[test]
+    fn repo_test() {
+        let root = TempDir::new("sync");
+        let (remote, a, b) = (root.join("remote.git"), root.join("a"), root.join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        run_git(None, &["init", "-q", "--bare", remote.to_str().unwrap()]).unwrap();
+        let remote = remote.to_str().unwrap();
+        assert!(!remote_has_vault(remote).unwrap());
+
+        let repo_a = Repo::init(&a, remote, &["entries", "settings"]).unwrap();
+        fs::write(a.join("entries"), "1").unwrap();
+        fs::write(a.join("lock"), "1").unwrap();
+        repo_a.commit("first").unwrap();
+        assert!(!repo_a.fetch().unwrap());
+        repo_a.push().unwrap();
+        assert!(remote_has_vault(remote).unwrap());
+        assert!(Repo::init(&a, remote, &[]).is_err());
+
+        let repo_b = Repo::init(&b, remote, &["entries", "settings"]).unwrap();
+        assert!(repo_b.fetch().unwrap());
+        repo_b.reset_to_remote().unwrap();
+        assert_eq!("1", fs::read_to_string(b.join("entries")).unwrap());
+        assert!(!b.join("lock").exists());
+
+        fs::write(b.join("entries"), "2").unwrap();
+        repo_b.commit("second").unwrap();
+        assert_eq!(State::Ahead, repo_b.state().unwrap());
+        repo_b.push().unwrap();
+        repo_a.fetch().unwrap();
+        assert_eq!(State::Behind, repo_a.state().unwrap());
+        repo_a.fast_forward().unwrap();
+        assert_eq!(State::UpToDate, repo_a.state().unwrap());
+        assert_eq!("2", fs::read_to_string(a.join("entries")).unwrap());
+
+        fs::write(a.join("entries"), "3").unwrap();
+        repo_a.commit("third").unwrap();
+        repo_a.push().unwrap();
+        fs::write(b.join("settings"), "s").unwrap();
+        repo_b.commit("fourth").unwrap();
+        repo_b.fetch().unwrap();
+        let base = match repo_b.state().unwrap() {
+            State::Diverged(base) => base,
+            state => panic!("unexpected {:?}", state),
+        };
+        repo_b.start_merge().unwrap();
+        let entries = ["entries", "settings"]
+            .iter()
+            .map(|f| {
+                let (o, t) = (repo_b.show("HEAD", f).unwrap(), repo_b.show("MERGE_HEAD", f).unwrap());
+                merge_file(&repo_b.show(&base, f).unwrap(), &o, &t).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![b"3".to_vec(), b"s".to_vec()], entries);
+        fs::write(b.join("entries"), &entries[0]).unwrap();
+        repo_b.commit("merge").unwrap();
+        repo_b.push().unwrap();
+        repo_a.fetch().unwrap();
+        repo_a.fast_forward().unwrap();
+        assert_eq!("s", fs::read_to_string(a.join("settings")).unwrap());
+    }
+}
@@ -0,0 +1,190 @@
+use secret::SecretString;
+use secretref::SecretRef;
+use errors::*;
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+const KEYWORD: &str = "pwwr";
+
+/// A piece of a parsed template.
+#[derive(PartialEq, Debug)]
+pub enum Segment {
+    Text(String),
+    /// `{{ pwwr "TITLE" "FIELD" }}` on the given line. The field defaults to `password`.
+    Ref(SecretRef, usize),
+}
+
+/// split a template into text and references. `{{ ... }}` blocks that don't
+/// start with `pwwr` are kept as text so other template languages pass through.
+pub fn parse(template: &str) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut rest = template;
+    let mut line = 1;
+
+    while let Some(start) = rest.find(OPEN) {
+        let (before, block) = rest.split_at(start);
+        let inner = block[OPEN.len()..].trim_start();
+        if !is_keyword(inner) {
+            text.push_str(before);
+            text.push_str(OPEN);
+            line += count_lines(before) + count_lines(OPEN);
+            rest = &block[OPEN.len()..];
+            continue;
+        }
+
+        text.push_str(before);
+        line += count_lines(before);
+        let end = match block.find(CLOSE) {
+            Some(end) => end,
+            None => bail!("line {}: unclosed {}", line, OPEN),
+        };
+        let body = block[OPEN.len()..end].trim_start();
+        let reference = parse_args(&body[KEYWORD.len()..])
+            .chain_err(|| format!("line {}: invalid reference", line))?;
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+            text = String::new();
+        }
+        segments.push(Segment::Ref(reference, line));
+        line += count_lines(&block[..end]);
+        rest = &block[end + CLOSE.len()..];
+    }
+
+    text.push_str(rest);
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+fn is_keyword(inner: &str) -> bool {
+    inner.starts_with(KEYWORD)
+        && inner[KEYWORD.len()..]
+            .chars()
+            .next()
+            .is_some_and(char::is_whitespace)
+}
+
+fn count_lines(s: &str) -> usize {
+    s.matches('\n').count()
+}
+
+/// parse `"TITLE" ["FIELD"]`.
+fn parse_args(args: &str) -> Result<SecretRef> {
+    let mut values = Vec::new();
+    let mut chars = args.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        match chars.next() {
+            None => break,
+            Some('"') => {}
+            Some(c) => bail!("expected a quoted string, found '{}'", c),
+        }
+
+        let mut value = String::new();
+        loop {
+            match chars.next() {
+                None => bail!("unclosed string"),
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some(c) => value.push(c),
+                    None => bail!("unclosed string"),
+                },
+                Some(c) => value.push(c),
+            }
+        }
+        values.push(value);
+    }
+
+    match values.len() {
+        1 if !values[0].is_empty() => Ok(SecretRef::new(&values[0], "password")),
+        2 if !values[0].is_empty() && !values[1].is_empty() => {
+            Ok(SecretRef::new(&values[0], &values[1]))
+        }
+        _ => bail!("expected \"TITLE\" \"FIELD\""),
+    }
+}
+
+/// the references of a template in order of appearance.
+pub fn references(segments: &[Segment]) -> Vec<(&SecretRef, usize)> {
+    segments
+        .iter()
+        .filter_map(|s| match *s {
+            Segment::Ref(ref reference, line) => Some((reference, line)),
+            Segment::Text(_) => None,
+        })
+        .collect()
+}
+
+/// substitute every reference with the value `resolve` gives.
+/// Errors are passed through as is so their kind decides the exit status.
+pub fn render<F>(segments: &[Segment], mut resolve: F) -> Result<SecretString>
+where
+    F: FnMut(&SecretRef) -> Result<SecretString>,
+{
+    let mut values = Vec::new();
+    for segment in segments {
+        if let Segment::Ref(ref reference, _) = *segment {
+            values.push(resolve(reference)?);
+        }
+    }
+
+    let capacity = segments
+        .iter()
+        .map(|s| match *s {
+            Segment::Text(ref text) => text.len(),
+            Segment::Ref(..) => 0,
+        })
+        .sum::<usize>() + values.iter().map(|v| v.expose().len()).sum::<usize>();
+    let mut rendered = String::with_capacity(capacity);
+    let mut values = values.iter();
+    for segment in segments {
+        match *segment {
+            Segment::Text(ref text) => rendered.push_str(text),
+            Segment::Ref(..) => rendered.push_str(values.next().unwrap().expose()),
+        }
+    }
+    Ok(SecretString::new(rendered))
+}
+
+#[cfg(test)]
+mod test {
+    use template::*;
+
+    #[test]
+    fn parse_test() {
+        let segments = parse("user: app\npass: {{ pwwr \"prod/db\" \"password\" }}\nhelm: {{ .Values.x }}\n").unwrap();
+        assert_eq!(
+            vec![
+                Segment::Text(String::from("user: app\npass: ")),
+                Segment::Ref(SecretRef::new("prod/db", "password"), 2),
+                Segment::Text(String::from("\nhelm: {{ .Values.x }}\n")),
+            ],
+            segments
+        );
+    }
+
+    #[test]
+    fn parse_escape_and_default_field_test() {
+        let segments = parse("{{pwwr \"a \\\"b\\\"\"}}").unwrap();
+        assert_eq!(vec![Segment::Ref(SecretRef::new("a \"b\"", "password"), 1)], segments);
+    }
+
+    #[test]
+    fn parse_error_test() {
+        assert!(parse("{{ pwwr \"a\" ").is_err());
+        assert!(parse("{{ pwwr a }}").is_err());
+        assert!(parse("{{ pwwr }}").is_err());
+        assert!(parse("{{ pwwr \"a\" \"b\" \"c\" }}").is_err());
+    }
+
+    #[test]
+    fn render_test() {
+        let segments = parse("a={{ pwwr \"x\" \"user\" }}, b={{ pwwr \"y\" }}").unwrap();
+        let rendered = render(&segments, |r| Ok(SecretString::new(format!("<{}>", r)))).unwrap();
+        assert_eq!("a=<x:user>, b=<y:password>", rendered.expose());
+    }
+}
@@ -0,0 +1,80 @@
+//! In-memory doubles of the storage and the cipher for tests.
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use entrystore::{Cipher, EntryStore};
+use rawio::RawStore;
+use secret::SecretBytes;
+use errors::*;
+
+/// A `RawStore` whose content can be inspected through a shared buffer.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    pub buf: Rc<RefCell<Vec<u8>>>,
+}
+
+impl RawStore for MemoryStore {
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        buf.extend_from_slice(&self.buf.borrow());
+        Ok(self.buf.borrow().len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        *self.buf.borrow_mut() = Vec::from(buf);
+        Ok(())
+    }
+}
+
+/// A `Cipher` that doesn't encrypt at all.
+pub struct PlainCipher;
+
+impl Cipher for PlainCipher {
+    fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(Vec::from(data))
+    }
+
+    fn decrypt(&self, data: &[u8]) -> Result<SecretBytes> {
+        Ok(SecretBytes::from_slice(data))
+    }
+}
+
+pub fn empty_store() -> EntryStore<MemoryStore, PlainCipher> {
+    EntryStore::load(MemoryStore::default(), PlainCipher).unwrap()
+}
+
+static TEMP_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// A new directory in the temporary one, removed with its content when
+/// dropped, even when the test panics.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let n = TEMP_DIRS.fetch_add(1, Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("pwwr-{}-{}-{}", name, process::id(), n));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
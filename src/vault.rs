@@ -0,0 +1,278 @@
+//! Named vaults, each a directory of its own entries, key slots, kept copies
+//! and agent, so that personal, team and client secrets stay apart.
+//!
+//! The vault `default` is `~/.pwwr` itself, where a single vault has always
+//! lived, and the others are `~/.pwwr/vaults/NAME`. A command opens the vault
+//! of `--vault`, else of `PWWR_VAULT`, else the `default_vault` of the config.
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use serde_json;
+use backup;
+use primitives;
+use rotation::{Retention, DAY};
+use errors::*;
+
+pub const DEFAULT: &str = "default";
+pub const ENV: &str = "PWWR_VAULT";
+const DIR: &str = "vaults";
+pub const SETTINGS_FILE: &str = "settings";
+
+/// Argon2id parameters of the key slots of a vault.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct KdfParams {
+    /// in KiB.
+    pub memory: u32,
+    pub iterations: u32,
+    pub lanes: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> KdfParams {
+        let (memory, iterations, lanes) = backup::KDF_PARAMS;
+        KdfParams {
+            memory,
+            iterations,
+            lanes,
+        }
+    }
+}
+
+impl KdfParams {
+    pub fn tuple(self) -> (u32, u32, u32) {
+        (self.memory, self.iterations, self.lanes)
+    }
+
+    pub fn validate(self) -> Result<()> {
+        if self.lanes == 0 || self.lanes > 255 {
+            bail!("the kdf lanes must be between 1 and 255");
+        }
+        if self.iterations == 0 {
+            bail!("the kdf iterations must be at least 1");
+        }
+        if self.memory < 8 * self.lanes {
+            bail!("the kdf memory must be at least 8 KiB per lane");
+        }
+        // beyond the limits, a backup made with them could not be opened.
+        primitives::check_argon2(
+            u64::from(self.memory),
+            u64::from(self.iterations),
+            u64::from(self.lanes),
+        )
+    }
+}
+
+/// The settings of a vault, the defaults for those left out.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<KdfParams>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_keep: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_max_age_days: Option<u64>,
+}
+
+impl Settings {
+    /// the settings of the vault in `dir`.
+    pub fn load(dir: &Path) -> Result<Settings> {
+        let path = dir.join(SETTINGS_FILE);
+        if !path.exists() {
+            return Ok(Settings::default());
+        }
+        let content = fs::read(&path).chain_err(|| format!("can't read [{}]", path.display()))?;
+        let settings: Settings =
+            serde_json::from_slice(&content).chain_err(|| format!("invalid settings [{}]", path.display()))?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(SETTINGS_FILE);
+        let content = serde_json::to_vec_pretty(self).chain_err(|| "can't write the settings")?;
+        fs::write(&path, content).chain_err(|| format!("can't write [{}]", path.display()))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(kdf) = self.kdf {
+            kdf.validate()?;
+        }
+        Ok(())
+    }
+
+    /// the Argon2id parameters of the vault, else `fallback`.
+    pub fn kdf(&self, fallback: KdfParams) -> KdfParams {
+        self.kdf.unwrap_or(fallback)
+    }
+
+    /// the retention of the copies of the vault, before `PWWR_BACKUP_*`.
+    pub fn retention(&self) -> Retention {
+        let mut retention = Retention::default();
+        if let Some(count) = self.backup_keep {
+            retention.count = count;
+        }
+        if let Some(days) = self.backup_max_age_days {
+            retention.max_age = days * DAY;
+        }
+        retention
+    }
+}
+
+/// a vault name: lowercase letters, digits, `-` and `_`, starting with a
+/// letter or a digit.
+pub fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('-')
+        && !name.starts_with('_')
+        && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_');
+    if !valid {
+        bail!("invalid vault name [{}], use lowercase letters, digits, - and _", name);
+    }
+    Ok(())
+}
+
+/// The vaults of `~/.pwwr`.
+pub struct Vaults {
+    root: PathBuf,
+    default: Option<String>,
+}
+
+impl Vaults {
+    /// the vaults of `root`, `default` being opened when none is given.
+    pub fn new(root: PathBuf, default: Option<String>) -> Vaults {
+        Vaults { root, default }
+    }
+
+    /// the directory of vault `name`, which may not exist.
+    pub fn path(&self, name: &str) -> Result<PathBuf> {
+        check_name(name)?;
+        if name == DEFAULT {
+            Ok(self.root.clone())
+        } else {
+            Ok(self.root.join(DIR).join(name))
+        }
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.path(name).map(|path| path.is_dir()).unwrap_or(false)
+    }
+
+    /// the directory of vault `name`, which must exist.
+    pub fn open(&self, name: &str) -> Result<PathBuf> {
+        if !self.exists(name) {
+            bail!("no vault [{}], create it with `pwwr vault create {}`", name, name);
+        }
+        self.path(name)
+    }
+
+    /// the names of the vaults, `default` first.
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let dir = self.root.join(DIR);
+        if dir.exists() {
+            for dir_entry in fs::read_dir(&dir).chain_err(|| format!("can't read [{}]", dir.display()))? {
+                let dir_entry = dir_entry.chain_err(|| format!("can't read [{}]", dir.display()))?;
+                let name = dir_entry.file_name().to_string_lossy().into_owned();
+                if name != DEFAULT && check_name(&name).is_ok() && dir_entry.path().is_dir() {
+                    names.push(name);
+                }
+            }
+        }
+        names.sort();
+        names.insert(0, String::from(DEFAULT));
+        Ok(names)
+    }
+
+    /// the vault opened when none is given.
+    pub fn default_name(&self) -> &str {
+        self.default.as_ref().map_or(DEFAULT, |name| name.as_str())
+    }
+
+    /// the vault of `PWWR_VAULT`, else the default one.
+    pub fn selected(&self) -> String {
+        match env::var(ENV) {
+            Ok(ref name) if !name.is_empty() => name.clone(),
+            _ => String::from(self.default_name()),
+        }
+    }
+
+    /// create the directory of vault `name` with its settings.
+    pub fn create(&self, name: &str, settings: &Settings) -> Result<PathBuf> {
+        settings.validate()?;
+        let path = self.path(name)?;
+        if name == DEFAULT || path.exists() {
+            bail!("the vault [{}] exists", name);
+        }
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&path)
+            .chain_err(|| format!("can't create [{}]", path.display()))?;
+        settings.save(&path)?;
+        Ok(path)
+    }
+
+    /// remove vault `name` and everything in it, but never the default one.
+    pub fn remove(&self, name: &str) -> Result<()> {
+        if name == DEFAULT {
+            bail!("the vault [{}] can't be removed", DEFAULT);
+        }
+        let path = self.open(name)?;
+        fs::remove_dir_all(&path).chain_err(|| format!("can't remove [{}]", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vault::*;
+    use testutil::TempDir;
+
+    #[test]
+    fn vaults_test() {
+        let root = TempDir::new("vaults");
+        let vaults = Vaults::new(root.to_path_buf(), None);
+        assert_eq!(vec![DEFAULT], vaults.list().unwrap());
+
+        let settings = Settings {
+            kdf: Some(KdfParams {
+                memory: 1024,
+                iterations: 2,
+                lanes: 1,
+            }),
+            ..Settings::default()
+        };
+        let work = vaults.create("work", &settings).unwrap();
+        assert_eq!(root.join("vaults").join("work"), work);
+        assert_eq!(settings, Settings::load(&work).unwrap());
+        assert_eq!((1024, 2, 1), Settings::load(&work).unwrap().kdf(KdfParams::default()).tuple());
+        assert_eq!(backup::KDF_PARAMS, Settings::load(&root).unwrap().kdf(KdfParams::default()).tuple());
+        assert!(vaults.create("work", &settings).is_err());
+        assert!(vaults.create(DEFAULT, &settings).is_err());
+        assert!(vaults.create("../x", &settings).is_err());
+        assert!(vaults.create("Work", &settings).is_err());
+        vaults.create("client-1", &Settings::default()).unwrap();
+        assert_eq!(vec![DEFAULT, "client-1", "work"], vaults.list().unwrap());
+
+        assert_eq!(DEFAULT, vaults.default_name());
+        assert_eq!("work", Vaults::new(root.to_path_buf(), Some(String::from("work"))).default_name());
+        vaults.remove("work").unwrap();
+        assert!(vaults.remove(DEFAULT).is_err());
+        assert!(vaults.open("work").is_err());
+    }
+
+    #[test]
+    fn settings_test() {
+        let bad = KdfParams {
+            memory: 8,
+            iterations: 1,
+            lanes: 2,
+        };
+        assert!(bad.validate().is_err());
+        assert!(KdfParams::default().validate().is_ok());
+        let settings: Settings = serde_json::from_str(r#"{"backup_keep": 3}"#).unwrap();
+        assert_eq!(3, settings.retention().count);
+        assert_eq!(Retention::default().max_age, settings.retention().max_age);
+    }
+}
@@ -0,0 +1,92 @@
+#!/usr/bin/env python3
+"""Write the 1Password export fixture used by the tests of src/onepassword.rs.
+
+    python3 testdata/make_1pux.py
+
+A .1pux file is a zip archive whose export.data holds the accounts, their
+vaults and their items as JSON.
+"""
+import json
+import os
+import zipfile
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+
+def field(title, value, id=""):
+    return {"title": title, "id": id, "value": value, "indexAtSource": 0,
+            "guarded": False, "multiline": False, "dontGenerate": False, "inputTraits": {}}
+
+
+ITEMS = [
+    {
+        "uuid": "a1", "favIndex": 0, "createdAt": 0, "updatedAt": 0, "state": "active",
+        "categoryUuid": "001",
+        "details": {
+            "loginFields": [
+                {"value": "alice", "id": "", "name": "username", "fieldType": "T", "designation": "username"},
+                {"value": "s3cret", "id": "", "name": "password", "fieldType": "P", "designation": "password"},
+                {"value": "", "id": "", "name": "remember", "fieldType": "C"},
+            ],
+            "notesPlain": "memo",
+            "sections": [{"title": "Security", "name": "s1", "fields": [
+                field("one-time password", {"totp": "otpauth://totp/github?secret=JBSWY3DP"}, "TOTP_1"),
+                field("recovery", {"concealed": "r-e-c"}),
+                field("", {"string": "untitled field"}, "custom1"),
+            ]}],
+            "passwordHistory": [],
+        },
+        "overview": {"subtitle": "alice", "title": "GitHub", "url": "https://github.com",
+                     "urls": [{"label": "", "url": "https://github.com"}, {"label": "", "url": "https://gist.github.com"}],
+                     "tags": ["work", "dev"]},
+    },
+    {
+        "uuid": "a2", "state": "active", "categoryUuid": "002",
+        "details": {"loginFields": [], "sections": [{"title": "", "name": "", "fields": [
+            field("cardholder name", {"string": "Alice"}, "cardholder"),
+            field("type", {"creditCardType": "visa"}, "type"),
+            field("number", {"creditCardNumber": "4111111111111111"}, "ccnum"),
+            field("verification number", {"concealed": "123"}, "cvv"),
+            field("expiry date", {"monthYear": 203004}, "expiry"),
+        ]}]},
+        "overview": {"title": "Visa", "tags": []},
+    },
+    {
+        "uuid": "a3", "state": "active", "categoryUuid": "004",
+        "details": {"loginFields": [], "sections": [{"title": "Identification", "name": "name", "fields": [
+            field("first name", {"string": "Alice"}, "firstname"),
+            field("email", {"email": {"email_address": "alice@example.com", "provider": None}}, "email"),
+            field("address", {"address": {"street": "1 Main St", "city": "Oxford", "country": "uk",
+                                          "zip": "OX1", "state": ""}}, "address"),
+            field("birth date", {"date": None}, "birthdate"),
+        ]}]},
+        "overview": {"title": "me"},
+    },
+    {
+        "uuid": "a4", "state": "active", "categoryUuid": "003",
+        "details": {"notesPlain": "the key is 42", "loginFields": [], "sections": []},
+        "overview": {"title": "wifi"},
+    },
+    {
+        "uuid": "a5", "state": "archived", "categoryUuid": "005",
+        "details": {"password": "gone", "loginFields": [], "sections": []},
+        "overview": {"title": "old"},
+    },
+    {
+        "uuid": "a6", "state": "active", "categoryUuid": "005",
+        "details": {"password": "routerpw", "loginFields": [], "sections": []},
+        "overview": {"title": "router"},
+    },
+]
+
+EXPORT = {"accounts": [{"attrs": {"accountName": "Alice", "name": "Alice", "email": "alice@example.com",
+                                  "uuid": "acc", "domain": "https://my.1password.com/"},
+                        "vaults": [{"attrs": {"uuid": "v1", "name": "Private", "type": "P"}, "items": ITEMS[:2]},
+                                   {"attrs": {"uuid": "v2", "name": "Shared", "type": "U"}, "items": ITEMS[2:]}]}]}
+
+if __name__ == "__main__":
+    path = os.path.join(HERE, "export.1pux")
+    with zipfile.ZipFile(path, "w", zipfile.ZIP_DEFLATED) as z:
+        z.writestr("export.attributes", json.dumps({"version": 3, "description": "1Password Unencrypted Export",
+                                                    "createdAt": 0}))
+        z.writestr("export.data", json.dumps(EXPORT))
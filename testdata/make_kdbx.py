@@ -0,0 +1,220 @@
+#!/usr/bin/env python3
+"""Write the KDBX fixtures used by the tests of src/kdbx.rs.
+
+    python3 testdata/make_kdbx.py
+
+Needs the `cryptography` package. Both files are protected by the password
+`test`: keepass3.kdbx is KDBX 3.1 (AES-KDF, AES-CBC, Salsa20 inner stream)
+and keepass4.kdbx is KDBX 4.0 (Argon2id, ChaCha20, ChaCha20 inner stream).
+"""
+import base64
+import gzip
+import hashlib
+import hmac
+import os
+import struct
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+from cryptography.hazmat.primitives.kdf.argon2 import Argon2id
+from cryptography.hazmat.primitives import padding
+
+PASSWORD = b"test"
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+AES_CIPHER = bytes.fromhex("31c1f2e6bf714350be5805216afc5aff")
+CHACHA20_CIPHER = bytes.fromhex("d6038a2b8b6f4cb5a524339a31dbb59a")
+AES_KDF = bytes.fromhex("c9d9f39a628a4460bf740d08c18a4fea")
+ARGON2ID_KDF = bytes.fromhex("9e298b1956db4773b23dfc3ec6f0a1e6")
+SALSA20_NONCE = bytes.fromhex("e830094b97205d2a")
+
+
+def salsa20_stream(key, nonce, length):
+    def rotl(v, c):
+        return ((v << c) & 0xFFFFFFFF) | (v >> (32 - c))
+
+    def block(counter):
+        c = b"expand 32-byte k"
+        s = list(struct.unpack("<4I", c))
+        k = list(struct.unpack("<8I", key))
+        n = list(struct.unpack("<2I", nonce))
+        x = [s[0], k[0], k[1], k[2], k[3], s[1], n[0], n[1],
+             counter & 0xFFFFFFFF, counter >> 32, s[2], k[4], k[5], k[6], k[7], s[3]]
+        z = list(x)
+        for _ in range(10):
+            for a, b, c_, d in ((0, 4, 8, 12), (5, 9, 13, 1), (10, 14, 2, 6), (15, 3, 7, 11)):
+                z[b] ^= rotl((z[a] + z[d]) & 0xFFFFFFFF, 7)
+                z[c_] ^= rotl((z[b] + z[a]) & 0xFFFFFFFF, 9)
+                z[d] ^= rotl((z[c_] + z[b]) & 0xFFFFFFFF, 13)
+                z[a] ^= rotl((z[d] + z[c_]) & 0xFFFFFFFF, 18)
+            for a, b, c_, d in ((0, 1, 2, 3), (5, 6, 7, 4), (10, 11, 8, 9), (15, 12, 13, 14)):
+                z[b] ^= rotl((z[a] + z[d]) & 0xFFFFFFFF, 7)
+                z[c_] ^= rotl((z[b] + z[a]) & 0xFFFFFFFF, 9)
+                z[d] ^= rotl((z[c_] + z[b]) & 0xFFFFFFFF, 13)
+                z[a] ^= rotl((z[d] + z[c_]) & 0xFFFFFFFF, 18)
+        return struct.pack("<16I", *[(z[i] + x[i]) & 0xFFFFFFFF for i in range(16)])
+
+    out = b""
+    counter = 0
+    while len(out) < length:
+        out += block(counter)
+        counter += 1
+    return out[:length]
+
+
+def chacha20_stream(key, nonce, length):
+    cipher = Cipher(algorithms.ChaCha20(key, b"\0\0\0\0" + nonce), mode=None)
+    return cipher.encryptor().update(b"\0" * length)
+
+
+class Protector:
+    """XORs protected values with the inner stream in document order."""
+
+    def __init__(self, stream):
+        self.stream = stream
+        self.offset = 0
+
+    def protect(self, value):
+        data = value.encode()
+        key = self.stream[self.offset:self.offset + len(data)]
+        self.offset += len(data)
+        return base64.b64encode(bytes(a ^ b for a, b in zip(data, key))).decode()
+
+
+def xml(protector, binaries_in_meta):
+    p = protector.protect
+    attachment = gzip.compress(b"hello attachment", mtime=0)
+    meta_binaries = ""
+    if binaries_in_meta:
+        meta_binaries = ('<Binaries><Binary ID="0" Compressed="True">%s</Binary></Binaries>'
+                         % base64.b64encode(attachment).decode())
+    recycle = base64.b64encode(b"\x02" * 16).decode()
+    # protected values are consumed in document order.
+    password, otp = p("s3cret"), p("JBSWY3DP")
+    history = ('<History><Entry><UUID>AAAAAAAAAAAAAAAAAAAAAQ==</UUID>'
+               '<String><Key>Title</Key><Value>github</Value></String>'
+               '<String><Key>UserName</Key><Value>alice</Value></String>'
+               '<String><Key>Password</Key><Value Protected="True">%s</Value></String>'
+               '<Times><LastModificationTime>2020-01-01T00:00:00Z</LastModificationTime></Times>'
+               '</Entry></History>') % p("old")
+    github = ('<Entry><UUID>AAAAAAAAAAAAAAAAAAAAAQ==</UUID><Tags>work;dev</Tags>'
+              '<String><Key>Title</Key><Value>github</Value></String>'
+              '<String><Key>UserName</Key><Value>alice</Value></String>'
+              '<String><Key>Password</Key><Value Protected="True">%s</Value></String>'
+              '<String><Key>URL</Key><Value>https://github.com</Value></String>'
+              '<String><Key>Notes</Key><Value>memo &amp; more</Value></String>'
+              '<String><Key>otp</Key><Value Protected="True">%s</Value></String>'
+              '<Binary><Key>notes.txt</Key><Value Ref="0"/></Binary>'
+              '%s</Entry>') % (password, otp, history)
+    db = ('<Entry><UUID>AAAAAAAAAAAAAAAAAAAAAg==</UUID>'
+          '<String><Key>Title</Key><Value>db</Value></String>'
+          '<String><Key>UserName</Key><Value>root</Value></String>'
+          '<String><Key>Password</Key><Value Protected="True">%s</Value></String>'
+          '</Entry>') % p("dbpw")
+    deleted = ('<Entry><UUID>AAAAAAAAAAAAAAAAAAAAAw==</UUID>'
+               '<String><Key>Title</Key><Value>deleted</Value></String>'
+               '<String><Key>Password</Key><Value Protected="True">%s</Value></String>'
+               '</Entry>') % p("gone")
+    return ('<?xml version="1.0" encoding="utf-8" standalone="yes"?>'
+            '<KeePassFile><Meta><Generator>make_kdbx.py</Generator>'
+            '<RecycleBinEnabled>True</RecycleBinEnabled><RecycleBinUUID>%s</RecycleBinUUID>%s</Meta>'
+            '<Root><Group><UUID>AAAAAAAAAAAAAAAAAAAAAA==</UUID><Name>Root</Name>%s'
+            '<Group><UUID>AQEBAQEBAQEBAQEBAQEBAQ==</UUID><Name>Work</Name>'
+            '<Group><UUID>AwMDAwMDAwMDAwMDAwMDAw==</UUID><Name>Servers</Name>%s</Group></Group>'
+            '<Group><UUID>%s</UUID><Name>Recycle Bin</Name>%s</Group>'
+            '</Group><DeletedObjects/></Root></KeePassFile>'
+            % (recycle, meta_binaries, github, db, recycle, deleted)).encode()
+
+
+def composite_key():
+    return hashlib.sha256(hashlib.sha256(PASSWORD).digest()).digest()
+
+
+def aes_kdf(key, seed, rounds):
+    encryptor = Cipher(algorithms.AES(seed), modes.ECB()).encryptor()
+    for _ in range(rounds):
+        key = encryptor.update(key)
+    return hashlib.sha256(key).digest()
+
+
+def aes_cbc(key, iv, data):
+    padder = padding.PKCS7(128).padder()
+    data = padder.update(data) + padder.finalize()
+    encryptor = Cipher(algorithms.AES(key), modes.CBC(iv)).encryptor()
+    return encryptor.update(data) + encryptor.finalize()
+
+
+def kdbx3():
+    master_seed, transform_seed, iv = os.urandom(32), os.urandom(32), os.urandom(16)
+    stream_key, start_bytes, rounds = os.urandom(32), os.urandom(32), 1000
+
+    def field(id, data):
+        return struct.pack("<BH", id, len(data)) + data
+
+    header = struct.pack("<IIHH", 0x9AA2D903, 0xB54BFB67, 1, 3)
+    header += field(2, AES_CIPHER) + field(3, struct.pack("<I", 1))
+    header += field(4, master_seed) + field(5, transform_seed)
+    header += field(6, struct.pack("<Q", rounds)) + field(7, iv)
+    header += field(8, stream_key) + field(9, start_bytes)
+    header += field(10, struct.pack("<I", 2)) + field(0, b"\r\n\r\n")
+
+    stream = salsa20_stream(hashlib.sha256(stream_key).digest(), SALSA20_NONCE, 4096)
+    payload = gzip.compress(xml(Protector(stream), True), mtime=0)
+    blocks = struct.pack("<I", 0) + hashlib.sha256(payload).digest() + struct.pack("<I", len(payload)) + payload
+    blocks += struct.pack("<I", 1) + b"\0" * 32 + struct.pack("<I", 0)
+
+    key = hashlib.sha256(master_seed + aes_kdf(composite_key(), transform_seed, rounds)).digest()
+    return header + aes_cbc(key, iv, start_bytes + blocks)
+
+
+def variant_dictionary(items):
+    out = struct.pack("<H", 0x0100)
+    for kind, name, value in items:
+        out += struct.pack("<BI", kind, len(name)) + name + struct.pack("<I", len(value)) + value
+    return out + b"\0"
+
+
+def kdbx4():
+    master_seed, iv, salt, stream_key = os.urandom(32), os.urandom(12), os.urandom(32), os.urandom(64)
+    memory, iterations, lanes = 1024 * 1024, 2, 1
+    kdf = variant_dictionary([
+        (0x42, b"$UUID", ARGON2ID_KDF), (0x42, b"S", salt), (0x04, b"P", struct.pack("<I", lanes)),
+        (0x05, b"M", struct.pack("<Q", memory)), (0x05, b"I", struct.pack("<Q", iterations)),
+        (0x04, b"V", struct.pack("<I", 0x13)),
+    ])
+
+    def field(id, data):
+        return struct.pack("<BI", id, len(data)) + data
+
+    header = struct.pack("<IIHH", 0x9AA2D903, 0xB54BFB67, 0, 4)
+    header += field(2, CHACHA20_CIPHER) + field(3, struct.pack("<I", 1))
+    header += field(4, master_seed) + field(7, iv) + field(11, kdf) + field(0, b"\r\n\r\n")
+
+    transformed = Argon2id(salt=salt, length=32, iterations=iterations, lanes=lanes,
+                           memory_cost=memory // 1024).derive(composite_key())
+    key = hashlib.sha256(master_seed + transformed).digest()
+    hmac_key = hashlib.sha512(master_seed + transformed + b"\x01").digest()
+
+    def block_key(index):
+        return hashlib.sha512(struct.pack("<Q", index) + hmac_key).digest()
+
+    header_hmac = hmac.new(block_key(0xFFFFFFFFFFFFFFFF), header, hashlib.sha256).digest()
+
+    inner_key = hashlib.sha512(stream_key).digest()
+    stream = chacha20_stream(inner_key[:32], inner_key[32:44], 4096)
+    inner = struct.pack("<BI", 1, 4) + struct.pack("<I", 3) + struct.pack("<BI", 2, len(stream_key)) + stream_key
+    inner += struct.pack("<BI", 3, 17) + b"\x01hello attachment" + struct.pack("<BI", 0, 0)
+    payload = gzip.compress(inner + xml(Protector(stream), False), mtime=0)
+    encrypted = Cipher(algorithms.ChaCha20(key, b"\0\0\0\0" + iv), mode=None).encryptor().update(payload)
+
+    blocks = b""
+    for index, data in enumerate([encrypted, b""]):
+        size = struct.pack("<I", len(data))
+        mac = hmac.new(block_key(index), struct.pack("<Q", index) + size + data, hashlib.sha256).digest()
+        blocks += mac + size + data
+    return header + hashlib.sha256(header).digest() + header_hmac + blocks
+
+
+if __name__ == "__main__":
+    with open(os.path.join(HERE, "keepass3.kdbx"), "wb") as f:
+        f.write(kdbx3())
+    with open(os.path.join(HERE, "keepass4.kdbx"), "wb") as f:
+        f.write(kdbx4())